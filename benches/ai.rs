@@ -1,7 +1,8 @@
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
 
 use mancala_rust::{
-    Board, DepthSearcher, NeuralNet4Evaluator, NeuralNet6Evaluator, ScoreDiffEvaluator, Searcher,
+    Board, DepthSearcher, NeuralNet4Evaluator, NeuralNet6Evaluator, ParallelDepthSearcher,
+    ScoreDiffEvaluator, Searcher,
 };
 
 fn dfs5_with_stealing(c: &mut Criterion) {
@@ -32,6 +33,26 @@ fn dfs6_no_stealing(c: &mut Criterion) {
     });
 }
 
+/// スレッド数を変えて同じ局面を読み、どれだけ速くなるかを見る
+fn pdfs8_threads(c: &mut Criterion) {
+    let mut midgame = Board::new(true);
+    for pos in [2, 5, 1, 4, 0] {
+        midgame.sow(pos);
+    }
+    let mut group = c.benchmark_group("pdfs8_threads");
+    for (name, board) in [("initial", Board::new(true)), ("midgame", midgame)] {
+        for threads in [1, 2, 4, 8] {
+            group.bench_with_input(BenchmarkId::new(name, threads), &board, |b, board| {
+                b.iter(|| {
+                    // 置換表を持ち越さないように毎回作り直す
+                    ParallelDepthSearcher::new(ScoreDiffEvaluator::new(), 8, threads).sow(board)
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
 fn nn4_dfs2(c: &mut Criterion) {
     let mut ai = DepthSearcher::new(NeuralNet4Evaluator::new(true), 2);
     c.bench_function("nn4_dfs2", |b| b.iter(|| ai.sow(&Board::new(true))));
@@ -48,6 +69,7 @@ criterion_group!(
     dfs5_no_stealing,
     dfs6_with_stealing,
     dfs6_no_stealing,
+    pdfs8_threads,
     nn4_dfs2,
     nn6_dfs2,
 );
@@ -0,0 +1,40 @@
+//! 設定を変えた AI 同士を先手と後手を入れ替えながら対戦させて強さを比べる
+//! 時間で打ち切る AI は同じ時間を使うので、結果は実行環境の速さで変わる
+//! `cargo bench --bench strength -- mcgraph` のように AI の一部を渡すと、それを含む組だけを比べる
+
+use mancala_rust::play_match;
+
+const GAMES: usize = 40;
+
+/// (比べたい AI, 基準の AI)
+const PAIRS: &[(&str, &str)] = &[
+    // 得点差を報酬にするか、勝ち負けだけを報酬にするか
+    ("mctree:50:2:1.0:margin", "mctree:50:2:1.0:win"),
+    // 合流した局面の統計を共有するか、同じ時間の木と比べる
+    ("mcgraph:50:2:1.0", "mctree:50:2:1.0"),
+];
+
+fn main() {
+    let filters = std::env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .collect::<Vec<_>>();
+    let pairs = PAIRS
+        .iter()
+        .filter(|(a, b)| {
+            filters.is_empty() || filters.iter().any(|f| a.contains(f) || b.contains(f))
+        })
+        .collect::<Vec<_>>();
+    for stealing in [true, false] {
+        for (a, b) in pairs.iter() {
+            let result = play_match(stealing, a, b, GAMES).unwrap();
+            println!(
+                "stealing={stealing} {a} vs {b}: {}-{}-{} win rate {:.3}",
+                result.wins,
+                result.draws,
+                result.losses,
+                result.win_rate()
+            );
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+use super::Searcher;
+use crate::board::Board;
+
+/// 外から止めるまで読み続けられる探索
+pub trait AnytimeSearcher: Searcher {
+    /// `stop` が立つまで `board` を読み、最善手が更新されるたびに `report` に知らせる
+    /// 読み切った場合は `stop` を待たずに返る
+    fn think(
+        &mut self,
+        board: &Board,
+        stop: &AtomicBool,
+        report: &mut dyn FnMut(&[usize]),
+    ) -> Vec<usize>;
+
+    /// 自分が打った後の `board` で、相手の手を予想して `stop` が立つまで先読みしておく
+    /// 予想が当たれば次の `sow` で読んだ結果を使う
+    fn ponder(&mut self, board: &Board, stop: &AtomicBool);
+}
+
+/// 別スレッドで動いている `AnytimeSearcher`
+pub struct Background<S> {
+    stop: Arc<AtomicBool>,
+    best: Arc<Mutex<Vec<usize>>>,
+    handle: JoinHandle<(S, Vec<usize>)>,
+}
+
+impl<S> Background<S>
+where
+    S: AnytimeSearcher + Send + 'static,
+{
+    /// `board` の手を別スレッドで考え始める
+    pub fn think(mut searcher: S, board: Board) -> Background<S> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let best = Arc::new(Mutex::new(Vec::new()));
+        let handle = {
+            let (stop, best) = (stop.clone(), best.clone());
+            thread::spawn(move || {
+                let pos_list = searcher.think(&board, &stop, &mut |pos_list| {
+                    *best.lock().unwrap() = pos_list.to_vec();
+                });
+                (searcher, pos_list)
+            })
+        };
+        Background { stop, best, handle }
+    }
+
+    /// 相手の手番の `board` について別スレッドで先読みを始める
+    pub fn ponder(mut searcher: S, board: Board) -> Background<S> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                searcher.ponder(&board, &stop);
+                (searcher, Vec::new())
+            })
+        };
+        Background {
+            stop,
+            best: Arc::new(Mutex::new(Vec::new())),
+            handle,
+        }
+    }
+
+    /// これまでの最善手。まだ無ければ空
+    pub fn best(&self) -> Vec<usize> {
+        self.best.lock().unwrap().clone()
+    }
+
+    /// 読み切って止まっているかどうか
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// 止めて、探索器と最後の最善手を返す。先読みの場合は最善手は空
+    pub fn stop(self) -> (S, Vec<usize>) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.join().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand_pcg::Mcg128Xsl64;
+
+    use super::*;
+    use crate::{DepthSearcher, McTreeSearcher, ScoreDiffEvaluator};
+
+    fn check_stop<S: AnytimeSearcher + Send + 'static>(searcher: S) {
+        let board = Board::new(true);
+        let bg = Background::think(searcher, board.clone());
+        thread::sleep(Duration::from_millis(50));
+        assert!(!bg.best().is_empty());
+        let (_, pos_list) = bg.stop();
+        let mut b = board.clone();
+        for pos in pos_list {
+            assert!(b.can_sow(pos).is_ok());
+            b.sow(pos);
+        }
+        assert_ne!(b.side(), board.side());
+    }
+
+    #[test]
+    fn stop_thinking() {
+        check_stop(DepthSearcher::new(ScoreDiffEvaluator::new(), 4));
+        check_stop(McTreeSearcher::new(Mcg128Xsl64::new(1), 10, 2, 1.0));
+    }
+}
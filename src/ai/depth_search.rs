@@ -1,24 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use rand::Rng;
 
 use super::{
-    Evaluator, Score, Searcher,
-    utils::{ab_search, choice_with_weight, soft_max},
+    AnytimeSearcher, Evaluator, Score, Searcher,
+    dump::{DumpNode, DumpOptions},
+    mctree::same_position,
+    result::{SearchResult, search_root, search_root_dump, search_root_until},
+    utils::{ab_search_q, choice_with_weight, soft_max},
 };
 use crate::board::Board;
 
 #[derive(Debug, Clone)]
 pub struct DepthSearcher<E> {
     max_depth: usize,
+    qdepth: usize,
     evaluator: E,
+    dump: Option<DumpOptions>,
+    last_dump: Option<DumpNode>,
+    /// 先読みで予想した局面とその最善手
+    pondered: Option<(Board, Vec<usize>)>,
 }
 
 impl<E> DepthSearcher<E> {
     pub fn new(evaluator: E, max_depth: usize) -> DepthSearcher<E> {
         DepthSearcher {
             max_depth,
+            qdepth: 0,
             evaluator,
+            dump: None,
+            last_dump: None,
+            pondered: None,
         }
     }
+
+    /// 末端で横取りが絡むターンを最大 `qdepth` ターン延長して読む。0 なら延長しない
+    pub fn set_quiescence(&mut self, qdepth: usize) {
+        self.qdepth = qdepth;
+    }
+}
+
+impl<E> DepthSearcher<E>
+where
+    E: Evaluator,
+{
+    /// 読み筋や探索ノード数などを含めた探索結果を返す
+    /// 最善手以外の初手の評価値は上界であることに注意
+    pub fn search(&mut self, board: &Board) -> SearchResult<E::Score> {
+        search_root(
+            board,
+            &mut self.evaluator,
+            self.max_depth,
+            self.qdepth,
+            false,
+        )
+    }
 }
 
 impl<E> Searcher for DepthSearcher<E>
@@ -30,29 +66,110 @@ where
         if next_lists.len() == 1 {
             return next_lists.drain().next().unwrap().1;
         }
-        let mut best = Vec::new();
-        let mut best_score = E::Score::MIN;
-        for (next, pos_list) in next_lists {
-            let s = ab_search(
-                next,
-                &mut self.evaluator,
-                self.max_depth,
-                E::Score::MIN,
-                best_score.flip(),
-            )
-            .flip();
-            if s > best_score {
-                best_score = s;
-                best = pos_list;
+        if let Some((pondered, best)) = self.pondered.take()
+            && same_position(&pondered, board)
+        {
+            return best;
+        }
+        if self.dump.is_none() {
+            return self.search(board).best;
+        }
+        // 探索しながら記録するので、書き出す木は実際に読んだ木そのものになる
+        let stop = AtomicBool::new(false);
+        let (result, dump) = search_root_dump(
+            board,
+            &mut self.evaluator,
+            self.max_depth,
+            self.qdepth,
+            false,
+            &stop,
+            self.dump,
+        )
+        .unwrap();
+        self.last_dump = dump;
+        result.best
+    }
+
+    fn set_dump(&mut self, options: Option<DumpOptions>) {
+        self.dump = options;
+    }
+
+    fn take_dump(&mut self) -> Option<DumpNode> {
+        self.last_dump.take()
+    }
+}
+
+impl<E> AnytimeSearcher for DepthSearcher<E>
+where
+    E: Evaluator,
+{
+    /// 1 ターンずつ深くしながら読む。`max_depth` を超えても止めるまで読み続ける
+    fn think(
+        &mut self,
+        board: &Board,
+        stop: &AtomicBool,
+        report: &mut dyn FnMut(&[usize]),
+    ) -> Vec<usize> {
+        let mut best = board
+            .list_next_with_pos()
+            .into_values()
+            .min()
+            .unwrap_or_default();
+        let mut prev_nodes = 0;
+        for depth in 1.. {
+            let Some(result) =
+                search_root_until(board, &mut self.evaluator, depth, self.qdepth, false, stop)
+            else {
+                break;
+            };
+            best = result.best;
+            report(&best);
+            // 深くしてもノード数が増えなければ終局まで読み切っている
+            if result.nodes == prev_nodes || stop.load(Ordering::Relaxed) {
+                break;
             }
+            prev_nodes = result.nodes;
         }
         best
     }
+
+    /// 相手の最善手を `max_depth` で予想し、その後の局面を `max_depth` で読んでおく
+    fn ponder(&mut self, board: &Board, stop: &AtomicBool) {
+        self.pondered = None;
+        let Some(reply) = search_root_until(
+            board,
+            &mut self.evaluator,
+            self.max_depth,
+            self.qdepth,
+            false,
+            stop,
+        ) else {
+            return;
+        };
+        let mut expected = board.clone();
+        for pos in reply.best {
+            expected.sow(pos);
+        }
+        if expected.is_finished() {
+            return;
+        }
+        if let Some(result) = search_root_until(
+            &expected,
+            &mut self.evaluator,
+            self.max_depth,
+            self.qdepth,
+            false,
+            stop,
+        ) {
+            self.pondered = Some((expected, result.best));
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RandomDepthSearcher<E, R> {
     max_depth: usize,
+    qdepth: usize,
     weight: f64,
     evaluator: E,
     random: R,
@@ -67,11 +184,17 @@ impl<E, R> RandomDepthSearcher<E, R> {
     ) -> RandomDepthSearcher<E, R> {
         RandomDepthSearcher {
             max_depth,
+            qdepth: 0,
             weight,
             evaluator,
             random,
         }
     }
+
+    /// 末端で横取りが絡むターンを最大 `qdepth` ターン延長して読む。0 なら延長しない
+    pub fn set_quiescence(&mut self, qdepth: usize) {
+        self.qdepth = qdepth;
+    }
 }
 
 impl<E, R> Searcher for RandomDepthSearcher<E, R>
@@ -88,10 +211,11 @@ where
         let mut moves = Vec::with_capacity(next_lists.len());
         let mut scores = Vec::with_capacity(next_lists.len());
         for (next, pos_list) in next_lists {
-            let s = ab_search(
+            let s = ab_search_q(
                 next,
                 &mut self.evaluator,
                 self.max_depth,
+                self.qdepth,
                 E::Score::MIN,
                 E::Score::MAX,
             )
@@ -103,3 +227,90 @@ where
         moves.swap_remove(choice_with_weight(&mut self.random, &scores))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoreDiffEvaluator;
+    use rand_pcg::Mcg128Xsl64;
+
+    fn random_positions(stealing: bool, games: usize) -> Vec<Board> {
+        let mut random = Mcg128Xsl64::new(1);
+        let mut ret = Vec::new();
+        for _ in 0..games {
+            let mut board = Board::new(stealing);
+            loop {
+                let mut next_list = board.list_next().drain().collect::<Vec<_>>();
+                if next_list.len() < 2 {
+                    break;
+                }
+                ret.push(board.clone());
+                let idx = random.random_range(0..next_list.len());
+                board = next_list.swap_remove(idx);
+            }
+        }
+        ret
+    }
+
+    /// 浅い探索で最善と判断された手が、深く読むと最善手よりどれだけ悪いかの合計
+    /// 同点の手はどれが選ばれるか決まらないので平均をとる
+    fn horizon_loss(qdepth: usize) -> f64 {
+        let mut eval = ScoreDiffEvaluator::new();
+        let mut loss = 0.0;
+        for board in random_positions(true, 10) {
+            let exact = search_root(&board, &mut eval, 5, 0, true);
+            let shallow = search_root(&board, &mut eval, 1, qdepth, true);
+            let chosen = shallow
+                .root_scores
+                .iter()
+                .zip(exact.root_scores.iter())
+                .filter(|((_, s), _)| *s == shallow.score)
+                .map(|(_, (_, s))| f64::from(exact.score - *s))
+                .collect::<Vec<_>>();
+            loss += chosen.iter().sum::<f64>() / chosen.len() as f64;
+        }
+        loss
+    }
+
+    #[test]
+    fn quiescence_reduces_horizon_blunders() {
+        let plain = horizon_loss(0);
+        let quiet = horizon_loss(4);
+        assert!(quiet < plain, "{quiet} < {plain}");
+    }
+
+    #[test]
+    fn think_solves_endgame() {
+        let mut seeds = [0; crate::PIT * 2];
+        seeds[0] = 2;
+        seeds[crate::PIT - 1] = 1;
+        seeds[crate::PIT] = 1;
+        seeds[crate::PIT + 2] = 3;
+        let board = Board::from_seeds(true, &seeds);
+        let mut ai = DepthSearcher::new(ScoreDiffEvaluator::new(), 1);
+        let mut reports = 0;
+        // 止めなくても読み切ったら返る
+        let best = ai.think(&board, &AtomicBool::new(false), &mut |_| reports += 1);
+        assert!(reports > 1);
+        let exact = search_root(&board, &mut ScoreDiffEvaluator::new(), 30, 0, true);
+        let chosen = exact.root_scores.iter().find(|(p, _)| *p == best).unwrap();
+        assert_eq!(chosen.1, exact.score);
+    }
+
+    #[test]
+    fn ponder_hit() {
+        let mut board = Board::new(true);
+        board.sow(0);
+        let mut ai = DepthSearcher::new(ScoreDiffEvaluator::new(), 3);
+        ai.ponder(&board, &AtomicBool::new(false));
+        let (expected, best) = ai.pondered.clone().unwrap();
+        assert_ne!(expected.side(), board.side());
+        assert_eq!(ai.sow(&expected), best);
+        assert!(ai.pondered.is_none());
+
+        // 止められたら何も残さない
+        let stop = AtomicBool::new(true);
+        ai.ponder(&board, &stop);
+        assert!(ai.pondered.is_none());
+    }
+}
@@ -0,0 +1,284 @@
+use std::io::{self, Write};
+
+use super::Score;
+use crate::board::Board;
+
+/// 探索木をどこまで書き出すか
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DumpOptions {
+    /// 根からの深さ（ターン数）の上限
+    pub max_depth: usize,
+    /// 訪問回数（ノード数）がこれ未満の子は書き出さない
+    pub min_visits: u64,
+}
+
+impl Default for DumpOptions {
+    fn default() -> DumpOptions {
+        DumpOptions {
+            max_depth: 2,
+            min_visits: 1,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DumpFormat {
+    Dot,
+    Json,
+}
+
+impl DumpFormat {
+    /// 拡張子から形式を決める
+    pub fn from_path(path: &str) -> Option<DumpFormat> {
+        if path.ends_with(".dot") || path.ends_with(".gv") {
+            Some(DumpFormat::Dot)
+        } else if path.ends_with(".json") {
+            Some(DumpFormat::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// 書き出すための探索木のノード
+/// MCTS なら `wins` と `ucb`、アルファベータ探索なら `alpha`、`beta` と `score` が入る
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DumpNode {
+    /// `Board::notation` で書いた盤面
+    pub position: String,
+    /// 親からこのノードに進む打ち手。根は空
+    pub pos_list: Vec<usize>,
+    pub visits: u64,
+    /// 親の手番側から見た報酬の合計
+    pub wins: Option<f64>,
+    /// 親から見た UCB の値
+    pub ucb: Option<f64>,
+    pub alpha: Option<String>,
+    pub beta: Option<String>,
+    /// このノードの手番側から見た評価値
+    pub score: Option<String>,
+    pub children: Vec<DumpNode>,
+}
+
+/// 評価値を書き出す。探索窓の端は無限大として書く
+pub(super) fn score_text<S: Score>(s: S) -> String {
+    if s <= S::MIN {
+        "-inf".to_owned()
+    } else if s >= S::MAX {
+        "inf".to_owned()
+    } else {
+        format!("{s:?}")
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            c if c.is_control() => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// JSON には無限大や NaN を書けないので null にする
+fn json_number(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
+
+impl DumpNode {
+    /// 全ノード数
+    pub fn size(&self) -> usize {
+        1 + self.children.iter().map(DumpNode::size).sum::<usize>()
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, format: DumpFormat) -> io::Result<()> {
+        match format {
+            DumpFormat::Dot => self.write_dot(w),
+            DumpFormat::Json => {
+                self.write_json(w)?;
+                writeln!(w)
+            }
+        }
+    }
+
+    fn label(&self) -> String {
+        let mut lines = vec![
+            self.position.replace('"', "\\\""),
+            format!("visits={}", self.visits),
+        ];
+        if let Some(wins) = self.wins {
+            lines.push(format!("wins={wins:.1}"));
+        }
+        if let Some(ucb) = self.ucb {
+            lines.push(format!("ucb={ucb:.3}"));
+        }
+        if let (Some(alpha), Some(beta)) = (&self.alpha, &self.beta) {
+            lines.push(format!("[{alpha}, {beta}]"));
+        }
+        if let Some(score) = &self.score {
+            lines.push(format!("score={score}"));
+        }
+        lines.join("\\n")
+    }
+
+    pub fn write_dot<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "digraph tree {{")?;
+        writeln!(w, "  node [shape=box, fontname=\"monospace\"];")?;
+        let mut id = 0;
+        self.write_dot_node(w, &mut id)?;
+        writeln!(w, "}}")
+    }
+
+    fn write_dot_node<W: Write>(&self, w: &mut W, id: &mut usize) -> io::Result<()> {
+        let me = *id;
+        writeln!(w, "  n{me} [label=\"{}\"];", self.label())?;
+        for child in self.children.iter() {
+            *id += 1;
+            writeln!(w, "  n{me} -> n{} [label=\"{:?}\"];", *id, child.pos_list)?;
+            child.write_dot_node(w, id)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(
+            w,
+            "{{\"position\":{},\"move\":{:?},\"visits\":{}",
+            json_string(&self.position),
+            self.pos_list,
+            self.visits
+        )?;
+        if let Some(wins) = self.wins {
+            write!(w, ",\"wins\":{}", json_number(wins))?;
+        }
+        if let Some(ucb) = self.ucb {
+            write!(w, ",\"ucb\":{}", json_number(ucb))?;
+        }
+        for (key, value) in [
+            ("alpha", &self.alpha),
+            ("beta", &self.beta),
+            ("score", &self.score),
+        ] {
+            if let Some(value) = value {
+                write!(w, ",\"{key}\":{}", json_string(value))?;
+            }
+        }
+        write!(w, ",\"children\":[")?;
+        for (i, child) in self.children.iter().enumerate() {
+            if i > 0 {
+                write!(w, ",")?;
+            }
+            child.write_json(w)?;
+        }
+        write!(w, "]}}")
+    }
+}
+
+/// 探索しながら、根からの深さが `max_depth` 以下のノードを記録する
+pub(super) struct DumpRecorder {
+    options: DumpOptions,
+    /// 根から探索中のノードまでの (盤面, ノード, 探索を始めたときの訪問ノード数)
+    stack: Vec<(Board, DumpNode, u64)>,
+    /// 探索中のノードの根からの深さ
+    level: usize,
+    root: Option<DumpNode>,
+}
+
+impl DumpRecorder {
+    pub(super) fn new(options: DumpOptions) -> DumpRecorder {
+        DumpRecorder {
+            options,
+            stack: Vec::new(),
+            level: 0,
+            root: None,
+        }
+    }
+
+    /// `nodes` はこのノードを訪問する前までの訪問ノード数
+    pub(super) fn enter<S: Score>(&mut self, board: &Board, alpha: S, beta: S, nodes: u64) {
+        if self.level <= self.options.max_depth {
+            let node = DumpNode {
+                position: board.notation(),
+                alpha: Some(score_text(alpha)),
+                beta: Some(score_text(beta)),
+                ..DumpNode::default()
+            };
+            self.stack.push((board.clone(), node, nodes));
+        }
+        self.level += 1;
+    }
+
+    /// `nodes` はこのノード以下を読み終えたときの訪問ノード数
+    pub(super) fn leave<S: Score>(&mut self, score: S, nodes: u64) {
+        self.level -= 1;
+        if self.level > self.options.max_depth {
+            return;
+        }
+        let (board, mut node, start) = self.stack.pop().unwrap();
+        node.visits = nodes - start;
+        node.score = Some(score_text(score));
+        match self.stack.last_mut() {
+            None => self.root = Some(node),
+            Some((parent, parent_node, _)) => {
+                if node.visits >= self.options.min_visits {
+                    node.pos_list = parent
+                        .list_next_with_pos()
+                        .remove(&board)
+                        .unwrap_or_default();
+                    parent_node.children.push(node);
+                }
+            }
+        }
+    }
+
+    pub(super) fn take(&mut self) -> Option<DumpNode> {
+        self.root.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_formats() {
+        let tree = DumpNode {
+            position: "root".to_owned(),
+            visits: 3,
+            wins: Some(1.5),
+            children: vec![DumpNode {
+                position: "\"x\"".to_owned(),
+                pos_list: vec![2, 5],
+                visits: 2,
+                ucb: Some(f64::INFINITY),
+                ..DumpNode::default()
+            }],
+            ..DumpNode::default()
+        };
+        let mut dot = Vec::new();
+        tree.write(&mut dot, DumpFormat::Dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph tree {"));
+        assert!(dot.contains("n0 -> n1 [label=\"[2, 5]\"];"));
+
+        let mut json = Vec::new();
+        tree.write(&mut json, DumpFormat::Json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(
+            json,
+            "{\"position\":\"root\",\"move\":[],\"visits\":3,\"wins\":1.5,\"children\":[\
+             {\"position\":\"\\\"x\\\"\",\"move\":[2, 5],\"visits\":2,\"ucb\":null,\"children\":[]}]}\n"
+        );
+    }
+}
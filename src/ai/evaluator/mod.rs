@@ -1,12 +1,20 @@
+mod model;
 mod score;
 
+use std::{
+    io::{self, Read},
+    path::Path,
+    sync::Arc,
+};
+
 use ndarray::Array1;
 use rand::Rng;
 use rust_nn::predict::{NN4Regression, NN6Regression, Regression};
 
 use super::Evaluator;
 use super::utils::{random_down, random_down_with_weight};
-use crate::board::Board;
+use crate::{board::Board, learn::Tablebase};
+pub use model::{model_params, model_widths, set_model_params};
 pub use score::*;
 
 // -- ScoreDiff
@@ -27,6 +35,42 @@ impl Evaluator for ScoreDiffEvaluator {
     }
 }
 
+// -- Tablebase
+
+/// 終盤データベースで読み切れる局面ではその値を、そうでなければ `evaluator` の値を返す
+#[derive(Debug, Clone)]
+pub struct TablebaseEvaluator<E> {
+    evaluator: E,
+    tablebase: Arc<Tablebase>,
+}
+
+impl<E> TablebaseEvaluator<E> {
+    pub fn new(evaluator: E, tablebase: Arc<Tablebase>) -> TablebaseEvaluator<E> {
+        TablebaseEvaluator {
+            evaluator,
+            tablebase,
+        }
+    }
+}
+
+impl<E> Evaluator for TablebaseEvaluator<E>
+where
+    E: Evaluator,
+    E::Score: From<i8>,
+{
+    type Score = E::Score;
+    fn eval(&mut self, board: &Board) -> Self::Score {
+        match self.tablebase.probe(board) {
+            Some(s) => s.into(),
+            None => self.evaluator.eval(board),
+        }
+    }
+
+    fn exact(&mut self, board: &Board) -> Option<Self::Score> {
+        self.tablebase.probe(board).map(Into::into)
+    }
+}
+
 // -- ScorePos
 
 lazy_static! {
@@ -174,11 +218,28 @@ impl NeuralNet4Evaluator {
         } else {
             NN4_FALSE_MODEL
         };
+        NeuralNet4Evaluator::from_reader(&mut model)
+    }
+
+    /// `rust_nn` の形式で保存したモデルを読み込む
+    pub fn from_reader<R: Read>(reader: &mut R) -> NeuralNet4Evaluator {
         NeuralNet4Evaluator {
-            nn: NN4Regression::new(&mut model),
+            nn: NN4Regression::new(reader),
             input: Array1::zeros(12),
         }
     }
+
+    /// `from_reader` と同じだが、NN4 の形のモデルか先に確かめる
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<NeuralNet4Evaluator> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        model::check_model(&bytes, "NN4", 5)?;
+        Ok(NeuralNet4Evaluator::from_reader(&mut bytes.as_slice()))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<NeuralNet4Evaluator> {
+        NeuralNet4Evaluator::read(&mut io::BufReader::new(std::fs::File::open(path)?))
+    }
 }
 
 impl Evaluator for NeuralNet4Evaluator {
@@ -201,11 +262,28 @@ impl NeuralNet6Evaluator {
         } else {
             NN6_FALSE_MODEL
         };
+        NeuralNet6Evaluator::from_reader(&mut model)
+    }
+
+    /// `rust_nn` の形式で保存したモデルを読み込む
+    pub fn from_reader<R: Read>(reader: &mut R) -> NeuralNet6Evaluator {
         NeuralNet6Evaluator {
-            nn: NN6Regression::new(&mut model),
+            nn: NN6Regression::new(reader),
             input: Array1::zeros(12),
         }
     }
+
+    /// `from_reader` と同じだが、NN6 の形のモデルか先に確かめる
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<NeuralNet6Evaluator> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        model::check_model(&bytes, "NN6", 7)?;
+        Ok(NeuralNet6Evaluator::from_reader(&mut bytes.as_slice()))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<NeuralNet6Evaluator> {
+        NeuralNet6Evaluator::read(&mut io::BufReader::new(std::fs::File::open(path)?))
+    }
 }
 
 impl Evaluator for NeuralNet6Evaluator {
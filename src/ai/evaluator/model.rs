@@ -0,0 +1,265 @@
+use std::io;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// `rust_nn` のモデルを MessagePack として読むための最小限の読み手
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let end = self.pos + n;
+        let b = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or_else(|| invalid_data("model is truncated"))?;
+        self.pos = end;
+        Ok(b)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn be(&mut self, n: usize) -> io::Result<u64> {
+        Ok(self
+            .take(n)?
+            .iter()
+            .fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    fn array_len(&mut self) -> io::Result<usize> {
+        match self.byte()? {
+            t @ 0x90..=0x9f => Ok(usize::from(t & 0x0f)),
+            0xdc => Ok(self.be(2)? as usize),
+            0xdd => Ok(self.be(4)? as usize),
+            _ => Err(invalid_data("expected an array")),
+        }
+    }
+
+    fn uint(&mut self) -> io::Result<usize> {
+        match self.byte()? {
+            t @ 0x00..=0x7f => Ok(usize::from(t)),
+            0xcc => Ok(self.be(1)? as usize),
+            0xcd => Ok(self.be(2)? as usize),
+            0xce => Ok(self.be(4)? as usize),
+            0xcf => Ok(self.be(8)? as usize),
+            _ => Err(invalid_data("expected an unsigned integer")),
+        }
+    }
+
+    /// 数を読み飛ばして型のバイトを返す
+    fn skip_number(&mut self) -> io::Result<u8> {
+        let tag = self.byte()?;
+        let n = match tag {
+            0x00..=0x7f | 0xe0..=0xff => 0,
+            0xcc | 0xd0 => 1,
+            0xcd | 0xd1 => 2,
+            0xca | 0xce | 0xd2 => 4,
+            0xcb | 0xcf | 0xd3 => 8,
+            _ => return Err(invalid_data("expected a number")),
+        };
+        self.take(n).map(|_| tag)
+    }
+
+    /// ndarray の `[version, shape, data]` を読んで形だけ返す
+    fn ndarray(&mut self) -> io::Result<Vec<usize>> {
+        self.ndarray_with(|_, _| Ok(()))
+    }
+
+    /// `ndarray` と同じだが、data の数ごとに型のバイトとその位置を `f` に渡す
+    fn ndarray_with(
+        &mut self,
+        mut f: impl FnMut(u8, usize) -> io::Result<()>,
+    ) -> io::Result<Vec<usize>> {
+        if self.array_len()? != 3 || self.uint()? != 1 {
+            return Err(invalid_data("expected an ndarray"));
+        }
+        let dims = self.array_len()?;
+        let shape = (0..dims)
+            .map(|_| self.uint())
+            .collect::<io::Result<Vec<_>>>()?;
+        if self.array_len()? != shape.iter().product::<usize>() {
+            return Err(invalid_data("ndarray data does not match its shape"));
+        }
+        for _ in 0..shape.iter().product::<usize>() {
+            let pos = self.pos;
+            f(self.skip_number()?, pos)?;
+        }
+        Ok(shape)
+    }
+}
+
+/// `rust_nn` のモデルの各層の幅を入力から順に返す。出力の 1 は含めない
+///
+/// モデルは重み `[out, in]` とバイアス `[out]` の組を入力側から並べたもの
+pub fn model_widths(bytes: &[u8]) -> io::Result<Vec<usize>> {
+    let mut r = Reader { bytes, pos: 0 };
+    let mut widths = Vec::new();
+    while r.pos < bytes.len() {
+        let w = r.ndarray()?;
+        let b = r.ndarray()?;
+        let [out, input] = w[..] else {
+            return Err(invalid_data("weight must be 2-dimensional"));
+        };
+        if b != [out] {
+            return Err(invalid_data("bias does not match weight"));
+        }
+        if widths.last().is_some_and(|last| *last != input) {
+            return Err(invalid_data("layer widths do not chain"));
+        }
+        if widths.is_empty() {
+            widths.push(input);
+        }
+        widths.push(out);
+    }
+    if widths.pop() != Some(1) {
+        return Err(invalid_data("model must end with a single output"));
+    }
+    Ok(widths)
+}
+
+/// 重みとバイアスの浮動小数点数の位置 (型のバイトの位置) を並び順に返す
+fn param_positions(bytes: &[u8]) -> io::Result<Vec<usize>> {
+    let mut r = Reader { bytes, pos: 0 };
+    let mut positions = Vec::new();
+    while r.pos < bytes.len() {
+        r.ndarray_with(|tag, pos| match tag {
+            0xca | 0xcb => {
+                positions.push(pos);
+                Ok(())
+            }
+            _ => Err(invalid_data("parameters must be floats")),
+        })?;
+    }
+    Ok(positions)
+}
+
+/// `rust_nn` のモデルの重みとバイアスを並び順に返す
+pub fn model_params(bytes: &[u8]) -> io::Result<Vec<f64>> {
+    Ok(param_positions(bytes)?
+        .into_iter()
+        .map(|pos| match bytes[pos] {
+            0xca => f64::from(f32::from_be_bytes(
+                bytes[pos + 1..pos + 5].try_into().unwrap(),
+            )),
+            _ => f64::from_be_bytes(bytes[pos + 1..pos + 9].try_into().unwrap()),
+        })
+        .collect())
+}
+
+/// `model_params` と同じ並びの値でモデルの重みとバイアスを書き換える。形は変えない
+pub fn set_model_params(bytes: &mut [u8], params: &[f64]) -> io::Result<()> {
+    let positions = param_positions(bytes)?;
+    if positions.len() != params.len() {
+        return Err(invalid_data(&format!(
+            "the model has {} parameters but {} are given",
+            positions.len(),
+            params.len()
+        )));
+    }
+    for (pos, p) in positions.into_iter().zip(params) {
+        match bytes[pos] {
+            0xca => bytes[pos + 1..pos + 5].copy_from_slice(&(*p as f32).to_be_bytes()),
+            _ => bytes[pos + 1..pos + 9].copy_from_slice(&p.to_be_bytes()),
+        }
+    }
+    Ok(())
+}
+
+/// `layers` 層 (入力を含めた幅の数) で入力が 12 のモデルか確かめる
+pub(super) fn check_model(bytes: &[u8], name: &str, layers: usize) -> io::Result<()> {
+    let widths = model_widths(bytes)?;
+    if widths.len() != layers || widths[0] != 12 {
+        return Err(invalid_data(&format!(
+            "{name} needs {layers} widths starting with 12 but the model has {widths:?}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::evaluator::{NN4_TRUE_MODEL, NN6_FALSE_MODEL};
+    use crate::{NeuralNet4Evaluator, NeuralNet6Evaluator, build_ai};
+
+    #[test]
+    fn embedded_widths() {
+        assert_eq!(
+            model_widths(NN4_TRUE_MODEL).unwrap(),
+            vec![12, 64, 64, 64, 64]
+        );
+        let mut nn6 = vec![12];
+        nn6.extend([128; 6]);
+        assert_eq!(model_widths(NN6_FALSE_MODEL).unwrap(), nn6);
+        assert!(model_widths(&NN4_TRUE_MODEL[..NN4_TRUE_MODEL.len() - 1]).is_err());
+        assert!(model_widths(b"").is_err());
+        assert!(model_widths(b"not a model").is_err());
+    }
+
+    #[test]
+    fn params_roundtrip() {
+        let params = model_params(NN4_TRUE_MODEL).unwrap();
+        assert_eq!(params.len(), 12 * 64 + 64 + (64 * 64 + 64) * 3 + 64 + 1);
+        let mut bytes = NN4_TRUE_MODEL.to_vec();
+        let doubled = params.iter().map(|p| p * 2.0).collect::<Vec<_>>();
+        set_model_params(&mut bytes, &doubled).unwrap();
+        assert_eq!(bytes.len(), NN4_TRUE_MODEL.len());
+        assert_eq!(model_params(&bytes).unwrap(), doubled);
+        assert_eq!(model_widths(&bytes).unwrap(), vec![12, 64, 64, 64, 64]);
+        assert!(set_model_params(&mut bytes, &params[1..]).is_err());
+    }
+
+    #[test]
+    fn load_checks_architecture() {
+        assert!(NeuralNet4Evaluator::read(&mut &NN4_TRUE_MODEL[..]).is_ok());
+        assert!(NeuralNet6Evaluator::read(&mut &NN6_FALSE_MODEL[..]).is_ok());
+        let e = NeuralNet4Evaluator::read(&mut &NN6_FALSE_MODEL[..]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(NeuralNet6Evaluator::read(&mut &NN4_TRUE_MODEL[..]).is_err());
+
+        let path = std::env::temp_dir().join(format!("mancala-{}-nn6.model", std::process::id()));
+        std::fs::write(&path, NN6_FALSE_MODEL).unwrap();
+        let path = path.to_str().unwrap();
+        assert!(build_ai(false, &format!("dfs:nn6={path}:2")).is_ok());
+        assert!(build_ai(false, &format!("pdfs:nn6={path}:2:1")).is_ok());
+        assert!(build_ai(false, &format!("mctree:10:1:1.0:win:trunc-nn6={path}-3")).is_ok());
+        assert!(build_ai(false, &format!("puct:10:1.0:nn6={path}")).is_ok());
+        assert!(build_ai(false, &format!("dfs:nn4={path}:2")).is_err());
+        assert!(build_ai(false, "dfs:nn6=/nonexistent.model:2").is_err());
+        for spec in [
+            "mctree:10:1:1.0:win:softmax-nn6=PATH",
+            "mcgraph:10:1:1.0:margin:trunc-nn6=PATH-3",
+            "pmctree:10:1:1.0:2:tree:win:softmax-nn6=PATH",
+        ] {
+            assert!(
+                build_ai(false, &spec.replace("PATH", path)).is_ok(),
+                "{spec}"
+            );
+        }
+        std::fs::remove_file(path).unwrap();
+
+        // 最後の引数ならパスに ':' が含まれていてもよい
+        let path = std::env::temp_dir().join(format!("mancala-{}-a:b.model", std::process::id()));
+        std::fs::write(&path, NN6_FALSE_MODEL).unwrap();
+        let path = path.to_str().unwrap();
+        for spec in [
+            "mctree:10:1:1.0:win:trunc-nn6=PATH-3",
+            "mcgraph:10:1:1.0:win:softmax-nn6=PATH",
+            "pmctree:10:1:1.0:2:root:win:trunc-nn6=PATH-2",
+            "puct:10:1.0:nn6=PATH",
+        ] {
+            assert!(
+                build_ai(false, &spec.replace("PATH", path)).is_ok(),
+                "{spec}"
+            );
+        }
+        assert!(build_ai(false, &format!("dfs:nn6={path}:2")).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+use fnv::FnvHashMap;
+use instant::{Duration, Instant};
+use rand::Rng;
+
+use super::{
+    Searcher,
+    mctree::Backup,
+    rollout::{RandomRollout, RolloutPolicy},
+};
+use crate::{Board, Side};
+
+#[derive(Debug, Clone)]
+struct GraphNode {
+    /// どの親から来たかによらず、この局面を通った回数
+    visited_count: u32,
+    /// 手番側から見た報酬の合計
+    reward: f64,
+    board: Board,
+    terminal: Option<f64>,
+    expanded: bool,
+    /// (子のノードの番号, その辺を通った回数)
+    edges: Vec<(usize, u32)>,
+}
+
+impl GraphNode {
+    fn new(board: Board) -> GraphNode {
+        GraphNode {
+            visited_count: 0,
+            reward: 0.0,
+            board,
+            terminal: None,
+            expanded: false,
+            edges: Vec::new(),
+        }
+    }
+}
+
+/// 同じ局面を一つのノードにまとめた DAG 上の MCTS
+///
+/// 異なる手順で同じ局面に合流した場合、報酬と訪問回数はノードで共有し、
+/// UCB の探索項には辺ごとの訪問回数を使う。
+/// マンカラはターンごとにストアの石が増えるか石が右に進むので、局面が循環することはない
+pub struct McGraphSearcher<R, P = RandomRollout> {
+    rng: R,
+    policy: P,
+    limit: Duration,
+    expansion_threshold: u32,
+    c: f64,
+    backup: Backup,
+    nodes: Vec<GraphNode>,
+    /// 局面からノードの番号を引く
+    /// ハッシュ値だけをキーにすると衝突した別の局面と統計が混ざるので、盤面と手番をキーにする
+    index: FnvHashMap<(Board, Side), usize>,
+    max_nodes: usize,
+}
+
+const DEFAULT_MAX_NODES: usize = 1 << 20;
+
+impl<R: Rng> McGraphSearcher<R> {
+    pub fn new(rng: R, limit: u64, expansion_threshold: u32, c: f64) -> McGraphSearcher<R> {
+        McGraphSearcher::with_policy(rng, RandomRollout::new(), limit, expansion_threshold, c)
+    }
+}
+
+impl<R: Rng, P: RolloutPolicy> McGraphSearcher<R, P> {
+    pub fn with_policy(
+        rng: R,
+        policy: P,
+        limit: u64,
+        expansion_threshold: u32,
+        c: f64,
+    ) -> McGraphSearcher<R, P> {
+        McGraphSearcher {
+            rng,
+            policy,
+            limit: Duration::from_millis(limit),
+            expansion_threshold,
+            c,
+            backup: Backup::WinLoss,
+            nodes: Vec::new(),
+            index: FnvHashMap::default(),
+            max_nodes: DEFAULT_MAX_NODES,
+        }
+    }
+
+    pub fn set_backup(&mut self, backup: Backup) {
+        self.backup = backup;
+    }
+
+    /// ノード数の上限。上限に達したら展開をやめ、次の手番でノードを全部捨てる
+    pub fn set_max_nodes(&mut self, max_nodes: usize) {
+        self.max_nodes = max_nodes;
+    }
+
+    /// `board` のノードの番号。なければ作る
+    fn node_of(&mut self, board: Board) -> usize {
+        let side = board.side();
+        let nodes = &mut self.nodes;
+        *self
+            .index
+            .entry((board, side))
+            .or_insert_with_key(|(board, _)| {
+                nodes.push(GraphNode::new(board.clone()));
+                nodes.len() - 1
+            })
+    }
+
+    fn expand(&mut self, i: usize) {
+        let next_list = self.nodes[i].board.list_next();
+        let edges = next_list
+            .into_iter()
+            .map(|next| (self.node_of(next), 0))
+            .collect();
+        let node = &mut self.nodes[i];
+        node.edges = edges;
+        node.expanded = true;
+    }
+
+    /// UCB1 が最大の辺を選ぶ。同点の場合は乱数で選ぶ
+    fn choice_edge(&mut self, i: usize) -> usize {
+        let node = &self.nodes[i];
+        let log_count = f64::from(node.visited_count.max(1)).ln();
+        let mut best = 0;
+        let mut best_value = f64::NEG_INFINITY;
+        let mut ties = 0;
+        for (i, &(k, count)) in node.edges.iter().enumerate() {
+            let child = &self.nodes[k];
+            let value = if count == 0 || child.visited_count == 0 {
+                f64::INFINITY
+            } else {
+                let a = 1.0 - child.reward / f64::from(child.visited_count);
+                let b = self.c * (log_count / f64::from(count)).sqrt();
+                a + b
+            };
+            if value > best_value {
+                best = i;
+                best_value = value;
+                ties = 1;
+            } else if value == best_value {
+                ties += 1;
+                if self.rng.random_range(0..ties) == 0 {
+                    best = i;
+                }
+            }
+        }
+        best
+    }
+
+    fn playout(&mut self, root: usize) {
+        let mut path = Vec::new();
+        let mut id = root;
+        let mut reward = loop {
+            let node = &self.nodes[id];
+            if let Some(reward) = node.terminal {
+                break reward;
+            }
+            if node.board.is_finished() {
+                let reward = self.backup.reward(f64::from(node.board.last_score()));
+                self.nodes[id].terminal = Some(reward);
+                break reward;
+            }
+            if !node.expanded {
+                if node.visited_count < self.expansion_threshold
+                    || self.nodes.len() >= self.max_nodes
+                {
+                    let score = self.policy.rollout(&mut self.rng, &node.board);
+                    break self.backup.reward(score);
+                }
+                self.expand(id);
+            }
+            let i = self.choice_edge(id);
+            path.push((id, i));
+            id = self.nodes[id].edges[i].0;
+        };
+        let node = &mut self.nodes[id];
+        node.visited_count += 1;
+        node.reward += reward;
+        for (id, i) in path.into_iter().rev() {
+            reward = 1.0 - reward;
+            let node = &mut self.nodes[id];
+            node.visited_count += 1;
+            node.reward += reward;
+            node.edges[i].1 += 1;
+        }
+    }
+}
+
+impl<R: Rng, P: RolloutPolicy> Searcher for McGraphSearcher<R, P> {
+    fn sow(&mut self, board: &Board) -> Vec<usize> {
+        let start = Instant::now();
+        let next_with_pos = board.list_next_with_pos();
+        if next_with_pos.is_empty() {
+            return Vec::new();
+        }
+        if self.nodes.len() >= self.max_nodes {
+            self.nodes.clear();
+            self.index.clear();
+        }
+        let root = self.node_of(board.clone());
+        if !self.nodes[root].expanded {
+            self.expand(root);
+        }
+        while start.elapsed() < self.limit {
+            for _ in 0..1000 {
+                self.playout(root);
+            }
+        }
+        let &(best, _) = self.nodes[root]
+            .edges
+            .iter()
+            .max_by_key(|(_, count)| *count)
+            .unwrap();
+        next_with_pos[&self.nodes[best].board].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_pcg::Mcg128Xsl64;
+
+    #[test]
+    fn share_transpositions() {
+        let mut ai = McGraphSearcher::new(Mcg128Xsl64::new(1), 50, 1, 1.0);
+        let board = Board::new(true);
+        ai.sow(&board);
+        let root = &ai.nodes[ai.index[&(board.clone(), board.side())]];
+        let edge_total = root.edges.iter().map(|(_, c)| *c).sum::<u32>();
+        // 根の訪問回数は辺の訪問回数の合計と一致する
+        assert_eq!(root.visited_count, edge_total);
+        // 合流した局面では、ノードの訪問回数は入ってくる辺の訪問回数の合計以上になる
+        let mut incoming = vec![0; ai.nodes.len()];
+        for node in ai.nodes.iter() {
+            for &(k, c) in node.edges.iter() {
+                incoming[k] += c;
+            }
+        }
+        for (node, c) in ai.nodes.iter().zip(incoming) {
+            assert!(node.visited_count >= c);
+        }
+        // 各ノードは自分の局面と手番で引ける
+        assert_eq!(ai.index.len(), ai.nodes.len());
+        for (i, node) in ai.nodes.iter().enumerate() {
+            assert_eq!(ai.index[&(node.board.clone(), node.board.side())], i);
+        }
+    }
+
+    #[test]
+    fn take_winning_steal() {
+        // skip
+        if !(crate::PIT == 6 && crate::SEED == 4) {
+            return;
+        }
+        let board = Board::from_seeds(true, &[1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 20, 1]);
+        let mut ai = McGraphSearcher::new(Mcg128Xsl64::new(1), 100, 2, 1.0);
+        let mut b = board.clone();
+        for pos in ai.sow(&board) {
+            b.sow(pos);
+        }
+        assert!(b.scores().0 > 20, "{b}");
+    }
+}
@@ -0,0 +1,385 @@
+use std::{sync::Mutex, thread};
+
+use instant::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Mcg128Xsl64;
+
+use super::{
+    Searcher,
+    mctree::{
+        Backup, DEFAULT_MAX_NODES, McTreeSearcher, Node, best_child, choice_child, same_position,
+    },
+    rollout::{RandomRollout, RolloutPolicy},
+};
+use crate::Board;
+
+/// MCTS の並列化の方法
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parallelism {
+    /// スレッドごとに独立に木を育てて、最後に根の子の訪問回数を足し合わせる
+    Root,
+    /// 一つの木を共有し、探索中の経路には仮想損失を入れて他のスレッドと別の手を読ませる
+    Tree,
+}
+
+/// 複数スレッドで探索する MCTS
+/// 探索木は手番ごとに作り直す
+/// どちらの並列化でも勝敗が証明できた手は `McTreeSearcher` と同じようにすぐに選ぶ
+pub struct ParallelMcTreeSearcher<R, P = RandomRollout> {
+    rng: R,
+    policy: P,
+    limit: Duration,
+    expansion_threshold: u32,
+    c: f64,
+    backup: Backup,
+    threads: usize,
+    parallelism: Parallelism,
+    max_nodes: usize,
+    playouts: Vec<u64>,
+}
+
+/// 共有する探索木
+struct SharedTree {
+    root: Node,
+    nodes: usize,
+}
+
+/// 木を降りた先で報酬を決めるために必要なもの
+enum Leaf {
+    Reward(f64),
+    Rollout(Board),
+}
+
+impl<R: Rng> ParallelMcTreeSearcher<R> {
+    pub fn new(
+        rng: R,
+        limit: u64,
+        expansion_threshold: u32,
+        c: f64,
+        threads: usize,
+        parallelism: Parallelism,
+    ) -> ParallelMcTreeSearcher<R> {
+        ParallelMcTreeSearcher::with_policy(
+            rng,
+            RandomRollout::new(),
+            limit,
+            expansion_threshold,
+            c,
+            threads,
+            parallelism,
+        )
+    }
+}
+
+impl<R, P> ParallelMcTreeSearcher<R, P>
+where
+    R: Rng,
+    P: RolloutPolicy + Clone + Send,
+{
+    pub fn with_policy(
+        rng: R,
+        policy: P,
+        limit: u64,
+        expansion_threshold: u32,
+        c: f64,
+        threads: usize,
+        parallelism: Parallelism,
+    ) -> ParallelMcTreeSearcher<R, P> {
+        ParallelMcTreeSearcher {
+            rng,
+            policy,
+            limit: Duration::from_millis(limit),
+            expansion_threshold,
+            c,
+            backup: Backup::WinLoss,
+            threads: threads.max(1),
+            parallelism,
+            max_nodes: DEFAULT_MAX_NODES,
+            playouts: Vec::new(),
+        }
+    }
+
+    pub fn set_backup(&mut self, backup: Backup) {
+        self.backup = backup;
+    }
+
+    /// 全スレッド合わせたノード数の上限
+    pub fn set_max_nodes(&mut self, max_nodes: usize) {
+        self.max_nodes = max_nodes;
+    }
+
+    /// 直前の探索で各スレッドが行ったプレイアウトの回数
+    pub fn playouts_per_thread(&self) -> &[u64] {
+        &self.playouts
+    }
+
+    /// 各スレッドの根の子の訪問回数を足し合わせた根を返す
+    /// どれかのスレッドで証明できた子は証明済みとする
+    fn root_parallel(&mut self, board: &Board) -> Node {
+        let limit = self.limit.as_millis() as u64;
+        let searchers = (0..self.threads)
+            .map(|_| {
+                let mut ai = McTreeSearcher::with_policy(
+                    Mcg128Xsl64::from_rng(&mut self.rng),
+                    self.policy.clone(),
+                    limit,
+                    self.expansion_threshold,
+                    self.c,
+                );
+                ai.set_backup(self.backup);
+                ai.set_max_nodes(self.max_nodes / self.threads);
+                ai
+            })
+            .collect::<Vec<_>>();
+        let roots = thread::scope(|scope| {
+            let handles = searchers
+                .into_iter()
+                .map(|mut ai| scope.spawn(move || ai.grow(board)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        self.playouts = roots
+            .iter()
+            .map(|root| u64::from(root.visited_count))
+            .collect();
+        let mut merged = Node::new(board.clone());
+        for root in roots {
+            merged.visited_count += root.visited_count;
+            for mut child in root.children {
+                match merged
+                    .children
+                    .iter_mut()
+                    .find(|c| same_position(&c.board, &child.board))
+                {
+                    Some(c) => {
+                        c.visited_count += child.visited_count;
+                        c.reward += child.reward;
+                        c.proven = c.proven.or(child.proven);
+                    }
+                    None => {
+                        child.children.clear();
+                        merged.children.push(child);
+                    }
+                }
+            }
+        }
+        merged.prove();
+        merged
+    }
+
+    fn tree_parallel(&mut self, board: &Board) -> Node {
+        let start = Instant::now();
+        let mut root = Node::new(board.clone());
+        root.children
+            .extend(board.list_next().into_iter().map(Node::new));
+        let nodes = 1 + root.children.len();
+        let tree = Mutex::new(SharedTree { root, nodes });
+        let workers = (0..self.threads)
+            .map(|_| TreeWorker {
+                rng: Mcg128Xsl64::from_rng(&mut self.rng),
+                policy: self.policy.clone(),
+                expansion_threshold: self.expansion_threshold,
+                c: self.c,
+                backup: self.backup,
+                max_nodes: self.max_nodes,
+            })
+            .collect::<Vec<_>>();
+        let limit = self.limit;
+        self.playouts = thread::scope(|scope| {
+            let handles = workers
+                .into_iter()
+                .map(|mut worker| {
+                    let tree = &tree;
+                    scope.spawn(move || {
+                        let mut count = 0;
+                        // 根の勝敗が証明できたらそれ以上読まない
+                        while start.elapsed() < limit && tree.lock().unwrap().root.proven.is_none()
+                        {
+                            for _ in 0..100 {
+                                worker.playout(tree);
+                                count += 1;
+                            }
+                        }
+                        count
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        tree.into_inner().unwrap().root
+    }
+}
+
+struct TreeWorker<P> {
+    rng: Mcg128Xsl64,
+    policy: P,
+    expansion_threshold: u32,
+    c: f64,
+    backup: Backup,
+    max_nodes: usize,
+}
+
+impl<P: RolloutPolicy> TreeWorker<P> {
+    /// 木を降りて葉までの経路に仮想損失を入れる
+    fn descend(&mut self, tree: &mut SharedTree, path: &mut Vec<usize>) -> Leaf {
+        let mut node = &mut tree.root;
+        loop {
+            node.virtual_loss += 1;
+            if let Some(reward) = node.proven {
+                return Leaf::Reward(reward);
+            }
+            if node.children.is_empty() {
+                if node.board.is_finished() {
+                    let reward = self.backup.reward(f64::from(node.board.last_score()));
+                    node.proven = Some(reward);
+                    return Leaf::Reward(reward);
+                }
+                if node.visited_count + node.virtual_loss <= self.expansion_threshold
+                    || tree.nodes >= self.max_nodes
+                {
+                    return Leaf::Rollout(node.board.clone());
+                }
+                node.children
+                    .extend(node.board.list_next().into_iter().map(Node::new));
+                tree.nodes += node.children.len();
+            }
+            let i = choice_child(&mut self.rng, self.c, node);
+            path.push(i);
+            node = &mut node.children[i];
+        }
+    }
+
+    fn playout(&mut self, tree: &Mutex<SharedTree>) {
+        let mut path = Vec::new();
+        let leaf = self.descend(&mut tree.lock().unwrap(), &mut path);
+        // プレイアウトは木のロックを外して行う
+        let mut reward = match leaf {
+            Leaf::Reward(reward) => reward,
+            Leaf::Rollout(board) => self
+                .backup
+                .reward(self.policy.rollout(&mut self.rng, &board)),
+        };
+        // 根から見た報酬にしておく
+        if path.len() % 2 == 1 {
+            reward = 1.0 - reward;
+        }
+        backup(&mut tree.lock().unwrap().root, &path, reward);
+    }
+}
+
+/// 経路に沿って仮想損失を外して報酬を足し、葉から根に向かって勝敗の証明を伝える
+/// `reward` は `node` の手番側から見た報酬
+fn backup(node: &mut Node, path: &[usize], reward: f64) {
+    node.virtual_loss -= 1;
+    node.visited_count += 1;
+    node.reward += reward;
+    if let Some((&i, rest)) = path.split_first() {
+        backup(&mut node.children[i], rest, 1.0 - reward);
+        if node.children[i].proven.is_some() {
+            node.prove();
+        }
+    }
+}
+
+impl<R, P> Searcher for ParallelMcTreeSearcher<R, P>
+where
+    R: Rng,
+    P: RolloutPolicy + Clone + Send,
+{
+    fn sow(&mut self, board: &Board) -> Vec<usize> {
+        let next_with_pos = board.list_next_with_pos();
+        if next_with_pos.is_empty() {
+            return Vec::new();
+        }
+        let root = match self.parallelism {
+            Parallelism::Root => self.root_parallel(board),
+            Parallelism::Tree => self.tree_parallel(board),
+        };
+        next_with_pos[&root.children[best_child(&root)].board].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn use_configured_threads() {
+        let board = Board::new(true);
+        for parallelism in [Parallelism::Root, Parallelism::Tree] {
+            let mut ai =
+                ParallelMcTreeSearcher::new(Mcg128Xsl64::new(1), 50, 2, 1.0, 3, parallelism);
+            ai.sow(&board);
+            let playouts = ai.playouts_per_thread();
+            assert_eq!(playouts.len(), 3, "{parallelism:?}");
+            assert!(playouts.iter().all(|&p| p > 0), "{parallelism:?}");
+        }
+    }
+
+    #[test]
+    fn all_playouts_backed_up() {
+        let mut ai =
+            ParallelMcTreeSearcher::new(Mcg128Xsl64::new(1), 30, 2, 1.0, 2, Parallelism::Tree);
+        let root = ai.tree_parallel(&Board::new(true));
+        let total = root
+            .children
+            .iter()
+            .map(|c| u64::from(c.visited_count))
+            .sum::<u64>();
+        // 根の子の訪問回数の合計はプレイアウトの回数と一致する
+        assert_eq!(total, ai.playouts_per_thread().iter().sum::<u64>());
+    }
+
+    /// どちらの並列化でも根の勝敗が証明され、その値は `McTreeSearcher` と一致する
+    #[test]
+    fn prove_endgame() {
+        let mut seeds = [0; crate::PIT * 2];
+        seeds[crate::PIT - 1] = 2;
+        seeds[crate::PIT - 2] = 1;
+        seeds[crate::PIT * 2 - 1] = 1;
+        seeds[crate::PIT * 2 - 3] = 2;
+        for stealing in [true, false] {
+            let board = Board::from_seeds(stealing, &seeds);
+            let expected = McTreeSearcher::new(Mcg128Xsl64::new(1), 1000, 0, 1.0)
+                .grow(&board)
+                .proven;
+            assert!(expected.is_some());
+            for parallelism in [Parallelism::Root, Parallelism::Tree] {
+                let mut ai =
+                    ParallelMcTreeSearcher::new(Mcg128Xsl64::new(1), 1000, 0, 1.0, 2, parallelism);
+                let start = Instant::now();
+                let root = match parallelism {
+                    Parallelism::Root => ai.root_parallel(&board),
+                    Parallelism::Tree => ai.tree_parallel(&board),
+                };
+                // 証明できたら制限時間を待たずに終わる
+                assert!(start.elapsed() < Duration::from_millis(1000));
+                assert_eq!(root.proven, expected, "{parallelism:?}\n{board}");
+            }
+        }
+    }
+
+    #[test]
+    fn take_winning_steal() {
+        // skip
+        if !(crate::PIT == 6 && crate::SEED == 4) {
+            return;
+        }
+        let board = Board::from_seeds(true, &[1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 20, 1]);
+        for parallelism in [Parallelism::Root, Parallelism::Tree] {
+            let mut ai =
+                ParallelMcTreeSearcher::new(Mcg128Xsl64::new(1), 100, 2, 1.0, 2, parallelism);
+            let mut b = board.clone();
+            for pos in ai.sow(&board) {
+                b.sow(pos);
+            }
+            assert!(b.scores().0 > 20, "{parallelism:?}\n{b}");
+        }
+    }
+}
@@ -1,136 +1,631 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
 use instant::{Duration, Instant};
 use rand::Rng;
 
 use super::{
-    Searcher,
-    utils::{choice_with_weight, random_down},
+    AnytimeSearcher, Searcher,
+    dump::{DumpNode, DumpOptions},
+    rollout::{RandomRollout, RolloutPolicy},
 };
-use crate::Board;
+use crate::{Board, PIT, SEED, learn::Tablebase};
 
 #[derive(Debug, Clone)]
-struct Node {
-    visited_count: u32,
-    win_count: u32,
-    board: Board,
-    is_win: Option<bool>,
-    children: Vec<Node>,
+pub(super) struct Node {
+    pub(super) visited_count: u32,
+    /// 手番側から見た報酬の合計。報酬は 0 から 1 の値
+    pub(super) reward: f64,
+    pub(super) board: Board,
+    /// 終局しているか、子から勝敗が証明できた場合の報酬
+    pub(super) proven: Option<f64>,
+    /// 親から見てこのノードに進む事前確率。PUCT で使う
+    pub(super) prior: f64,
+    /// 並列探索で、他のスレッドが探索中の回数。その分は負けとして数える
+    pub(super) virtual_loss: u32,
+    pub(super) children: Vec<Node>,
 }
 
-pub struct McTreeSearcher<R> {
+/// プレイアウトの結果をどう報酬にするか
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backup {
+    /// 勝ちを 1、引き分けを 0.5、負けを 0 とする
+    WinLoss,
+    /// 得点差を 0 から 1 に線形に写したものを報酬とする
+    Margin,
+}
+
+impl Backup {
+    /// `score` は手番側から見た最終的な得点差
+    pub(super) fn reward(self, score: f64) -> f64 {
+        match self {
+            Backup::WinLoss => {
+                if score > 0.0 {
+                    1.0
+                } else if score < 0.0 {
+                    0.0
+                } else {
+                    0.5
+                }
+            }
+            Backup::Margin => {
+                let total = (PIT * 2) as f64 * f64::from(SEED);
+                (0.5 + score / (2.0 * total)).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// 前回の探索木は使い回すので、自分と相手の手を打った後の局面が木に残っていれば続きから探索する
+pub struct McTreeSearcher<R, P = RandomRollout> {
     rng: R,
+    policy: P,
     limit: Duration,
     expansion_threshold: u32,
     c: f64,
+    backup: Backup,
+    root: Option<Node>,
+    nodes: usize,
+    max_nodes: usize,
+    dump: Option<DumpOptions>,
+    last_dump: Option<DumpNode>,
+    tablebase: Option<Arc<Tablebase>>,
+}
+
+pub(super) const DEFAULT_MAX_NODES: usize = 1 << 20;
+
+pub(super) fn same_position(a: &Board, b: &Board) -> bool {
+    a == b && a.side() == b.side()
+}
+
+/// UCB1 が最大の子を選ぶ。同点の場合は乱数で選ぶ
+/// 探索項には `node` 自身の訪問回数 (仮想損失を含む) の対数を使う
+pub(super) fn choice_child<R: Rng>(rng: &mut R, c: f64, node: &Node) -> usize {
+    let log_count = f64::from((node.visited_count + node.virtual_loss).max(1)).ln();
+    let mut best = 0;
+    let mut best_value = f64::NEG_INFINITY;
+    let mut ties = 0;
+    for (i, child) in node.children.iter().enumerate() {
+        let count = child.visited_count + child.virtual_loss;
+        let value = if count == 0 {
+            f64::INFINITY
+        } else {
+            // 子の手番側から見て仮想損失の分は勝ちとする
+            let a = 1.0 - (child.reward + f64::from(child.virtual_loss)) / f64::from(count);
+            let b = c * (log_count / f64::from(count)).sqrt();
+            a + b
+        };
+        if value > best_value {
+            best = i;
+            best_value = value;
+            ties = 1;
+        } else if value == best_value {
+            ties += 1;
+            if rng.random_range(0..ties) == 0 {
+                best = i;
+            }
+        }
+    }
+    best
 }
 
 impl Node {
-    fn new(board: Board) -> Node {
+    pub(super) fn new(board: Board) -> Node {
+        Node::with_prior(board, 1.0)
+    }
+
+    pub(super) fn with_prior(board: Board, prior: f64) -> Node {
         Node {
             visited_count: 0,
-            win_count: 0,
+            reward: 0.0,
             board,
-            is_win: None,
+            proven: None,
+            prior,
+            virtual_loss: 0,
             children: Vec::new(),
         }
     }
+
+    pub(super) fn size(&self) -> usize {
+        1 + self.children.iter().map(Node::size).sum::<usize>()
+    }
+
+    /// 子の証明済みの報酬から、このノードの報酬が確定するか調べる
+    /// 相手が負ける子が一つでもあれば勝ち、全ての子が証明済みならその中で最善の報酬になる
+    pub(super) fn prove(&mut self) {
+        if self.proven.is_some() || self.children.is_empty() {
+            return;
+        }
+        let mut best = f64::NEG_INFINITY;
+        let mut all = true;
+        for child in self.children.iter() {
+            match child.proven {
+                Some(reward) => best = best.max(1.0 - reward),
+                None => all = false,
+            }
+        }
+        if all || best >= 1.0 {
+            self.proven = Some(best);
+        }
+    }
+
+    /// `pos_list` はこのノードに進む打ち手、`ucb` は親から見た UCB の値
+    fn dump(
+        &self,
+        pos_list: Vec<usize>,
+        ucb: Option<f64>,
+        c: f64,
+        level: usize,
+        options: &DumpOptions,
+    ) -> DumpNode {
+        let mut children = Vec::new();
+        if level < options.max_depth && !self.children.is_empty() {
+            let log_count = f64::from(self.visited_count.max(1)).ln();
+            let next_with_pos = self.board.list_next_with_pos();
+            for child in self.children.iter() {
+                if u64::from(child.visited_count) < options.min_visits {
+                    continue;
+                }
+                let n = f64::from(child.visited_count);
+                let ucb = if child.visited_count == 0 {
+                    f64::INFINITY
+                } else {
+                    1.0 - child.reward / n + c * (log_count / n).sqrt()
+                };
+                children.push(child.dump(
+                    next_with_pos[&child.board].clone(),
+                    Some(ucb),
+                    c,
+                    level + 1,
+                    options,
+                ));
+            }
+        }
+        DumpNode {
+            position: self.board.notation(),
+            pos_list,
+            visits: u64::from(self.visited_count),
+            // 根には親がないので書かない
+            wins: ucb.map(|_| f64::from(self.visited_count) - self.reward),
+            ucb,
+            score: self.proven.map(|r| format!("proven {r}")),
+            children,
+            ..DumpNode::default()
+        }
+    }
+
+    /// 訪問回数が `min_visits` 未満のノードの子を捨てて、残ったノード数を返す
+    fn prune(&mut self, min_visits: u32) -> usize {
+        if self.visited_count < min_visits {
+            self.children.clear();
+            return 1;
+        }
+        1 + self
+            .children
+            .iter_mut()
+            .map(|child| child.prune(min_visits))
+            .sum::<usize>()
+    }
 }
 
 impl<R: Rng> McTreeSearcher<R> {
     pub fn new(rng: R, limit: u64, expansion_threshold: u32, c: f64) -> McTreeSearcher<R> {
+        McTreeSearcher::with_policy(rng, RandomRollout::new(), limit, expansion_threshold, c)
+    }
+}
+
+impl<R: Rng, P: RolloutPolicy> McTreeSearcher<R, P> {
+    pub fn with_policy(
+        rng: R,
+        policy: P,
+        limit: u64,
+        expansion_threshold: u32,
+        c: f64,
+    ) -> McTreeSearcher<R, P> {
         McTreeSearcher {
             rng,
+            policy,
             limit: Duration::from_millis(limit),
             expansion_threshold,
             c,
+            backup: Backup::WinLoss,
+            root: None,
+            nodes: 0,
+            max_nodes: DEFAULT_MAX_NODES,
+            dump: None,
+            last_dump: None,
+            tablebase: None,
         }
     }
 
-    fn choice_child(&mut self, log_total_count: f64, node: &Node) -> usize {
-        let mut weight = Vec::with_capacity(node.children.len());
-        for (i, child) in node.children.iter().enumerate() {
-            if child.visited_count == 0 {
-                return i;
+    pub fn set_backup(&mut self, backup: Backup) {
+        self.backup = backup;
+    }
+
+    /// 探索木のノード数の上限を設定する
+    /// 上限に達したら展開をやめ、手番の切り替わりで訪問回数の少ないノードから捨てる
+    pub fn set_max_nodes(&mut self, max_nodes: usize) {
+        self.max_nodes = max_nodes;
+    }
+
+    /// 終盤データベースで引ける局面は、プレイアウトせずに勝敗が証明できたものとする
+    pub fn set_tablebase(&mut self, tablebase: Arc<Tablebase>) {
+        self.tablebase = Some(tablebase);
+    }
+
+    /// 探索木の根を `board` に移す。木の中に見つからなければ木を捨てる
+    fn reroot(&mut self, board: &Board) {
+        self.root = match self.root.take() {
+            Some(root) if same_position(&root.board, board) => Some(root),
+            Some(root) => root
+                .children
+                .into_iter()
+                .find(|child| same_position(&child.board, board)),
+            None => None,
+        };
+        self.nodes = self.root.as_ref().map_or(0, Node::size);
+        if self.nodes > self.max_nodes {
+            let mut min_visits = self.expansion_threshold + 1;
+            while self.nodes > 1 && self.nodes > self.max_nodes / 2 {
+                self.nodes = self.root.as_mut().unwrap().prune(min_visits);
+                min_visits = min_visits.saturating_mul(2);
             }
-            let a = 1.0 - child.win_count as f64 / child.visited_count as f64;
-            let b = self.c * (log_total_count / child.visited_count as f64).sqrt();
-            weight.push(a + b);
         }
-        choice_with_weight(&mut self.rng, &weight)
     }
 
-    fn selection(&mut self, log_total_count: f64, node: &mut Node) -> bool {
+    /// `node` の手番側から見た報酬を返す
+    fn selection(&mut self, node: &mut Node) -> f64 {
         node.visited_count += 1;
-        if let Some(win) = node.is_win {
-            if win {
-                node.win_count += 1;
-            }
-            return win;
+        if let Some(reward) = node.proven {
+            node.reward += reward;
+            return reward;
         }
         if node.children.is_empty() {
-            if node.board.is_finished() {
-                // 引き分けも勝ちに入れとく
-                return if node.board.score() >= 0 {
-                    node.is_win = Some(true);
-                    node.win_count += 1;
-                    true
-                } else {
-                    node.is_win = Some(false);
-                    false
-                };
+            let score = match &self.tablebase {
+                Some(tb) => tb.probe(&node.board),
+                None => None,
+            };
+            let score = score.or_else(|| node.board.is_finished().then(|| node.board.last_score()));
+            if let Some(score) = score {
+                let reward = self.backup.reward(f64::from(score));
+                node.proven = Some(reward);
+                node.reward += reward;
+                return reward;
             }
-            if node.visited_count <= self.expansion_threshold {
-                let board = random_down(&mut self.rng, &node.board);
-                return if board.side() == node.board.side() {
-                    if board.score() >= 0 {
-                        node.win_count += 1;
-                        true
-                    } else {
-                        false
-                    }
-                } else if board.score() <= 0 {
-                    node.win_count += 1;
-                    true
-                } else {
-                    false
-                };
+            if node.visited_count <= self.expansion_threshold || self.nodes >= self.max_nodes {
+                let score = self.policy.rollout(&mut self.rng, &node.board);
+                let reward = self.backup.reward(score);
+                node.reward += reward;
+                return reward;
             }
         }
         if node.children.is_empty() {
             node.children
                 .extend(node.board.list_next().into_iter().map(Node::new));
+            self.nodes += node.children.len();
+        }
+        let i = choice_child(&mut self.rng, self.c, node);
+        let reward = 1.0 - self.selection(&mut node.children[i]);
+        if node.children[i].proven.is_some() {
+            node.prove();
+        }
+        node.reward += reward;
+        reward
+    }
+
+    /// `board` を根とする木を制限時間まで育てて返す
+    pub(super) fn grow(&mut self, board: &Board) -> Node {
+        let start = Instant::now();
+        let limit = self.limit;
+        self.grow_while(board, |_| start.elapsed() < limit)
+    }
+
+    /// `board` を根とする木を `cont` が `false` を返すまで育てて返す
+    fn grow_while(&mut self, board: &Board, mut cont: impl FnMut(&Node) -> bool) -> Node {
+        self.reroot(board);
+        let mut node = match self.root.take() {
+            Some(node) => node,
+            None => {
+                self.nodes = 1;
+                Node::new(board.clone())
+            }
+        };
+        if node.children.is_empty() {
+            node.children
+                .extend(node.board.list_next().into_iter().map(Node::new));
+            self.nodes += node.children.len();
         }
-        let i = self.choice_child(log_total_count, node);
-        let win = !self.selection(log_total_count, &mut node.children[i]);
-        if win {
-            node.win_count += 1;
+        node.prove();
+        // 根の勝敗が証明できたらそれ以上読まない
+        while node.proven.is_none() && cont(&node) {
+            for _ in 0..1000 {
+                self.selection(&mut node);
+            }
+        }
+        node
+    }
+
+    /// `node` から最善手を選び、選んだ子を相手の手番の根として残す
+    fn finish(&mut self, mut node: Node, next_with_pos: &HashMap<Board, Vec<usize>>) -> Vec<usize> {
+        if let Some(options) = self.dump {
+            self.last_dump = Some(node.dump(Vec::new(), None, self.c, 0, &options));
         }
-        win
+        let best = node.children.swap_remove(best_child(&node));
+        let pos_list = next_with_pos[&best.board].clone();
+        // 相手の手番の局面を根として残しておく
+        self.root = Some(best);
+        self.nodes = self.root.as_ref().map_or(0, Node::size);
+        pos_list
     }
 }
 
-impl<R: Rng> Searcher for McTreeSearcher<R> {
+/// 最善の子の番号。勝敗が証明できていればそれに従い、そうでなければ訪問回数が最大の子
+pub(super) fn best_child(node: &Node) -> usize {
+    match node.proven {
+        // 証明済みの手があればすぐに選ぶ
+        Some(_) => {
+            node.children
+                .iter()
+                .enumerate()
+                .filter_map(|(i, child)| child.proven.map(|reward| (i, 1.0 - reward)))
+                .max_by(|x, y| x.1.total_cmp(&y.1))
+                .unwrap()
+                .0
+        }
+        None => {
+            node.children
+                .iter()
+                .enumerate()
+                .max_by(|x, y| x.1.visited_count.cmp(&y.1.visited_count))
+                .unwrap()
+                .0
+        }
+    }
+}
+
+impl<R: Rng, P: RolloutPolicy> Searcher for McTreeSearcher<R, P> {
     fn sow(&mut self, board: &Board) -> Vec<usize> {
-        let start = Instant::now();
         let next_with_pos = board.list_next_with_pos();
         if next_with_pos.is_empty() {
             return Vec::new();
         }
-        let mut node = Node::new(board.clone());
-        for board in next_with_pos.keys() {
-            node.children.push(Node::new(board.clone()));
+        let node = self.grow(board);
+        self.finish(node, &next_with_pos)
+    }
+
+    fn observe(&mut self, board: &Board) {
+        self.reroot(board);
+    }
+
+    fn set_dump(&mut self, options: Option<DumpOptions>) {
+        self.dump = options;
+    }
+
+    fn take_dump(&mut self) -> Option<DumpNode> {
+        self.last_dump.take()
+    }
+}
+
+impl<R: Rng, P: RolloutPolicy> AnytimeSearcher for McTreeSearcher<R, P> {
+    fn think(
+        &mut self,
+        board: &Board,
+        stop: &AtomicBool,
+        report: &mut dyn FnMut(&[usize]),
+    ) -> Vec<usize> {
+        let next_with_pos = board.list_next_with_pos();
+        if next_with_pos.is_empty() {
+            return Vec::new();
         }
-        let mut total_count = 0;
-        while start.elapsed() < self.limit {
-            for _ in 0..1000 {
-                total_count += 1;
-                self.selection((total_count as f64).ln(), &mut node);
-            }
+        let node = self.grow_while(board, |node| {
+            report(&next_with_pos[&node.children[best_child(node)].board]);
+            !stop.load(Ordering::Relaxed)
+        });
+        self.finish(node, &next_with_pos)
+    }
+
+    /// 相手の手番の局面で木を育てておく。相手が打った後は `observe` で続きから使う
+    fn ponder(&mut self, board: &Board, stop: &AtomicBool) {
+        let node = self.grow_while(board, |_| !stop.load(Ordering::Relaxed));
+        self.root = Some(node);
+        self.nodes = self.root.as_ref().map_or(0, Node::size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_pcg::Mcg128Xsl64;
+
+    #[test]
+    fn reuse_tree_after_reply() {
+        let mut ai = McTreeSearcher::new(Mcg128Xsl64::new(1), 50, 2, 1.0);
+        let mut board = Board::new(true);
+        for pos in ai.sow(&board) {
+            board.sow(pos);
         }
-        let best = node
+        let root = ai.root.as_ref().unwrap();
+        assert!(same_position(&root.board, &board));
+        let reply = root
             .children
             .iter()
-            .max_by(|x, y| x.visited_count.cmp(&y.visited_count))
+            .max_by_key(|child| child.visited_count)
             .unwrap();
-        next_with_pos[&best.board].clone()
+        let visited = reply.visited_count;
+        let reply = reply.board.clone();
+        assert!(visited > 0);
+
+        ai.observe(&reply);
+        assert_eq!(ai.root.as_ref().unwrap().visited_count, visited);
+        ai.sow(&reply);
+        assert!(ai.root.as_ref().unwrap().visited_count > 0);
+
+        // 木にない局面を渡されたら捨てる
+        ai.observe(&Board::new(false));
+        assert!(ai.root.is_none());
+    }
+
+    #[test]
+    fn reward() {
+        assert_eq!(Backup::WinLoss.reward(3.0), 1.0);
+        assert_eq!(Backup::WinLoss.reward(0.0), 0.5);
+        assert_eq!(Backup::WinLoss.reward(-1.0), 0.0);
+        assert_eq!(Backup::Margin.reward(0.0), 0.5);
+        assert!(Backup::Margin.reward(2.0) > Backup::Margin.reward(1.0));
+        let total = (PIT * 2) as f64 * f64::from(SEED);
+        assert_eq!(Backup::Margin.reward(total), 1.0);
+        assert_eq!(Backup::Margin.reward(-total), 0.0);
+        assert_eq!(Backup::Margin.reward(total * 2.0), 1.0);
+    }
+
+    /// 終局の報酬は穴に残った種も数える
+    #[test]
+    fn terminal_reward_counts_pits() {
+        let mut seeds = [0; PIT * 2];
+        seeds[PIT] = 5;
+        let board = Board::from_seeds(true, &seeds);
+        assert!(board.is_finished());
+        assert_eq!(board.score(), 0);
+        for (backup, expected) in [
+            (Backup::WinLoss, 0.0),
+            (Backup::Margin, Backup::Margin.reward(-5.0)),
+        ] {
+            let mut ai = McTreeSearcher::new(Mcg128Xsl64::new(1), 10, 0, 1.0);
+            ai.set_backup(backup);
+            let mut node = Node::new(board.clone());
+            let reward = ai.selection(&mut node);
+            assert!((reward - expected).abs() < 1e-9, "{backup:?} {reward}");
+            assert_eq!(node.proven, Some(reward));
+        }
+    }
+
+    #[test]
+    fn take_winning_steal() {
+        // skip
+        if !(PIT == 6 && SEED == 4) {
+            return;
+        }
+        // 0 番から蒔けば相手の 4 番の穴の 20 個を横取りして勝ちが決まる
+        let board = Board::from_seeds(true, &[1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 20, 1]);
+        for backup in [Backup::WinLoss, Backup::Margin] {
+            let mut ai = McTreeSearcher::new(Mcg128Xsl64::new(1), 100, 2, 1.0);
+            ai.set_backup(backup);
+            let mut b = board.clone();
+            for pos in ai.sow(&board) {
+                b.sow(pos);
+            }
+            assert!(b.scores().0 > 20, "{backup:?}\n{b}");
+        }
+    }
+
+    /// 終局まで読み切れる局面では根の勝敗が証明され、その値は minimax と一致する
+    #[test]
+    fn prove_endgame() {
+        fn solve(board: &Board) -> i8 {
+            if board.is_finished() {
+                return board.last_score();
+            }
+            board.list_next().iter().map(|b| -solve(b)).max().unwrap()
+        }
+        let mut seeds = [0; PIT * 2];
+        seeds[PIT - 1] = 2;
+        seeds[PIT - 2] = 1;
+        seeds[PIT * 2 - 1] = 1;
+        seeds[PIT * 2 - 3] = 2;
+        for stealing in [true, false] {
+            let board = Board::from_seeds(stealing, &seeds);
+            let mut ai = McTreeSearcher::new(Mcg128Xsl64::new(1), 1000, 0, 1.0);
+            let start = Instant::now();
+            let node = ai.grow(&board);
+            // 証明できたら制限時間を待たずに終わる
+            assert!(start.elapsed() < Duration::from_millis(1000));
+            let expected = Backup::WinLoss.reward(f64::from(solve(&board)));
+            assert_eq!(node.proven, Some(expected), "{board}");
+        }
+    }
+
+    #[test]
+    fn dump_tree() {
+        let mut ai = McTreeSearcher::new(Mcg128Xsl64::new(1), 20, 2, 1.0);
+        ai.set_dump(Some(DumpOptions {
+            max_depth: 2,
+            min_visits: 10,
+        }));
+        let board = Board::new(true);
+        ai.sow(&board);
+        let tree = ai.take_dump().unwrap();
+        assert_eq!(tree.position, board.notation());
+        assert!(!tree.children.is_empty());
+        for child in tree.children.iter() {
+            assert!(child.visits >= 10);
+            assert!(child.ucb.is_some());
+            assert!(!child.pos_list.is_empty());
+            assert!(child.children.iter().all(|c| c.children.is_empty()));
+            // 孫の UCB は根ではなく親 (子) の訪問回数から求める
+            let log_count = (child.visits as f64).ln();
+            for c in child.children.iter() {
+                let n = c.visits as f64;
+                let expected = c.wins.unwrap() / n + (log_count / n).sqrt();
+                assert!((c.ucb.unwrap() - expected).abs() < 1e-9);
+            }
+        }
+        assert!(ai.take_dump().is_none());
+    }
+
+    #[test]
+    fn choice_child_uses_parent_count() {
+        let board = Board::new(true);
+        let mut node = Node::new(board.clone());
+        node.children
+            .extend(board.list_next().into_iter().take(2).map(Node::new));
+        // 親の訪問回数が 1 なら探索項は 0 で、勝率の高い子を選ぶ
+        node.visited_count = 1;
+        node.children[0].visited_count = 10;
+        node.children[0].reward = 5.0;
+        node.children[1].visited_count = 1;
+        node.children[1].reward = 1.0;
+        let mut rng = Mcg128Xsl64::new(1);
+        assert_eq!(choice_child(&mut rng, 1.0, &node), 0);
+        // 訪問回数が増えると探索項で訪問の少ない子を選ぶ
+        node.visited_count = 1000;
+        assert_eq!(choice_child(&mut rng, 1.0, &node), 1);
+    }
+
+    #[test]
+    fn tablebase_proves_root() {
+        let mut seeds = [0; PIT * 2];
+        seeds[1] = 3;
+        seeds[PIT - 1] = 1;
+        seeds[PIT + 1] = 2;
+        seeds[PIT + 4] = 2;
+        let board = Board::from_seeds(true, &seeds);
+        let tb = Arc::new(Tablebase::generate(true, 8));
+        let mut ai = McTreeSearcher::new(Mcg128Xsl64::new(1), 1000, 2, 1.0);
+        ai.set_tablebase(tb.clone());
+        let node = ai.grow(&board);
+        let expected = Backup::WinLoss.reward(f64::from(tb.probe(&board).unwrap()));
+        assert_eq!(node.proven, Some(expected));
+    }
+
+    #[test]
+    fn max_nodes() {
+        let mut ai = McTreeSearcher::new(Mcg128Xsl64::new(1), 50, 0, 1.0);
+        ai.set_max_nodes(200);
+        let mut board = Board::new(false);
+        for _ in 0..3 {
+            if board.is_finished() {
+                break;
+            }
+            for pos in ai.sow(&board) {
+                board.sow(pos);
+            }
+            // 展開は子をまとめて追加するので、少しだけ上限を超えることがある
+            assert!(ai.nodes <= 200 + 64);
+        }
     }
 }
@@ -1,16 +1,38 @@
+mod anytime;
 mod depth_search;
+mod dump;
 mod evaluator;
 mod greedy;
+mod mcgraph;
+mod mcparallel;
 mod mctree;
+mod parallel;
+mod perfect;
+mod puct;
+mod result;
+mod rollout;
 mod simple;
 mod utils;
 
+pub use self::anytime::{AnytimeSearcher, Background};
 pub use self::depth_search::{DepthSearcher, RandomDepthSearcher};
+pub use self::dump::{DumpFormat, DumpNode, DumpOptions};
 pub use self::evaluator::*;
-pub use self::greedy::GreedySearcher;
-pub use self::mctree::McTreeSearcher;
+pub use self::greedy::{GreedySearcher, greedy_turn};
+pub use self::mcgraph::McGraphSearcher;
+pub use self::mcparallel::{ParallelMcTreeSearcher, Parallelism};
+pub use self::mctree::{Backup, McTreeSearcher};
+pub use self::parallel::{ParallelDepthSearcher, TranspositionTable};
+pub use self::perfect::PerfectSearcher;
+pub use self::puct::{
+    LinearPolicy, ModelPolicy, Policy, PolicyValueModel, PuctSearcher, UniformPolicy, ValueModel,
+};
+pub use self::result::{SearchResult, pv_to_turns, search_root, search_root_until};
+pub use self::rollout::{
+    GreedyRollout, RandomRollout, RolloutPolicy, SoftmaxRollout, TruncatedRollout,
+};
 pub use self::simple::{Interactive, RandomSearcher};
-pub use utils::ab_search;
+pub use utils::{PvSearch, ab_search, ab_search_q, quiescence_search};
 
 use std::fmt::Debug;
 
@@ -21,11 +43,28 @@ use crate::board::Board;
 
 pub trait Searcher {
     fn sow(&mut self, board: &Board) -> Vec<usize>;
+
+    /// ターンが終わるたびに、その後の盤面を教えてもらう
+    /// 前の探索結果を使い回す AI のためのもの
+    fn observe(&mut self, _board: &Board) {}
+
+    /// `sow` するたびに探索木を記録する。`None` なら記録しない
+    fn set_dump(&mut self, _options: Option<DumpOptions>) {}
+
+    /// 直前の `sow` で記録した探索木を取り出す
+    fn take_dump(&mut self) -> Option<DumpNode> {
+        None
+    }
 }
 
 pub trait Evaluator {
     type Score: Score;
     fn eval(&mut self, board: &Board) -> Self::Score;
+
+    /// 読み切った値が分かる局面ならその値を返す。探索はそれ以上読まずにこの値を使う
+    fn exact(&mut self, _board: &Board) -> Option<Self::Score> {
+        None
+    }
 }
 
 pub trait Score: PartialOrd + Copy + Debug {
@@ -34,6 +73,196 @@ pub trait Score: PartialOrd + Copy + Debug {
     fn flip(&self) -> Self;
 }
 
+fn human<E>(evaluator: E, max_depth: usize, live_hint: bool) -> Box<dyn Searcher>
+where
+    E: Evaluator + Clone + Send + 'static,
+{
+    let mut ai = Interactive::new(evaluator, max_depth);
+    ai.set_live_hint(live_hint);
+    Box::new(ai)
+}
+
+fn dfs<E>(evaluator: E, max_depth: usize, qdepth: usize) -> Box<dyn Searcher>
+where
+    E: Evaluator + 'static,
+{
+    let mut ai = DepthSearcher::new(evaluator, max_depth);
+    ai.set_quiescence(qdepth);
+    Box::new(ai)
+}
+
+fn pdfs<E>(evaluator: E, max_depth: usize, threads: usize, qdepth: usize) -> Box<dyn Searcher>
+where
+    E: Evaluator + Clone + Send + 'static,
+    E::Score: Send,
+{
+    let mut ai = ParallelDepthSearcher::new(evaluator, max_depth, threads);
+    ai.set_quiescence(qdepth);
+    Box::new(ai)
+}
+
+/// ロールアウトの方策から探索器を作る
+/// 方策の型ごとに探索器の型が変わるので、ジェネリックなメソッドで受け取る
+trait WithRollout {
+    fn build<P>(self, policy: P) -> Box<dyn Searcher>
+    where
+        P: RolloutPolicy + Clone + Send + 'static;
+}
+
+struct McTreeArgs {
+    limit: u64,
+    ex: u32,
+    c: f64,
+    backup: Backup,
+}
+
+impl WithRollout for McTreeArgs {
+    fn build<P>(self, policy: P) -> Box<dyn Searcher>
+    where
+        P: RolloutPolicy + Clone + Send + 'static,
+    {
+        let rng = Rng::from_rng(&mut rng());
+        let mut ai = McTreeSearcher::with_policy(rng, policy, self.limit, self.ex, self.c);
+        ai.set_backup(self.backup);
+        Box::new(ai)
+    }
+}
+
+struct McGraphArgs(McTreeArgs);
+
+impl WithRollout for McGraphArgs {
+    fn build<P>(self, policy: P) -> Box<dyn Searcher>
+    where
+        P: RolloutPolicy + Clone + Send + 'static,
+    {
+        let McTreeArgs {
+            limit,
+            ex,
+            c,
+            backup,
+        } = self.0;
+        let mut ai = McGraphSearcher::with_policy(Rng::from_rng(&mut rng()), policy, limit, ex, c);
+        ai.set_backup(backup);
+        Box::new(ai)
+    }
+}
+
+struct ParallelMcTreeArgs {
+    tree: McTreeArgs,
+    threads: usize,
+    parallelism: Parallelism,
+}
+
+impl WithRollout for ParallelMcTreeArgs {
+    fn build<P>(self, policy: P) -> Box<dyn Searcher>
+    where
+        P: RolloutPolicy + Clone + Send + 'static,
+    {
+        let McTreeArgs {
+            limit,
+            ex,
+            c,
+            backup,
+        } = self.tree;
+        let mut ai = ParallelMcTreeSearcher::with_policy(
+            Rng::from_rng(&mut rng()),
+            policy,
+            limit,
+            ex,
+            c,
+            self.threads,
+            self.parallelism,
+        );
+        ai.set_backup(backup);
+        Box::new(ai)
+    }
+}
+
+/// `softmax-(eval)` か `trunc-(eval)-(depth)` の方策で作る。`depth` が `None` なら softmax
+fn eval_rollout<E, W>(eval: E, depth: Option<usize>, with: W) -> Box<dyn Searcher>
+where
+    E: Evaluator + Clone + Send + 'static,
+    E::Score: Into<f64>,
+    W: WithRollout,
+{
+    match depth {
+        None => with.build(SoftmaxRollout::new(eval)),
+        Some(depth) => with.build(TruncatedRollout::new(eval, depth)),
+    }
+}
+
+const ROLLOUT_USAGE: &str = "(rollout) = random|greedy|softmax-(eval)|trunc-(eval)-(depth), (eval) = diff|pos|nn4|nn6|(nn4|nn6)=(path)";
+
+/// ロールアウトの指定を読んで `with` で探索器を作る。知らない指定なら `None`
+///
+/// 評価関数をファイルから読むときにパスに '-' が含まれていてもよいように、
+/// `trunc` の深さは最後の '-' の後ろとする
+fn with_rollout<W>(stealing: bool, spec: &str, with: W) -> Result<Option<Box<dyn Searcher>>, String>
+where
+    W: WithRollout,
+{
+    let (kind, rest) = spec.split_once('-').unwrap_or((spec, ""));
+    let (eval, depth) = match kind {
+        "random" if rest.is_empty() => return Ok(Some(with.build(RandomRollout::new()))),
+        "greedy" if rest.is_empty() => return Ok(Some(with.build(GreedyRollout::new()))),
+        "softmax" => (rest, None),
+        "trunc" => {
+            let Some((eval, depth)) = rest.rsplit_once('-') else {
+                return Ok(None);
+            };
+            let depth = depth.parse::<usize>().map_err(|e| e.to_string())?;
+            (eval, Some(depth))
+        }
+        _ => return Ok(None),
+    };
+    if let Some(nn) = nn_file(eval)? {
+        return Ok(Some(eval_rollout(nn, depth, with)));
+    }
+    Ok(Some(match eval {
+        "diff" => eval_rollout(ScoreDiffEvaluator::new(), depth, with),
+        "pos" => eval_rollout(ScorePosEvaluator::new(), depth, with),
+        "nn4" => eval_rollout(NeuralNet4Evaluator::new(stealing), depth, with),
+        "nn6" => eval_rollout(NeuralNet6Evaluator::new(stealing), depth, with),
+        _ => return Ok(None),
+    }))
+}
+
+/// `args` の `i` 番目から後ろをロールアウトの指定とする。パスに ':' が含まれていてもよいように全部つなげる
+fn rollout_spec(args: &[&str], i: usize) -> String {
+    if args.len() > i {
+        args[i..].join(":")
+    } else {
+        "random".to_string()
+    }
+}
+
+fn rdfs<E>(evaluator: E, max_depth: usize, weight: f64, qdepth: usize) -> Box<dyn Searcher>
+where
+    E: Evaluator + 'static,
+    E::Score: Into<f64>,
+{
+    let mut ai = RandomDepthSearcher::new(max_depth, weight, evaluator, Rng::from_rng(&mut rng()));
+    ai.set_quiescence(qdepth);
+    Box::new(ai)
+}
+
+/// `nn4=(path)` か `nn6=(path)` なら、形を確かめてファイルからモデルを読む
+fn nn_file(spec: &str) -> Result<Option<ValueModel>, String> {
+    let model = match spec.split_once('=') {
+        Some(("nn4", path)) => NeuralNet4Evaluator::load(path).map(ValueModel::NN4),
+        Some(("nn6", path)) => NeuralNet6Evaluator::load(path).map(ValueModel::NN6),
+        _ => return Ok(None),
+    };
+    model.map(Some).map_err(|e| format!("{spec}: {e}"))
+}
+
+// 評価関数の後ろにも ':' で区切った引数が続くので、これらの (path) には ':' を使えない
+const HUMAN_USAGE: &str = "human[:(diff|pos|nn4|nn6|(nn4|nn6)=(path)|mc-(num)):(max_depth)[:live]], (path) must not contain ':'";
+const DFS_USAGE: &str =
+    "dfs:(diff|pos|nn4|nn6|(nn4|nn6)=(path)):(max_depth)[:(qdepth)], (path) must not contain ':'";
+const PDFS_USAGE: &str = "pdfs:(diff|pos|nn4|nn6|(nn4|nn6)=(path)):(max_depth):(threads)[:(qdepth)], (path) must not contain ':'";
+const RDFS_USAGE: &str = "rdfs:(diff|pos|nn4|nn6|(nn4|nn6)=(path)):(max_depth):(weight)[:(qdepth)], (path) must not contain ':'";
+
 pub fn build_ai(stealing: bool, s: &str) -> Result<Box<dyn Searcher>, String> {
     let args = s.split(':').collect::<Vec<_>>();
     match args[0] {
@@ -41,25 +270,27 @@ pub fn build_ai(stealing: bool, s: &str) -> Result<Box<dyn Searcher>, String> {
             if args.len() == 1 {
                 return Ok(Box::new(Interactive::new(ScoreDiffEvaluator::new(), 0)));
             }
-            if args.len() != 3 {
-                return Err("human[:(eval):(max_depth)]".to_string());
+            if args.len() != 3 && args.len() != 4 {
+                return Err(HUMAN_USAGE.to_string());
             }
+            let live_hint = match args.get(3) {
+                None => false,
+                Some(&"live") => true,
+                _ => return Err(HUMAN_USAGE.to_string()),
+            };
             let max_depth = match args[2].parse() {
                 Ok(d) => d,
-                Err(e) => return Err(format!("human[:(eval):(max_depth)] {e}")),
+                Err(e) => return Err(format!("{HUMAN_USAGE} {e}")),
             };
+            if let Some(nn) = nn_file(args[1])? {
+                return Ok(human(nn, max_depth, live_hint));
+            }
             let eval_args = args[1].split('-').collect::<Vec<_>>();
             Ok(match eval_args[0] {
-                "diff" => Box::new(Interactive::new(ScoreDiffEvaluator::new(), max_depth)),
-                "pos" => Box::new(Interactive::new(ScorePosEvaluator::new(), max_depth)),
-                "nn4" => Box::new(Interactive::new(
-                    NeuralNet4Evaluator::new(stealing),
-                    max_depth,
-                )),
-                "nn6" => Box::new(Interactive::new(
-                    NeuralNet6Evaluator::new(stealing),
-                    max_depth,
-                )),
+                "diff" => human(ScoreDiffEvaluator::new(), max_depth, live_hint),
+                "pos" => human(ScorePosEvaluator::new(), max_depth, live_hint),
+                "nn4" => human(NeuralNet4Evaluator::new(stealing), max_depth, live_hint),
+                "nn6" => human(NeuralNet6Evaluator::new(stealing), max_depth, live_hint),
                 "mc" => {
                     if eval_args.len() != 2 {
                         return Err("human:mc-(num):(max_depth)".to_string());
@@ -68,14 +299,13 @@ pub fn build_ai(stealing: bool, s: &str) -> Result<Box<dyn Searcher>, String> {
                         Ok(d) => d,
                         Err(e) => return Err(format!("human:mc-(num):(max_depth) {e}")),
                     };
-                    Box::new(Interactive::new(
+                    human(
                         McTreeEvaluator::new(Rng::from_rng(&mut rng()), num),
                         max_depth,
-                    ))
-                }
-                _ => {
-                    return Err("human[:(diff|pos|nn4|nn6|mc-(num)):(max_depth)]".to_string());
+                        live_hint,
+                    )
                 }
+                _ => return Err(HUMAN_USAGE.to_string()),
             })
         }
         "random" => {
@@ -85,33 +315,71 @@ pub fn build_ai(stealing: bool, s: &str) -> Result<Box<dyn Searcher>, String> {
             Ok(Box::new(RandomSearcher::new(Rng::from_rng(&mut rng()))))
         }
         "dfs" => {
-            if args.len() != 3 {
-                return Err("dfs:(eval):(max_depth)".to_string());
+            if args.len() != 3 && args.len() != 4 {
+                return Err(DFS_USAGE.to_string());
             }
             let max_depth = match args[2].parse() {
                 Ok(d) => d,
-                Err(e) => return Err(format!("dfs:(eval):(max_depth) {e}")),
+                Err(e) => return Err(format!("{DFS_USAGE} {e}")),
             };
+            let qdepth = match args.get(3).map(|s| s.parse()) {
+                None => 0,
+                Some(Ok(d)) => d,
+                Some(Err(e)) => return Err(format!("qdepth must be usize: {e}")),
+            };
+            if let Some(nn) = nn_file(args[1])? {
+                return Ok(dfs(nn, max_depth, qdepth));
+            }
             let eval_args = args[1].split('-').collect::<Vec<_>>();
             Ok(match eval_args[0] {
-                "diff" => Box::new(DepthSearcher::new(ScoreDiffEvaluator::new(), max_depth)),
-                "pos" => Box::new(DepthSearcher::new(ScorePosEvaluator::new(), max_depth)),
-                "nn4" => Box::new(DepthSearcher::new(
+                "diff" => dfs(ScoreDiffEvaluator::new(), max_depth, qdepth),
+                "pos" => dfs(ScorePosEvaluator::new(), max_depth, qdepth),
+                "nn4" => dfs(NeuralNet4Evaluator::new(stealing), max_depth, qdepth),
+                "nn6" => dfs(NeuralNet6Evaluator::new(stealing), max_depth, qdepth),
+                _ => return Err(DFS_USAGE.to_string()),
+            })
+        }
+        "pdfs" => {
+            if args.len() != 4 && args.len() != 5 {
+                return Err(PDFS_USAGE.to_string());
+            }
+            let max_depth = match args[2].parse() {
+                Ok(d) => d,
+                Err(e) => return Err(format!("max_depth must be usize: {e}")),
+            };
+            let threads = match args[3].parse() {
+                Ok(t) => t,
+                Err(e) => return Err(format!("threads must be usize: {e}")),
+            };
+            let qdepth = match args.get(4).map(|s| s.parse()) {
+                None => 0,
+                Some(Ok(d)) => d,
+                Some(Err(e)) => return Err(format!("qdepth must be usize: {e}")),
+            };
+            if let Some(nn) = nn_file(args[1])? {
+                return Ok(pdfs(nn, max_depth, threads, qdepth));
+            }
+            Ok(match args[1] {
+                "diff" => pdfs(ScoreDiffEvaluator::new(), max_depth, threads, qdepth),
+                "pos" => pdfs(ScorePosEvaluator::new(), max_depth, threads, qdepth),
+                "nn4" => pdfs(
                     NeuralNet4Evaluator::new(stealing),
                     max_depth,
-                )),
-                "nn6" => Box::new(DepthSearcher::new(
+                    threads,
+                    qdepth,
+                ),
+                "nn6" => pdfs(
                     NeuralNet6Evaluator::new(stealing),
                     max_depth,
-                )),
-                _ => {
-                    return Err("dfs:(diff|pos|nn4|nn6|mc-(num)):(max_depth)".to_string());
-                }
+                    threads,
+                    qdepth,
+                ),
+                _ => return Err(PDFS_USAGE.to_string()),
             })
         }
         "rdfs" => {
-            if args.len() != 4 {
-                return Err("rdfs:(eval):(max_depth):(weight)".to_string());
+            if args.len() != 4 && args.len() != 5 {
+                return Err(RDFS_USAGE.to_string());
             }
             let max_depth = match args[2].parse() {
                 Ok(d) => d,
@@ -121,50 +389,158 @@ pub fn build_ai(stealing: bool, s: &str) -> Result<Box<dyn Searcher>, String> {
                 Ok(w) => w,
                 Err(e) => return Err(format!("weight must be f64: {e}")),
             };
-            let random = Rng::from_rng(&mut rng());
+            let qdepth = match args.get(4).map(|s| s.parse()) {
+                None => 0,
+                Some(Ok(d)) => d,
+                Some(Err(e)) => return Err(format!("qdepth must be usize: {e}")),
+            };
+            if let Some(nn) = nn_file(args[1])? {
+                return Ok(rdfs(nn, max_depth, weight, qdepth));
+            }
             Ok(match args[1] {
-                "diff" => Box::new(RandomDepthSearcher::new(
-                    max_depth,
-                    weight,
-                    ScoreDiffEvaluator::new(),
-                    random,
-                )),
-                "pos" => Box::new(RandomDepthSearcher::new(
-                    max_depth,
-                    weight,
-                    ScorePosEvaluator::new(),
-                    random,
-                )),
-                "nn4" => Box::new(RandomDepthSearcher::new(
-                    max_depth,
-                    weight,
+                "diff" => rdfs(ScoreDiffEvaluator::new(), max_depth, weight, qdepth),
+                "pos" => rdfs(ScorePosEvaluator::new(), max_depth, weight, qdepth),
+                "nn4" => rdfs(
                     NeuralNet4Evaluator::new(stealing),
-                    random,
-                )),
-                "nn6" => Box::new(RandomDepthSearcher::new(
                     max_depth,
                     weight,
+                    qdepth,
+                ),
+                "nn6" => rdfs(
                     NeuralNet6Evaluator::new(stealing),
-                    random,
-                )),
-                _ => {
-                    return Err("rdfs:(diff|pos|nn4|nn6):(max_depth):(weight)".to_string());
-                }
+                    max_depth,
+                    weight,
+                    qdepth,
+                ),
+                _ => return Err(RDFS_USAGE.to_string()),
             })
         }
         "mctree" => {
-            if args.len() != 4 {
-                return Err("mctree:{limit}:{ex}:{c}".to_owned());
+            let usage = format!(
+                "mctree:{{limit}}:{{ex}}:{{c}}[:(win|margin)[:(rollout)]], {ROLLOUT_USAGE}"
+            );
+            if args.len() < 4 {
+                return Err(usage);
             }
             let limit = args[1].parse::<u64>().map_err(|e| e.to_string())?;
             let ex = args[2].parse::<u32>().map_err(|e| e.to_string())?;
-            let c = args[2].parse::<f64>().map_err(|e| e.to_string())?;
-            Ok(Box::new(McTreeSearcher::new(
-                Rng::from_rng(&mut rng()),
+            let c = args[3].parse::<f64>().map_err(|e| e.to_string())?;
+            let backup = match args.get(4) {
+                None | Some(&"win") => Backup::WinLoss,
+                Some(&"margin") => Backup::Margin,
+                _ => return Err(usage),
+            };
+            let tree = McTreeArgs {
                 limit,
                 ex,
                 c,
-            )))
+                backup,
+            };
+            with_rollout(stealing, &rollout_spec(&args, 5), tree)?.ok_or(usage)
+        }
+        "mcgraph" => {
+            let usage = format!(
+                "mcgraph:{{limit}}:{{ex}}:{{c}}[:(win|margin)[:(rollout)]], {ROLLOUT_USAGE}"
+            );
+            if args.len() < 4 {
+                return Err(usage);
+            }
+            let limit = args[1].parse::<u64>().map_err(|e| e.to_string())?;
+            let ex = args[2].parse::<u32>().map_err(|e| e.to_string())?;
+            let c = args[3].parse::<f64>().map_err(|e| e.to_string())?;
+            let backup = match args.get(4) {
+                None | Some(&"win") => Backup::WinLoss,
+                Some(&"margin") => Backup::Margin,
+                _ => return Err(usage),
+            };
+            let graph = McGraphArgs(McTreeArgs {
+                limit,
+                ex,
+                c,
+                backup,
+            });
+            with_rollout(stealing, &rollout_spec(&args, 5), graph)?.ok_or(usage)
+        }
+        "pmctree" => {
+            let usage = format!(
+                "pmctree:{{limit}}:{{ex}}:{{c}}:{{threads}}:(root|tree)[:(win|margin)[:(rollout)]], {ROLLOUT_USAGE}"
+            );
+            if args.len() < 6 {
+                return Err(usage);
+            }
+            let limit = args[1].parse::<u64>().map_err(|e| e.to_string())?;
+            let ex = args[2].parse::<u32>().map_err(|e| e.to_string())?;
+            let c = args[3].parse::<f64>().map_err(|e| e.to_string())?;
+            let threads = args[4].parse::<usize>().map_err(|e| e.to_string())?;
+            let parallelism = match args[5] {
+                "root" => Parallelism::Root,
+                "tree" => Parallelism::Tree,
+                _ => return Err(usage),
+            };
+            let backup = match args.get(6) {
+                None | Some(&"win") => Backup::WinLoss,
+                Some(&"margin") => Backup::Margin,
+                _ => return Err(usage),
+            };
+            let parallel = ParallelMcTreeArgs {
+                tree: McTreeArgs {
+                    limit,
+                    ex,
+                    c,
+                    backup,
+                },
+                threads,
+                parallelism,
+            };
+            with_rollout(stealing, &rollout_spec(&args, 7), parallel)?.ok_or(usage)
+        }
+        "puct" => {
+            const USAGE: &str = "puct:{limit}:{c}:(diff|pos|nn4|nn6|(nn4|nn6|model)=(path))";
+            if args.len() < 4 {
+                return Err(USAGE.to_owned());
+            }
+            let limit = args[1].parse::<u64>().map_err(|e| e.to_string())?;
+            let c = args[2].parse::<f64>().map_err(|e| e.to_string())?;
+            let uniform = UniformPolicy::new();
+            // パスに ':' が含まれていてもよいように残りを全部つなげる
+            if let Some(nn) = nn_file(&args[3..].join(":"))? {
+                return Ok(Box::new(PuctSearcher::new(nn, uniform, limit, c)));
+            }
+            Ok(match args[3] {
+                "diff" => Box::new(PuctSearcher::new(
+                    ScoreDiffEvaluator::new(),
+                    uniform,
+                    limit,
+                    c,
+                )),
+                "pos" => Box::new(PuctSearcher::new(
+                    ScorePosEvaluator::new(),
+                    uniform,
+                    limit,
+                    c,
+                )),
+                "nn4" => Box::new(PuctSearcher::new(
+                    NeuralNet4Evaluator::new(stealing),
+                    uniform,
+                    limit,
+                    c,
+                )),
+                "nn6" => Box::new(PuctSearcher::new(
+                    NeuralNet6Evaluator::new(stealing),
+                    uniform,
+                    limit,
+                    c,
+                )),
+                model if model.starts_with("model=") => {
+                    // パスに ':' が含まれていてもよいように残りを全部つなげる
+                    let path = args[3..].join(":");
+                    let model = PolicyValueModel::load(&path["model=".len()..])
+                        .map_err(|e| format!("{USAGE} {e}"))?;
+                    let (value, policy) = model.split().map_err(|e| format!("{USAGE} {e}"))?;
+                    Box::new(PuctSearcher::new(value, policy, limit, c))
+                }
+                _ => return Err(USAGE.to_owned()),
+            })
         }
         "greedy" => {
             if args.len() != 1 {
@@ -175,6 +551,21 @@ pub fn build_ai(stealing: bool, s: &str) -> Result<Box<dyn Searcher>, String> {
                 Rng::from_rng(&mut rng()),
             )))
         }
-        _ => Err("(human|random|dfs|rdfs|mctree|weighted|greedy)".to_string()),
+        "perfect" => {
+            // パスに ':' が含まれていてもよいように残りを全部つなげる
+            let path = if args.len() == 1 {
+                crate::learn::db_name(stealing)
+            } else {
+                args[1..].join(":")
+            };
+            match PerfectSearcher::load(&path, stealing) {
+                Ok(ai) => Ok(Box::new(ai)),
+                Err(e) => Err(format!("perfect[:(path)] {path}: {e}")),
+            }
+        }
+        _ => Err(
+            "(human|random|dfs|pdfs|rdfs|mctree|mcgraph|pmctree|puct|weighted|greedy|perfect)"
+                .to_string(),
+        ),
     }
 }
@@ -0,0 +1,414 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread,
+};
+
+use instant::Instant;
+
+use super::{
+    Evaluator, Score, Searcher,
+    result::SearchResult,
+    utils::{Ordable, position_key, quiescence_search},
+};
+use crate::board::Board;
+
+const TABLE_BITS: u32 = 18;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Entry<S> {
+    key: u64,
+    depth: usize,
+    bound: Bound,
+    score: S,
+    /// 最善手で進んだ後の局面のキー。全ての手が `alpha` 以下だったなら `None`
+    best: Option<u64>,
+}
+
+/// スレッド間で共有する置換表
+/// 同じ深さ以上で探索した結果を使うので、他のスレッドが深く読んだ結果もそのまま使える
+pub struct TranspositionTable<S> {
+    entries: Vec<Mutex<Option<Entry<S>>>>,
+    mask: u64,
+}
+
+impl<S: Score> TranspositionTable<S> {
+    pub fn new(bits: u32) -> TranspositionTable<S> {
+        let size = 1 << bits;
+        let mut entries = Vec::with_capacity(size);
+        entries.resize_with(size, || Mutex::new(None));
+        TranspositionTable {
+            entries,
+            mask: size as u64 - 1,
+        }
+    }
+
+    pub fn clear(&self) {
+        for entry in self.entries.iter() {
+            *entry.lock().unwrap() = None;
+        }
+    }
+
+    fn probe(&self, key: u64, depth: usize) -> Option<(Bound, S)> {
+        match *self.entries[(key & self.mask) as usize].lock().unwrap() {
+            Some(e) if e.key == key && e.depth >= depth => Some((e.bound, e.score)),
+            _ => None,
+        }
+    }
+
+    /// 読み筋をたどるために、深さによらず最善手の後の局面のキーを返す
+    fn best(&self, key: u64) -> Option<u64> {
+        match *self.entries[(key & self.mask) as usize].lock().unwrap() {
+            Some(e) if e.key == key => e.best,
+            _ => None,
+        }
+    }
+
+    /// `board` から置換表の最善手をたどって読み筋を作る
+    /// 置換表のエントリーが上書きされていたらそこで打ち切る
+    fn pv(&self, board: &Board, max_len: usize) -> Vec<Vec<usize>> {
+        let mut pv = Vec::new();
+        let mut board = board.clone();
+        while pv.len() < max_len {
+            let Some(best) = self.best(position_key(&board)) else {
+                break;
+            };
+            let Some((next, pos_list)) = board
+                .list_next_with_pos()
+                .into_iter()
+                .find(|(next, _)| position_key(next) == best)
+            else {
+                break;
+            };
+            pv.push(pos_list);
+            board = next;
+        }
+        pv
+    }
+
+    fn store(&self, key: u64, depth: usize, bound: Bound, score: S, best: Option<u64>) {
+        let mut entry = self.entries[(key & self.mask) as usize].lock().unwrap();
+        match *entry {
+            // 同じ局面なら深く読んだ方を残す
+            Some(e) if e.key == key && e.depth > depth => (),
+            _ => {
+                *entry = Some(Entry {
+                    key,
+                    depth,
+                    bound,
+                    score,
+                    best,
+                })
+            }
+        }
+    }
+}
+
+/// 初手を一通り読んだ結果
+struct RootResult<S> {
+    score: S,
+    /// 最善の初手の番号
+    best: Option<usize>,
+    /// 初手ごとの評価値
+    scores: Vec<S>,
+}
+
+struct Worker<'a, E: Evaluator> {
+    eval: E,
+    table: &'a TranspositionTable<E::Score>,
+    qdepth: usize,
+    nodes: u64,
+    /// 補助のスレッドだけが持つ。立ったら読むのをやめ、置換表にも書かない
+    stop: Option<&'a AtomicBool>,
+}
+
+impl<E: Evaluator> Worker<'_, E> {
+    fn stopped(&self) -> bool {
+        self.stop.is_some_and(|stop| stop.load(Ordering::Relaxed))
+    }
+
+    fn search(&mut self, board: Board, depth: usize, alpha: E::Score, beta: E::Score) -> E::Score {
+        self.nodes += 1;
+        if let Some(s) = self.eval.exact(&board) {
+            return s;
+        }
+        if depth == 0 || board.is_finished() {
+            return quiescence_search(board, &mut self.eval, self.qdepth, alpha, beta);
+        }
+        let key = position_key(&board);
+        match self.table.probe(key, depth) {
+            Some((Bound::Exact, s)) => return s,
+            Some((Bound::Lower, s)) if s >= beta => return s,
+            Some((Bound::Upper, s)) if s <= alpha => return alpha,
+            _ => (),
+        }
+        let mut list = board.list_next().drain().collect::<Vec<_>>();
+        if depth >= 3 {
+            list.sort_by_cached_key(|b| Ordable(self.eval.eval(b)));
+        }
+        // 浅く読んだときやほかのスレッドが読んだときの最善手から読む
+        if let Some(best) = self.table.best(key)
+            && let Some(i) = list.iter().position(|next| position_key(next) == best)
+        {
+            list[..=i].rotate_right(1);
+        }
+        let mut alpha = alpha;
+        let mut bound = Bound::Upper;
+        let mut best = None;
+        for next in list {
+            if self.stopped() {
+                return alpha;
+            }
+            let next_key = position_key(&next);
+            let a = self
+                .search(next, depth - 1, beta.flip(), alpha.flip())
+                .flip();
+            if a > alpha {
+                alpha = a;
+                bound = Bound::Exact;
+                best = Some(next_key);
+            }
+            if alpha >= beta {
+                bound = Bound::Lower;
+                break;
+            }
+        }
+        if !self.stopped() {
+            self.table.store(key, depth, bound, alpha, best);
+        }
+        alpha
+    }
+
+    /// 初手を `order` の順に読む。最善手以外の評価値は上界になる
+    /// 打ち切られたら `None`
+    fn search_root(
+        &mut self,
+        next_list: &[(Board, Vec<usize>)],
+        order: impl Iterator<Item = usize>,
+        depth: usize,
+    ) -> Option<RootResult<E::Score>> {
+        let mut best = (E::Score::MIN, None);
+        let mut scores = vec![E::Score::MIN; next_list.len()];
+        for i in order {
+            let s = self
+                .search(next_list[i].0.clone(), depth, E::Score::MIN, best.0.flip())
+                .flip();
+            if self.stopped() {
+                return None;
+            }
+            scores[i] = s;
+            if best.1.is_none() || s > best.0 {
+                best = (s, Some(i));
+            }
+        }
+        Some(RootResult {
+            score: best.0,
+            best: best.1,
+            scores,
+        })
+    }
+}
+
+/// 置換表を共有したスレッドがそれぞれ反復深化で全体を読む (Lazy SMP)
+///
+/// 補助のスレッドは初手の順番と読み始める深さをずらして読み、結果を置換表に残す。
+/// 主のスレッドはそれを使って早く読み終え、主のスレッドが読み終えたら補助のスレッドも止める。
+/// 置換表は手番をまたいでも使い回す
+pub struct ParallelDepthSearcher<E: Evaluator> {
+    max_depth: usize,
+    qdepth: usize,
+    threads: usize,
+    evaluator: E,
+    table: TranspositionTable<E::Score>,
+}
+
+impl<E: Evaluator> ParallelDepthSearcher<E> {
+    pub fn new(evaluator: E, max_depth: usize, threads: usize) -> ParallelDepthSearcher<E> {
+        ParallelDepthSearcher {
+            max_depth,
+            qdepth: 0,
+            threads: threads.max(1),
+            evaluator,
+            table: TranspositionTable::new(TABLE_BITS),
+        }
+    }
+
+    /// 末端で横取りが絡むターンを最大 `qdepth` ターン延長して読む。0 なら延長しない
+    pub fn set_quiescence(&mut self, qdepth: usize) {
+        self.qdepth = qdepth;
+        self.table.clear();
+    }
+}
+
+impl<E> ParallelDepthSearcher<E>
+where
+    E: Evaluator + Clone + Send,
+    E::Score: Send,
+{
+    /// 結果は主のスレッドが打ち手の辞書順に初手を読んだもの
+    /// 置換表に深く読んだ結果があればそれを使うので、`DepthSearcher` より深く読んだ評価値になることがある。
+    /// 最善手以外の初手の評価値は上界であることに注意
+    pub fn search(&mut self, board: &Board) -> SearchResult<E::Score> {
+        let start = Instant::now();
+        let mut next_list = board.list_next_with_pos().drain().collect::<Vec<_>>();
+        next_list.sort_by(|a, b| a.1.cmp(&b.1));
+
+        let max_depth = self.max_depth;
+        let n = next_list.len();
+        let stop = AtomicBool::new(false);
+        let nodes = AtomicU64::new(1);
+        let result = thread::scope(|scope| {
+            for id in 1..self.threads {
+                let mut worker = Worker {
+                    eval: self.evaluator.clone(),
+                    table: &self.table,
+                    qdepth: self.qdepth,
+                    nodes: 0,
+                    stop: Some(&stop),
+                };
+                let (next_list, nodes) = (&next_list, &nodes);
+                scope.spawn(move || {
+                    // 奇数番目のスレッドは 1 つ深いところから読み始める
+                    for depth in (1 + id % 2)..=max_depth {
+                        let order = (0..n).map(|i| (i + id) % n);
+                        if worker.search_root(next_list, order, depth).is_none() {
+                            break;
+                        }
+                    }
+                    nodes.fetch_add(worker.nodes, Ordering::Relaxed);
+                });
+            }
+            let mut worker = Worker {
+                eval: self.evaluator.clone(),
+                table: &self.table,
+                qdepth: self.qdepth,
+                nodes: 0,
+                stop: None,
+            };
+            let mut result = None;
+            for depth in max_depth.min(1)..=max_depth {
+                result = worker.search_root(&next_list, 0..n, depth);
+            }
+            stop.store(true, Ordering::Relaxed);
+            nodes.fetch_add(worker.nodes, Ordering::Relaxed);
+            result.unwrap()
+        });
+
+        let RootResult {
+            score,
+            best,
+            scores,
+        } = result;
+        let (score, best, pv) = match best {
+            Some(i) => {
+                let (next, pos_list) = &next_list[i];
+                let mut pv = vec![pos_list.clone()];
+                pv.extend(self.table.pv(next, self.max_depth));
+                (score, pos_list.clone(), pv)
+            }
+            None => (self.evaluator.eval(board), Vec::new(), Vec::new()),
+        };
+        let root_scores = next_list
+            .into_iter()
+            .map(|(_, pos_list)| pos_list)
+            .zip(scores)
+            .collect();
+        SearchResult {
+            pv,
+            best,
+            score,
+            depth: self.max_depth,
+            nodes: nodes.into_inner(),
+            elapsed: start.elapsed(),
+            root_scores,
+        }
+    }
+}
+
+impl<E> Searcher for ParallelDepthSearcher<E>
+where
+    E: Evaluator + Clone + Send,
+    E::Score: Send,
+{
+    fn sow(&mut self, board: &Board) -> Vec<usize> {
+        let mut next_lists = board.list_next_with_pos();
+        if next_lists.len() == 1 {
+            return next_lists.drain().next().unwrap().1;
+        }
+        self.search(board).best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::learn::test_utils::endgame;
+    use crate::{DepthSearcher, ScoreDiffEvaluator};
+
+    #[test]
+    fn same_score_as_depth_searcher() {
+        // 終局まで読み切れる深さなら、置換表に深い結果があっても評価値は変わらない
+        let board = endgame(true);
+        let expected = DepthSearcher::new(ScoreDiffEvaluator::new(), 30)
+            .search(&board)
+            .score;
+        for threads in [1, 4] {
+            let mut ai = ParallelDepthSearcher::new(ScoreDiffEvaluator::new(), 30, threads);
+            assert_eq!(ai.search(&board).score, expected);
+            // 置換表が埋まった状態でも同じ
+            assert_eq!(ai.search(&board).score, expected);
+        }
+    }
+
+    #[test]
+    fn same_score_with_one_thread() {
+        // 1 スレッドで置換表が空なら、置換表に入るのは今の反復の結果だけ
+        let mut board = Board::new(true);
+        board.sow(2);
+        board.sow(5);
+        let expected = DepthSearcher::new(ScoreDiffEvaluator::new(), 4)
+            .search(&board)
+            .score;
+        let mut ai = ParallelDepthSearcher::new(ScoreDiffEvaluator::new(), 4, 1);
+        assert_eq!(ai.search(&board).score, expected);
+    }
+
+    #[test]
+    fn pv_from_table() {
+        let board = Board::new(true);
+        for threads in [1, 4] {
+            let result =
+                ParallelDepthSearcher::new(ScoreDiffEvaluator::new(), 4, threads).search(&board);
+            assert_eq!(result.pv[0], result.best);
+            assert!(result.pv.len() > 1);
+            // 読み筋はどれも合法手
+            let mut b = board.clone();
+            for pos_list in result.pv.iter() {
+                let side = b.side();
+                for &pos in pos_list {
+                    assert_eq!(b.side(), side);
+                    b.sow(pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_with_one_thread() {
+        let board = Board::new(false);
+        let a = ParallelDepthSearcher::new(ScoreDiffEvaluator::new(), 4, 1).search(&board);
+        let b = ParallelDepthSearcher::new(ScoreDiffEvaluator::new(), 4, 1).search(&board);
+        assert_eq!(a.best, b.best);
+        assert_eq!(a.nodes, b.nodes);
+        assert_eq!(a.root_scores, b.root_scores);
+    }
+}
@@ -0,0 +1,121 @@
+use std::cmp::Reverse;
+
+use super::{Searcher, result::search_root};
+use crate::{
+    ScoreDiffEvaluator,
+    board::Board,
+    learn::{CompressedDb, DbError, Layout, SolveError, Solver, SortedDb, load, read_header},
+};
+
+/// DB にない局面を 1 手で読み切るときのノード数の上限
+const SOLVE_NODES: u64 = 1 << 22;
+/// 読み切れなかったときに代わりに読む深さ
+const FALLBACK_DEPTH: usize = 6;
+
+/// 読み切った DB を引いて最善手を打つ
+///
+/// DB にない局面はその場で読み切る。同じ得点差になる手の中では、勝っていれば早く終わる手、
+/// 負けていれば長引かせる手を選ぶ。
+/// 読み切れない局面で止まらないように、`load` したものは 1 局面あたり `SOLVE_NODES` ノードまでしか読まず、
+/// 読み切れなければ得点差で `FALLBACK_DEPTH` ターン読んで打つ
+pub struct PerfectSearcher {
+    solver: Solver,
+}
+
+impl PerfectSearcher {
+    pub fn new(solver: Solver) -> PerfectSearcher {
+        PerfectSearcher { solver }
+    }
+
+    /// ソートした DB や圧縮した DB ならメモリーマップして引き、そうでなければ全部読み込む
+    pub fn load(path: &str, stealing: bool) -> Result<PerfectSearcher, DbError> {
+        let mut solver = match read_header(path)?.layout {
+            Layout::Plain => Solver::new(load(path, stealing)?),
+            Layout::Sorted => Solver::with_base(SortedDb::open(path, stealing)?),
+            Layout::Compressed => Solver::with_base(CompressedDb::open(path, stealing)?),
+        };
+        solver.set_budget(Some(SOLVE_NODES), None);
+        Ok(PerfectSearcher::new(solver))
+    }
+
+    /// 手番側から見た最終的な得点差と、終局までのターン数
+    pub fn value(&mut self, board: &Board) -> Result<(i8, u8), SolveError> {
+        self.solver.solve(board)
+    }
+}
+
+impl Searcher for PerfectSearcher {
+    fn sow(&mut self, board: &Board) -> Vec<usize> {
+        let mut next_lists = board.list_next_with_pos().into_iter().collect::<Vec<_>>();
+        // 同じ評価の手の選び方を決めておく
+        next_lists.sort_by(|a, b| a.1.cmp(&b.1));
+        let values = next_lists
+            .into_iter()
+            .map(|(next, pos_list)| {
+                let (s, d) = self.value(&next)?;
+                let score = -s;
+                let depth = i32::from(d) + 1;
+                let speed = if score < 0 { depth } else { -depth };
+                Ok(((score, speed), Reverse(pos_list)))
+            })
+            .collect::<Result<Vec<_>, SolveError>>();
+        match values {
+            Ok(values) => values.into_iter().max().unwrap().1.0,
+            Err(_) => {
+                let mut eval = ScoreDiffEvaluator::new();
+                search_root(board, &mut eval, FALLBACK_DEPTH, 0, false).best
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fnv::FnvHashMap;
+
+    use super::*;
+    use crate::PIT;
+    use crate::learn::test_utils::{endgame, temp_path};
+
+    #[test]
+    fn fastest_win() {
+        // どちらの手でも 1 点勝ちだが、3 番の穴から打つ方が早く終わる
+        let mut seeds = [0; PIT * 2];
+        seeds[3] = 1;
+        seeds[4] = 1;
+        seeds[PIT + 4] = 1;
+        let board = Board::from_seeds(true, &seeds);
+        let mut ai = PerfectSearcher::new(Solver::new(FnvHashMap::default()));
+        assert_eq!(ai.value(&board).unwrap(), (1, 3));
+        assert_eq!(ai.sow(&board), vec![3]);
+    }
+
+    #[test]
+    fn load_sorted_db() {
+        let board = endgame(false);
+        let mut solver = Solver::new(FnvHashMap::default());
+        let value = solver.solve(&board).unwrap();
+        let path = temp_path("perfect.dat");
+        let path = path.as_str();
+        crate::learn::save_sorted(path, false, solver.data()).unwrap();
+
+        let mut ai = PerfectSearcher::load(path, false).unwrap();
+        assert_eq!(ai.value(&board).unwrap(), value);
+        // DB から引けたので何も読んでいない
+        assert!(ai.solver.data().is_empty());
+        assert!(PerfectSearcher::load(path, true).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fallback_over_budget() {
+        let board = Board::new(true);
+        let mut solver = Solver::new(FnvHashMap::default());
+        solver.set_budget(Some(1000), None);
+        let mut ai = PerfectSearcher::new(solver);
+        assert!(matches!(ai.value(&board), Err(SolveError::BudgetExceeded)));
+        // 読み切れなくても止まらずに打てる
+        let pos_list = ai.sow(&board);
+        assert!(board.list_next_with_pos().values().any(|p| *p == pos_list));
+    }
+}
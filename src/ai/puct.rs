@@ -0,0 +1,470 @@
+use std::io::{self, Read, Write};
+
+use instant::{Duration, Instant};
+
+use super::{
+    Evaluator, NeuralNet4Evaluator, NeuralNet6Evaluator, Searcher,
+    mctree::{Backup, Node},
+    utils::soft_max,
+};
+use crate::board::{Board, PIT};
+
+/// 方策。手番側がどの穴から蒔くかの確率を返す
+pub trait Policy {
+    /// 石のない穴の確率は 0 で、合計は 1 になるようにする
+    fn policy(&mut self, board: &Board) -> [f64; PIT];
+}
+
+/// 石のある穴を等確率で選ぶ。方策のモデルがない場合に使う
+#[derive(Debug, Clone, Default)]
+pub struct UniformPolicy;
+
+impl UniformPolicy {
+    pub fn new() -> UniformPolicy {
+        UniformPolicy
+    }
+}
+
+impl Policy for UniformPolicy {
+    fn policy(&mut self, board: &Board) -> [f64; PIT] {
+        let mut ret = [0.0; PIT];
+        let n = board.self_seeds().iter().filter(|s| **s > 0).count();
+        for (p, &s) in ret.iter_mut().zip(board.self_seeds().iter()) {
+            if s > 0 {
+                *p = 1.0 / n as f64;
+            }
+        }
+        ret
+    }
+}
+
+/// 盤面の石の数から線形に求めた値の softmax を方策とする
+#[derive(Debug, Clone)]
+pub struct LinearPolicy {
+    /// `weights[pit * PIT * 2 + i]` が入力 `i` の重み
+    weights: Vec<f64>,
+    bias: Vec<f64>,
+}
+
+impl LinearPolicy {
+    pub fn new(weights: Vec<f64>, bias: Vec<f64>) -> LinearPolicy {
+        assert_eq!(weights.len(), PIT * PIT * 2);
+        assert_eq!(bias.len(), PIT);
+        LinearPolicy { weights, bias }
+    }
+}
+
+impl Policy for LinearPolicy {
+    fn policy(&mut self, board: &Board) -> [f64; PIT] {
+        let input = board
+            .self_seeds()
+            .iter()
+            .chain(board.opposite_seed().iter())
+            .map(|s| f64::from(*s))
+            .collect::<Vec<_>>();
+        let mut logits = Vec::with_capacity(PIT);
+        let mut legal = Vec::with_capacity(PIT);
+        for (pit, &s) in board.self_seeds().iter().enumerate() {
+            if s == 0 {
+                continue;
+            }
+            let w = &self.weights[pit * PIT * 2..(pit + 1) * PIT * 2];
+            let x = w.iter().zip(input.iter()).map(|(w, x)| w * x).sum::<f64>();
+            logits.push(x + self.bias[pit]);
+            legal.push(pit);
+        }
+        soft_max(&mut logits);
+        let sum = logits.iter().sum::<f64>();
+        let mut ret = [0.0; PIT];
+        for (pit, p) in legal.into_iter().zip(logits) {
+            ret[pit] = p / sum;
+        }
+        ret
+    }
+}
+
+/// 価値のモデル
+#[derive(Debug, Clone)]
+pub enum ValueModel {
+    NN4(NeuralNet4Evaluator),
+    NN6(NeuralNet6Evaluator),
+}
+
+impl Evaluator for ValueModel {
+    type Score = rust_nn::Float;
+    fn eval(&mut self, board: &Board) -> Self::Score {
+        match self {
+            ValueModel::NN4(e) => e.eval(board),
+            ValueModel::NN6(e) => e.eval(board),
+        }
+    }
+}
+
+/// 方策も持っていれば使い、なければ一様な方策にする
+#[derive(Debug, Clone)]
+pub enum ModelPolicy {
+    Linear(LinearPolicy),
+    Uniform(UniformPolicy),
+}
+
+impl Policy for ModelPolicy {
+    fn policy(&mut self, board: &Board) -> [f64; PIT] {
+        match self {
+            ModelPolicy::Linear(p) => p.policy(board),
+            ModelPolicy::Uniform(p) => p.policy(board),
+        }
+    }
+}
+
+const MAGIC: &[u8; 4] = b"MCPV";
+const VERSION: u8 = 1;
+
+/// 価値と方策の二つの出力を持つモデル
+///
+/// ファイル形式はリトルエンディアンで
+/// - `MCPV` の 4 バイト、バージョン (u8)、PIT (u8)
+/// - 価値のモデルの種類 (u8, 4 か 6)、方策の有無 (u8, 0 か 1)
+/// - 価値のモデルのバイト数 (u64) と `rust_nn` の形式のモデル
+/// - 方策がある場合は `LinearPolicy` の重み `PIT * PIT * 2` 個とバイアス `PIT` 個 (f64)
+#[derive(Debug, Clone)]
+pub struct PolicyValueModel {
+    kind: u8,
+    value: Vec<u8>,
+    policy: Option<LinearPolicy>,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_f64s<R: Read>(reader: &mut R, n: usize) -> io::Result<Vec<f64>> {
+    let mut ret = Vec::with_capacity(n);
+    for _ in 0..n {
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        ret.push(f64::from_le_bytes(buf));
+    }
+    Ok(ret)
+}
+
+impl PolicyValueModel {
+    /// `kind` は 4 か 6 で、`value` は `rust_nn` の NN4Regression か NN6Regression の形式
+    pub fn new(kind: u8, value: Vec<u8>, policy: Option<LinearPolicy>) -> PolicyValueModel {
+        assert!(kind == 4 || kind == 6);
+        PolicyValueModel {
+            kind,
+            value,
+            policy,
+        }
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<PolicyValueModel> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a policy-value model"));
+        }
+        if read_u8(reader)? != VERSION {
+            return Err(invalid_data("unsupported version"));
+        }
+        if read_u8(reader)? as usize != PIT {
+            return Err(invalid_data("PIT mismatch"));
+        }
+        let kind = read_u8(reader)?;
+        if kind != 4 && kind != 6 {
+            return Err(invalid_data("value model must be NN4 or NN6"));
+        }
+        let has_policy = read_u8(reader)? != 0;
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let len = u64::from_le_bytes(buf);
+        // 長さはファイルの申告なので、そのまま確保せず実際に読めた分だけ使う
+        let mut value = Vec::new();
+        reader.by_ref().take(len).read_to_end(&mut value)?;
+        if value.len() as u64 != len {
+            return Err(invalid_data("value model is truncated"));
+        }
+        let policy = if has_policy {
+            let weights = read_f64s(reader, PIT * PIT * 2)?;
+            let bias = read_f64s(reader, PIT)?;
+            Some(LinearPolicy::new(weights, bias))
+        } else {
+            None
+        };
+        Ok(PolicyValueModel {
+            kind,
+            value,
+            policy,
+        })
+    }
+
+    pub fn load(path: &str) -> io::Result<PolicyValueModel> {
+        let mut f = io::BufReader::new(std::fs::File::open(path)?);
+        PolicyValueModel::read(&mut f)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[
+            VERSION,
+            PIT as u8,
+            self.kind,
+            u8::from(self.policy.is_some()),
+        ])?;
+        writer.write_all(&(self.value.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.value)?;
+        if let Some(policy) = self.policy.as_ref() {
+            for w in policy.weights.iter().chain(policy.bias.iter()) {
+                writer.write_all(&w.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_policy(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// 価値と方策に分ける。価値モデルの形が `kind` と合わなければエラーを返す
+    pub fn split(self) -> io::Result<(ValueModel, ModelPolicy)> {
+        let mut value = self.value.as_slice();
+        let value = if self.kind == 4 {
+            ValueModel::NN4(NeuralNet4Evaluator::read(&mut value)?)
+        } else {
+            ValueModel::NN6(NeuralNet6Evaluator::read(&mut value)?)
+        };
+        let policy = match self.policy {
+            Some(p) => ModelPolicy::Linear(p),
+            None => ModelPolicy::Uniform(UniformPolicy::new()),
+        };
+        Ok((value, policy))
+    }
+}
+
+/// 得点差の見積もりを勝率に直すときのスケール
+const VALUE_SCALE: f64 = 4.0;
+
+/// AlphaZero 風に、方策を事前確率とした PUCT で選び、プレイアウトの代わりに価値で評価する MCTS
+pub struct PuctSearcher<V, P> {
+    value: V,
+    policy: P,
+    limit: Duration,
+    c: f64,
+}
+
+impl<V, P> PuctSearcher<V, P>
+where
+    V: Evaluator,
+    V::Score: Into<f64>,
+    P: Policy,
+{
+    pub fn new(value: V, policy: P, limit: u64, c: f64) -> PuctSearcher<V, P> {
+        PuctSearcher {
+            value,
+            policy,
+            limit: Duration::from_millis(limit),
+            c,
+        }
+    }
+
+    /// 手番側から見た勝率
+    fn evaluate(&mut self, board: &Board) -> f64 {
+        let score: f64 = self.value.eval(board).into();
+        1.0 / (1.0 + (-score / VALUE_SCALE).exp())
+    }
+
+    /// 子を作る。ターンの事前確率は、最初に蒔く穴の確率をその穴から始まるターンで等分したもの
+    fn expand(&mut self, node: &mut Node) {
+        let prob = self.policy.policy(&node.board);
+        let mut next_list = node.board.list_next_with_pos().drain().collect::<Vec<_>>();
+        next_list.sort_by(|a, b| a.1.cmp(&b.1));
+        let mut count = [0; PIT];
+        for (_, pos_list) in next_list.iter() {
+            count[pos_list[0]] += 1;
+        }
+        for (board, pos_list) in next_list {
+            let prior = prob[pos_list[0]] / count[pos_list[0]] as f64;
+            node.children.push(Node::with_prior(board, prior));
+        }
+    }
+
+    fn choice_child(&self, node: &Node) -> usize {
+        let sqrt_total = f64::from(node.visited_count).sqrt();
+        // 未訪問の子は親の平均で見積もる
+        let fpu = if node.visited_count > 0 {
+            node.reward / f64::from(node.visited_count)
+        } else {
+            0.5
+        };
+        let mut best = 0;
+        let mut best_value = f64::NEG_INFINITY;
+        for (i, child) in node.children.iter().enumerate() {
+            let q = if child.visited_count == 0 {
+                fpu
+            } else {
+                1.0 - child.reward / f64::from(child.visited_count)
+            };
+            let u = self.c * child.prior * sqrt_total / f64::from(1 + child.visited_count);
+            if q + u > best_value {
+                best = i;
+                best_value = q + u;
+            }
+        }
+        best
+    }
+
+    /// `node` の手番側から見た報酬を返す
+    fn playout(&mut self, node: &mut Node) -> f64 {
+        node.visited_count += 1;
+        if let Some(reward) = node.proven {
+            node.reward += reward;
+            return reward;
+        }
+        if node.board.is_finished() {
+            let reward = Backup::WinLoss.reward(f64::from(node.board.last_score()));
+            node.proven = Some(reward);
+            node.reward += reward;
+            return reward;
+        }
+        if node.children.is_empty() {
+            self.expand(node);
+            let reward = self.evaluate(&node.board);
+            node.reward += reward;
+            return reward;
+        }
+        let i = self.choice_child(node);
+        let reward = 1.0 - self.playout(&mut node.children[i]);
+        node.reward += reward;
+        reward
+    }
+}
+
+impl<V, P> Searcher for PuctSearcher<V, P>
+where
+    V: Evaluator,
+    V::Score: Into<f64>,
+    P: Policy,
+{
+    fn sow(&mut self, board: &Board) -> Vec<usize> {
+        let start = Instant::now();
+        let next_with_pos = board.list_next_with_pos();
+        if next_with_pos.is_empty() {
+            return Vec::new();
+        }
+        let mut root = Node::new(board.clone());
+        self.playout(&mut root);
+        while start.elapsed() < self.limit {
+            for _ in 0..100 {
+                self.playout(&mut root);
+            }
+        }
+        let best = root
+            .children
+            .iter()
+            .max_by_key(|child| child.visited_count)
+            .unwrap();
+        next_with_pos[&best.board].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoreDiffEvaluator;
+
+    #[test]
+    fn policy_sums_to_one() {
+        let board = Board::from_seeds(true, &[0, 3, 0, 1, 2, 5, 4, 4, 4, 4, 4, 4]);
+        let linear = LinearPolicy::new(
+            (0..PIT * PIT * 2).map(|i| (i % 7) as f64 * 0.1).collect(),
+            vec![0.0; PIT],
+        );
+        for prob in [
+            UniformPolicy::new().policy(&board),
+            ModelPolicy::Linear(linear).policy(&board),
+        ] {
+            assert!((prob.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            for (p, &s) in prob.iter().zip(board.self_seeds().iter()) {
+                assert_eq!(*p == 0.0, s == 0);
+            }
+        }
+    }
+
+    #[test]
+    fn model_roundtrip() {
+        let weights = (0..PIT * PIT * 2).map(|i| i as f64).collect::<Vec<_>>();
+        let model = PolicyValueModel::new(
+            6,
+            vec![1, 2, 3],
+            Some(LinearPolicy::new(weights.clone(), vec![0.5; PIT])),
+        );
+        let mut buf = Vec::new();
+        model.write(&mut buf).unwrap();
+        let loaded = PolicyValueModel::read(&mut buf.as_slice()).unwrap();
+        assert_eq!(loaded.kind, 6);
+        assert_eq!(loaded.value, vec![1, 2, 3]);
+        assert_eq!(loaded.policy.unwrap().weights, weights);
+
+        let model = PolicyValueModel::new(4, vec![9], None);
+        let mut buf = Vec::new();
+        model.write(&mut buf).unwrap();
+        assert!(
+            !PolicyValueModel::read(&mut buf.as_slice())
+                .unwrap()
+                .has_policy()
+        );
+        assert!(PolicyValueModel::read(&mut &buf[1..]).is_err());
+    }
+
+    #[test]
+    fn huge_value_length() {
+        let mut buf = Vec::new();
+        PolicyValueModel::new(4, vec![9], None)
+            .write(&mut buf)
+            .unwrap();
+        // 価値モデルの長さを巨大な値に書き換えても、確保せずにエラーにする
+        buf[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = PolicyValueModel::read(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn split_checks_value_model() {
+        let nn4 = include_bytes!("evaluator/NN4_true.model");
+        let nn6 = include_bytes!("evaluator/NN6_false.model");
+        assert!(PolicyValueModel::new(4, nn4.to_vec(), None).split().is_ok());
+        // NN6 のモデルを NN4 として分けようとするとエラーになる
+        let err = PolicyValueModel::new(4, nn6.to_vec(), None)
+            .split()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            PolicyValueModel::new(6, vec![1, 2, 3], None)
+                .split()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn priors_sum_to_one() {
+        let mut ai = PuctSearcher::new(ScoreDiffEvaluator::new(), UniformPolicy::new(), 10, 1.5);
+        let mut node = Node::new(Board::new(true));
+        ai.expand(&mut node);
+        let sum = node.children.iter().map(|c| c.prior).sum::<f64>();
+        assert!((sum - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn smoke_puct() {
+        let mut ai = PuctSearcher::new(ScoreDiffEvaluator::new(), UniformPolicy::new(), 20, 1.5);
+        let board = Board::new(false);
+        let pos_list = ai.sow(&board);
+        assert!(board.list_next_with_pos().values().any(|p| *p == pos_list));
+    }
+}
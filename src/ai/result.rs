@@ -0,0 +1,223 @@
+use std::{
+    fmt::{self, Debug},
+    sync::atomic::AtomicBool,
+};
+
+use instant::{Duration, Instant};
+
+use super::{
+    Evaluator, Score,
+    dump::{DumpNode, DumpOptions},
+    utils::PvSearch,
+};
+use crate::board::Board;
+
+/// 探索の結果
+#[derive(Debug, Clone)]
+pub struct SearchResult<S> {
+    /// 最善手
+    pub best: Vec<usize>,
+    /// 読み筋。最善手から始まるターンごとの打ち手
+    pub pv: Vec<Vec<usize>>,
+    /// 手番側から見た評価値
+    pub score: S,
+    /// 探索した深さ
+    pub depth: usize,
+    /// 訪問したノード数
+    pub nodes: u64,
+    pub elapsed: Duration,
+    /// 初手ごとの評価値
+    pub root_scores: Vec<(Vec<usize>, S)>,
+}
+
+impl<S: Debug> fmt::Display for SearchResult<S> {
+    fn fmt(&self, dest: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            dest,
+            "score {:?} depth {} nodes {} time {}ms pv",
+            self.score,
+            self.depth,
+            self.nodes,
+            self.elapsed.as_millis(),
+        )?;
+        for pos_list in self.pv.iter() {
+            write!(dest, " {pos_list:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 盤面の列として得た読み筋を打ち手の列に直す
+pub fn pv_to_turns(board: &Board, pv: &[Board]) -> Vec<Vec<usize>> {
+    let mut ret = Vec::with_capacity(pv.len());
+    let mut prev = board.clone();
+    for next in pv {
+        match prev.list_next_with_pos().remove(next) {
+            Some(pos_list) => ret.push(pos_list),
+            None => break,
+        }
+        prev = next.clone();
+    }
+    ret
+}
+
+/// 初手ごとに `ab_search_q` して結果をまとめる
+/// `exact` が `false` の場合は最善手以外の評価値は上界になるが、その分速い
+pub fn search_root<E: Evaluator>(
+    board: &Board,
+    eval: &mut E,
+    max_depth: usize,
+    qdepth: usize,
+    exact: bool,
+) -> SearchResult<E::Score> {
+    let stop = AtomicBool::new(false);
+    search_root_until(board, eval, max_depth, qdepth, exact, &stop).unwrap()
+}
+
+/// `stop` が立ったら打ち切って `None` を返す `search_root`
+pub fn search_root_until<E: Evaluator>(
+    board: &Board,
+    eval: &mut E,
+    max_depth: usize,
+    qdepth: usize,
+    exact: bool,
+    stop: &AtomicBool,
+) -> Option<SearchResult<E::Score>> {
+    search_root_dump(board, eval, max_depth, qdepth, exact, stop, None).map(|(result, _)| result)
+}
+
+/// `dump` を指定すると、探索しながら実際に読んだ探索木も記録して返す `search_root_until`
+pub(super) fn search_root_dump<E: Evaluator>(
+    board: &Board,
+    eval: &mut E,
+    max_depth: usize,
+    qdepth: usize,
+    exact: bool,
+    stop: &AtomicBool,
+    dump: Option<DumpOptions>,
+) -> Option<(SearchResult<E::Score>, Option<DumpNode>)> {
+    let start = Instant::now();
+    let mut best = Vec::new();
+    let mut best_score = E::Score::MIN;
+    let mut best_pv = Vec::new();
+    let mut root_scores = Vec::new();
+    let mut pv = Vec::with_capacity(max_depth);
+    let next_list = board.list_next_with_pos();
+    if next_list.is_empty() {
+        best_score = eval.eval(board);
+    }
+    let mut search = PvSearch::new(eval, qdepth);
+    search.set_stop(stop);
+    if let Some(options) = dump {
+        search.set_dump(options);
+    }
+    search.enter(board, E::Score::MIN, E::Score::MAX);
+    for (next, pos_list) in next_list {
+        let beta = if exact {
+            E::Score::MAX
+        } else {
+            best_score.flip()
+        };
+        let s = search
+            .search(next.clone(), max_depth, E::Score::MIN, beta, &mut pv)
+            .flip();
+        root_scores.push((pos_list.clone(), s));
+        if s > best_score {
+            best_score = s;
+            best = pos_list;
+            best_pv.clear();
+            best_pv.push(next);
+            best_pv.append(&mut pv);
+        }
+    }
+    if search.stopped() {
+        return None;
+    }
+    search.leave(best_score);
+    let nodes = search.nodes() + 1;
+    let dump = search.take_dump().map(|mut root| {
+        // 根は `search` を通らないので、訪問ノード数に自分の分を足す
+        root.visits += 1;
+        root
+    });
+    root_scores.sort_by(|a, b| a.0.cmp(&b.0));
+    let result = SearchResult {
+        best,
+        pv: pv_to_turns(board, &best_pv),
+        score: best_score,
+        depth: max_depth,
+        nodes,
+        elapsed: start.elapsed(),
+        root_scores,
+    };
+    Some((result, dump))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoreDiffEvaluator;
+    use crate::ai::dump::score_text;
+
+    #[test]
+    fn pv_starts_with_best() {
+        let board = Board::new(true);
+        let mut eval = ScoreDiffEvaluator::new();
+        let result = search_root(&board, &mut eval, 3, 0, true);
+        assert_eq!(result.pv[0], result.best);
+        assert_eq!(result.pv.len(), 4);
+        assert_eq!(result.root_scores.len(), board.list_next().len());
+        let max = result.root_scores.iter().map(|(_, s)| *s).max().unwrap();
+        assert_eq!(max, result.score);
+
+        let mut b = board.clone();
+        for pos_list in result.pv.iter() {
+            let side = b.side();
+            for &pos in pos_list {
+                assert_eq!(b.side(), side);
+                b.sow(pos);
+            }
+        }
+    }
+
+    #[test]
+    fn narrow_window_keeps_best() {
+        let board = Board::new(false);
+        let mut eval = ScoreDiffEvaluator::new();
+        let exact = search_root(&board, &mut eval, 3, 0, true);
+        let fast = search_root(&board, &mut eval, 3, 0, false);
+        assert_eq!(exact.score, fast.score);
+        assert!(fast.nodes <= exact.nodes);
+    }
+
+    #[test]
+    fn dump_records_real_search() {
+        let board = Board::new(true);
+        let mut eval = ScoreDiffEvaluator::new();
+        let stop = AtomicBool::new(false);
+        let options = DumpOptions {
+            max_depth: 2,
+            min_visits: 1,
+        };
+        let (result, tree) =
+            search_root_dump(&board, &mut eval, 3, 0, false, &stop, Some(options)).unwrap();
+        let tree = tree.unwrap();
+        // 記録した木は探索そのものなので、ノード数も評価値も探索結果と一致する
+        assert_eq!(tree.visits, result.nodes);
+        assert_eq!(tree.score, Some(score_text(result.score)));
+        assert_eq!(tree.children.len(), board.list_next().len());
+        for child in tree.children.iter() {
+            let (_, s) = result
+                .root_scores
+                .iter()
+                .find(|(pos_list, _)| *pos_list == child.pos_list)
+                .unwrap();
+            assert_eq!(child.score, Some(score_text(s.flip())));
+            assert_eq!(
+                child.visits,
+                1 + child.children.iter().map(|c| c.visits).sum::<u64>()
+            );
+            assert!(child.children.iter().all(|c| c.children.is_empty()));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use rand::Rng;
+
+use super::{
+    Evaluator,
+    greedy::greedy_turn,
+    utils::{random_down, random_down_with_weight},
+};
+use crate::board::Board;
+
+/// MCTS のプレイアウトの打ち方
+pub trait RolloutPolicy {
+    /// `board` から打ち進めて、`board` の手番側から見た最終的な得点差（の見積もり）を返す
+    fn rollout<R: Rng>(&mut self, random: &mut R, board: &Board) -> f64;
+}
+
+/// 打ち進めた後の盤面 `last` の得点差を `board` の手番側から見たものにする
+fn score_from(board: &Board, last: &Board, score: f64) -> f64 {
+    if last.side() == board.side() {
+        score
+    } else {
+        -score
+    }
+}
+
+/// 一様ランダムに終局まで打つ
+#[derive(Debug, Clone, Default)]
+pub struct RandomRollout;
+
+impl RandomRollout {
+    pub fn new() -> RandomRollout {
+        RandomRollout
+    }
+}
+
+impl RolloutPolicy for RandomRollout {
+    fn rollout<R: Rng>(&mut self, random: &mut R, board: &Board) -> f64 {
+        let last = random_down(random, board);
+        score_from(board, &last, f64::from(last.last_score()))
+    }
+}
+
+/// `GreedySearcher` と同じ打ち方で終局まで打つ
+#[derive(Debug, Clone, Default)]
+pub struct GreedyRollout;
+
+impl GreedyRollout {
+    pub fn new() -> GreedyRollout {
+        GreedyRollout
+    }
+}
+
+impl RolloutPolicy for GreedyRollout {
+    fn rollout<R: Rng>(&mut self, random: &mut R, board: &Board) -> f64 {
+        let mut last = board.clone();
+        while !last.is_finished() {
+            for pos in greedy_turn(last.stealing(), random, &last) {
+                last.sow(pos);
+            }
+        }
+        score_from(board, &last, f64::from(last.last_score()))
+    }
+}
+
+/// 評価値の softmax に比例した確率で終局まで打つ
+#[derive(Debug, Clone)]
+pub struct SoftmaxRollout<E> {
+    eval: E,
+}
+
+impl<E> SoftmaxRollout<E> {
+    pub fn new(eval: E) -> SoftmaxRollout<E> {
+        SoftmaxRollout { eval }
+    }
+}
+
+impl<E> RolloutPolicy for SoftmaxRollout<E>
+where
+    E: Evaluator,
+    E::Score: Into<f64>,
+{
+    fn rollout<R: Rng>(&mut self, random: &mut R, board: &Board) -> f64 {
+        let last = random_down_with_weight(random, &mut self.eval, board.clone());
+        score_from(board, &last, f64::from(last.last_score()))
+    }
+}
+
+/// `depth` ターンだけランダムに打って、そこからは評価関数の値を使う
+#[derive(Debug, Clone)]
+pub struct TruncatedRollout<E> {
+    eval: E,
+    depth: usize,
+}
+
+impl<E> TruncatedRollout<E> {
+    pub fn new(eval: E, depth: usize) -> TruncatedRollout<E> {
+        TruncatedRollout { eval, depth }
+    }
+}
+
+impl<E> RolloutPolicy for TruncatedRollout<E>
+where
+    E: Evaluator,
+    E::Score: Into<f64>,
+{
+    fn rollout<R: Rng>(&mut self, random: &mut R, board: &Board) -> f64 {
+        let mut last = board.clone();
+        for _ in 0..self.depth {
+            let mut next_list = last.list_next().drain().collect::<Vec<_>>();
+            if next_list.is_empty() {
+                break;
+            }
+            let idx = random.random_range(0..next_list.len());
+            last = next_list.swap_remove(idx);
+        }
+        let score = if last.is_finished() {
+            f64::from(last.last_score())
+        } else {
+            self.eval.eval(&last).into()
+        };
+        score_from(board, &last, score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScoreDiffEvaluator;
+    use rand_pcg::Mcg128Xsl64;
+
+    /// 終局した盤面からはどの打ち方でも確定した得点差が返る
+    #[test]
+    fn finished_board() {
+        let board = Board::from_seeds(true, &[0, 0, 0, 0, 0, 0, 1, 2, 3, 0, 0, 0]);
+        let mut random = Mcg128Xsl64::new(1);
+        let expected = f64::from(board.last_score());
+        assert_eq!(RandomRollout::new().rollout(&mut random, &board), expected);
+        assert_eq!(GreedyRollout::new().rollout(&mut random, &board), expected);
+        assert_eq!(
+            SoftmaxRollout::new(ScoreDiffEvaluator::new()).rollout(&mut random, &board),
+            expected
+        );
+        assert_eq!(
+            TruncatedRollout::new(ScoreDiffEvaluator::new(), 3).rollout(&mut random, &board),
+            expected
+        );
+    }
+
+    #[test]
+    fn smoke_rollout() {
+        let board = Board::new(true);
+        let mut random = Mcg128Xsl64::new(1);
+        let total = f64::from(crate::SEED) * (crate::PIT * 2) as f64;
+        for s in [
+            RandomRollout::new().rollout(&mut random, &board),
+            GreedyRollout::new().rollout(&mut random, &board),
+            SoftmaxRollout::new(ScoreDiffEvaluator::new()).rollout(&mut random, &board),
+            TruncatedRollout::new(ScoreDiffEvaluator::new(), 3).rollout(&mut random, &board),
+        ] {
+            assert!(-total <= s && s <= total);
+        }
+    }
+}
@@ -1,32 +1,40 @@
-use std::io::stdin;
+use std::{
+    io::stdin,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
 
 use rand::{Rng, prelude::IndexedRandom};
 
-use super::{Evaluator, Score, Searcher, utils::ab_search};
+use super::{
+    AnytimeSearcher, DepthSearcher, Evaluator, Searcher,
+    result::{SearchResult, search_root},
+};
 use crate::board::{Board, PIT};
 
 #[derive(Debug, Clone, Default)]
 pub struct Interactive<E> {
     evaluator: E,
     max_depth: usize,
+    live_hint: bool,
 }
 
 fn get_suggest<E: Evaluator>(
     board: &Board,
     eval: &mut E,
     max_depth: usize,
-) -> Vec<Option<E::Score>> {
+) -> (Vec<Option<E::Score>>, SearchResult<E::Score>) {
+    let result = search_root(board, eval, max_depth, 0, true);
     let mut ret = vec![None; PIT];
-    for (next, pos_list) in board.list_next_with_pos() {
-        let s = ab_search(next, eval, max_depth, E::Score::MIN, E::Score::MAX).flip();
+    for (pos_list, s) in result.root_scores.iter() {
         let pos = pos_list[0];
         match ret.get(pos) {
-            Some(None) => ret[pos] = Some(s),
-            Some(Some(best)) if best < &s => ret[pos] = Some(s),
+            Some(None) => ret[pos] = Some(*s),
+            Some(Some(best)) if best < s => ret[pos] = Some(*s),
             _ => (),
         }
     }
-    ret
+    (ret, result)
 }
 
 impl<E> Interactive<E>
@@ -37,45 +45,72 @@ where
         Interactive {
             evaluator,
             max_depth,
+            live_hint: false,
         }
     }
 
+    /// 入力を待っている間、反復深化で読み続けて最善手を表示する
+    /// その場合は `max_depth` を超えても入力があるまで読む
+    pub fn set_live_hint(&mut self, live_hint: bool) {
+        self.live_hint = live_hint;
+    }
+
     fn print_suggest(&mut self, board: &Board) {
         eprintln!("suggest");
-        for (pos, best) in get_suggest(board, &mut self.evaluator, self.max_depth)
-            .iter()
-            .enumerate()
-        {
+        let (suggest, result) = get_suggest(board, &mut self.evaluator, self.max_depth);
+        for (pos, best) in suggest.iter().enumerate() {
             match best {
                 Some(best) => eprintln!("{pos} {best:?}"),
                 None => eprintln!("{pos} *"),
             }
         }
+        eprintln!("{result}");
+    }
+}
+
+fn read_pos(board: &Board) -> Vec<usize> {
+    loop {
+        eprint!("your turn: ");
+        let mut buf = String::new();
+        stdin().read_line(&mut buf).unwrap();
+        match buf.trim().parse() {
+            Ok(i) => match board.can_sow(i) {
+                Ok(_) => {
+                    return vec![i];
+                }
+                Err(e) => eprintln!("{e}"),
+            },
+            Err(e) => eprintln!("{e}"),
+        }
     }
 }
 
 impl<E> Searcher for Interactive<E>
 where
-    E: Evaluator,
+    E: Evaluator + Clone + Send,
 {
     fn sow(&mut self, board: &Board) -> Vec<usize> {
-        if self.max_depth > 0 {
-            self.print_suggest(board);
+        if self.max_depth == 0 {
+            return read_pos(board);
         }
-        loop {
-            eprint!("your turn: ");
-            let mut buf = String::new();
-            stdin().read_line(&mut buf).unwrap();
-            match buf.trim().parse() {
-                Ok(i) => match board.can_sow(i) {
-                    Ok(_) => {
-                        return vec![i];
-                    }
-                    Err(e) => eprintln!("{e}"),
-                },
-                Err(e) => eprintln!("{e}"),
-            }
+        if !self.live_hint {
+            self.print_suggest(board);
+            return read_pos(board);
         }
+        let mut ai = DepthSearcher::new(self.evaluator.clone(), self.max_depth);
+        let stop = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut depth = 0;
+                ai.think(board, &stop, &mut |best| {
+                    depth += 1;
+                    eprintln!("hint (depth {depth}): {best:?}");
+                });
+            });
+            let pos_list = read_pos(board);
+            stop.store(true, Ordering::Relaxed);
+            pos_list
+        })
     }
 }
 
@@ -95,7 +130,9 @@ where
     R: Rng,
 {
     fn sow(&mut self, board: &Board) -> Vec<usize> {
-        let next_list = board.list_next_with_pos().drain().collect::<Vec<_>>();
+        let mut next_list = board.list_next_with_pos().drain().collect::<Vec<_>>();
+        // HashMap の順序によらず、同じ乱数なら同じ手を選ぶ
+        next_list.sort_by(|a, b| a.1.cmp(&b.1));
         next_list.choose(&mut self.random).unwrap().1.clone()
     }
 }
@@ -1,9 +1,18 @@
+use std::{
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use fnv::FnvHasher;
 use rand::Rng;
 
-use super::{Evaluator, Score};
+use super::{
+    Evaluator, Score,
+    dump::{DumpNode, DumpOptions, DumpRecorder},
+};
 use crate::board::Board;
 
-struct Ordable<F>(pub F);
+pub(super) struct Ordable<F>(pub F);
 
 impl<F> PartialEq for Ordable<F>
 where
@@ -41,8 +50,23 @@ pub fn ab_search<E: Evaluator>(
     alpha: E::Score,
     beta: E::Score,
 ) -> E::Score {
+    ab_search_q(board, eval, depth, 0, alpha, beta)
+}
+
+/// 末端で `quiescence_search` を最大 `qdepth` ターン行う `ab_search`
+pub fn ab_search_q<E: Evaluator>(
+    board: Board,
+    eval: &mut E,
+    depth: usize,
+    qdepth: usize,
+    alpha: E::Score,
+    beta: E::Score,
+) -> E::Score {
+    if let Some(s) = eval.exact(&board) {
+        return s;
+    }
     if depth == 0 || board.is_finished() {
-        return eval.eval(&board);
+        return quiescence_search(board, eval, qdepth, alpha, beta);
     }
     let mut list = board.list_next().drain().collect::<Vec<_>>();
     if depth >= 3 {
@@ -50,7 +74,7 @@ pub fn ab_search<E: Evaluator>(
     }
     let mut alpha = alpha;
     for next in list {
-        let a = ab_search(next, eval, depth - 1, beta.flip(), alpha.flip()).flip();
+        let a = ab_search_q(next, eval, depth - 1, qdepth, beta.flip(), alpha.flip()).flip();
         if a > alpha {
             alpha = a;
         }
@@ -61,6 +85,175 @@ pub fn ab_search<E: Evaluator>(
     alpha
 }
 
+/// 横取りが絡むターンだけを `qdepth` ターンまで読み進めてから評価する
+/// 横取りの応酬の途中で評価して大きく読み違えるのを防ぐ
+pub fn quiescence_search<E: Evaluator>(
+    board: Board,
+    eval: &mut E,
+    qdepth: usize,
+    alpha: E::Score,
+    beta: E::Score,
+) -> E::Score {
+    if let Some(s) = eval.exact(&board) {
+        return s;
+    }
+    let stand = eval.eval(&board);
+    if qdepth == 0 || board.is_finished() {
+        return stand;
+    }
+    let mut alpha = alpha;
+    if stand > alpha {
+        alpha = stand;
+    }
+    if alpha >= beta {
+        return alpha;
+    }
+    for next in board.list_next_tactical() {
+        let a = quiescence_search(next, eval, qdepth - 1, beta.flip(), alpha.flip()).flip();
+        if a > alpha {
+            alpha = a;
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+    alpha
+}
+
+/// 読み筋の記録と探索ノード数の計測をしながら `ab_search_q` する
+pub struct PvSearch<'a, E> {
+    eval: &'a mut E,
+    qdepth: usize,
+    nodes: u64,
+    stop: Option<&'a AtomicBool>,
+    dump: Option<DumpRecorder>,
+}
+
+impl<'a, E: Evaluator> PvSearch<'a, E> {
+    pub fn new(eval: &'a mut E, qdepth: usize) -> PvSearch<'a, E> {
+        PvSearch {
+            eval,
+            qdepth,
+            nodes: 0,
+            stop: None,
+            dump: None,
+        }
+    }
+
+    /// 探索しながら、実際に読んだ探索木を `options` の範囲で記録する
+    pub(super) fn set_dump(&mut self, options: DumpOptions) {
+        self.dump = Some(DumpRecorder::new(options));
+    }
+
+    /// `search` を通さずに子を読むノード（根）の記録を始める
+    pub(super) fn enter(&mut self, board: &Board, alpha: E::Score, beta: E::Score) {
+        if let Some(dump) = self.dump.as_mut() {
+            dump.enter(board, alpha, beta, self.nodes);
+        }
+    }
+
+    /// `enter` したノードの記録を終える
+    pub(super) fn leave(&mut self, score: E::Score) {
+        if let Some(dump) = self.dump.as_mut() {
+            dump.leave(score, self.nodes);
+        }
+    }
+
+    /// 記録した探索木を取り出す
+    pub(super) fn take_dump(&mut self) -> Option<DumpNode> {
+        self.dump.as_mut().and_then(DumpRecorder::take)
+    }
+
+    /// `stop` が立ったら探索を打ち切る。打ち切った後の評価値は使えない
+    pub fn set_stop(&mut self, stop: &'a AtomicBool) {
+        self.stop = Some(stop);
+    }
+
+    /// 探索を打ち切ったかどうか
+    pub fn stopped(&self) -> bool {
+        self.stop.is_some_and(|stop| stop.load(Ordering::Relaxed))
+    }
+
+    /// これまでに訪問したノード数
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// `pv` には `board` の次の盤面からの読み筋が入る
+    pub fn search(
+        &mut self,
+        board: Board,
+        depth: usize,
+        alpha: E::Score,
+        beta: E::Score,
+        pv: &mut Vec<Board>,
+    ) -> E::Score {
+        if self.dump.is_none() {
+            return self.search_node(board, depth, alpha, beta, pv);
+        }
+        self.enter(&board, alpha, beta);
+        let s = self.search_node(board, depth, alpha, beta, pv);
+        self.leave(s);
+        s
+    }
+
+    fn search_node(
+        &mut self,
+        board: Board,
+        depth: usize,
+        alpha: E::Score,
+        beta: E::Score,
+        pv: &mut Vec<Board>,
+    ) -> E::Score {
+        self.nodes += 1;
+        pv.clear();
+        if self.stopped() {
+            return alpha;
+        }
+        if let Some(s) = self.eval.exact(&board) {
+            return s;
+        }
+        if depth == 0 || board.is_finished() {
+            return quiescence_search(board, self.eval, self.qdepth, alpha, beta);
+        }
+        let mut list = board.list_next().drain().collect::<Vec<_>>();
+        if depth >= 3 {
+            list.sort_by_cached_key(|b| Ordable(self.eval.eval(b)));
+        }
+        let mut alpha = alpha;
+        let mut child_pv = Vec::with_capacity(depth);
+        for next in list {
+            let a = self
+                .search(
+                    next.clone(),
+                    depth - 1,
+                    beta.flip(),
+                    alpha.flip(),
+                    &mut child_pv,
+                )
+                .flip();
+            if a > alpha {
+                alpha = a;
+                pv.clear();
+                pv.push(next);
+                pv.append(&mut child_pv);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        alpha
+    }
+}
+
+/// 手番も含めた局面のハッシュ値。置換表のキーに使う
+pub fn position_key(board: &Board) -> u64 {
+    let mut hasher = FnvHasher::default();
+    board.hash(&mut hasher);
+    board.side().hash(&mut hasher);
+    hasher.finish()
+}
+
 pub fn random_down<R: Rng>(random: &mut R, board: &Board) -> Board {
     let mut board = board.clone();
     loop {
@@ -154,6 +347,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pv_search_matches_ab_search() {
+        let board = Board::new(true);
+        let mut eval = ScoreDiffEvaluator::new();
+        let expected = ab_search(
+            board.clone(),
+            &mut eval,
+            4,
+            <i32 as Score>::MIN,
+            <i32 as Score>::MAX,
+        );
+        let mut pv = Vec::new();
+        let mut search = PvSearch::new(&mut eval, 0);
+        let score = search.search(board, 4, <i32 as Score>::MIN, <i32 as Score>::MAX, &mut pv);
+        assert_eq!(score, expected);
+        assert_eq!(pv.len(), 4);
+        assert!(search.nodes() > 1);
+    }
+
+    #[test]
+    fn quiescence_sees_steal() {
+        // skip
+        if !(crate::PIT == 6 && crate::SEED == 4) {
+            return;
+        }
+        // 手番側は 0 番の穴から蒔けば 4 個横取りできる
+        let board = Board::from_seeds(true, &[1, 0, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4]);
+        let mut eval = ScoreDiffEvaluator::new();
+        let stand = quiescence_search(
+            board.clone(),
+            &mut eval,
+            0,
+            <i32 as Score>::MIN,
+            <i32 as Score>::MAX,
+        );
+        let q = quiescence_search(
+            board,
+            &mut eval,
+            4,
+            <i32 as Score>::MIN,
+            <i32 as Score>::MAX,
+        );
+        assert!(q > stand);
+    }
+
     #[test]
     fn smoke_random_down() {
         let mut random = Mcg128Xsl64::new(1);
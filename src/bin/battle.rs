@@ -3,10 +3,40 @@ use std::process::exit;
 
 use mancala_rust::*;
 
+const DUMP_USAGE: &str = "--dump=(path.dot|path.json)[:(max_depth)[:(min_visits)]]";
+
+fn parse_dump(s: &str) -> Result<(String, DumpOptions), String> {
+    let args = s.split(':').collect::<Vec<_>>();
+    if args.len() > 3 {
+        return Err(DUMP_USAGE.to_string());
+    }
+    let mut options = DumpOptions::default();
+    if let Some(d) = args.get(1) {
+        options.max_depth = d.parse().map_err(|e| format!("{DUMP_USAGE} {e}"))?;
+    }
+    if let Some(v) = args.get(2) {
+        options.min_visits = v.parse().map_err(|e| format!("{DUMP_USAGE} {e}"))?;
+    }
+    Ok((args[0].to_string(), options))
+}
+
 fn main() {
-    let args = args().collect::<Vec<_>>();
+    let mut args = args().collect::<Vec<_>>();
+    let dump = match args.iter().position(|a| a.starts_with("--dump=")) {
+        Some(i) => match parse_dump(&args.remove(i)["--dump=".len()..]) {
+            Ok(dump) => Some(dump),
+            Err(e) => {
+                eprintln!("Usage: {e}");
+                exit(1);
+            }
+        },
+        None => None,
+    };
     if args.len() < 4 {
-        eprintln!("Usage: {} STEAL AI AI", args[0]);
+        eprintln!(
+            "Usage: {} STEAL AI AI [FIRST_SOW...] [{DUMP_USAGE}]",
+            args[0]
+        );
         exit(1);
     }
     let stealing = match args[1].parse::<bool>() {
@@ -36,6 +66,12 @@ fn main() {
         .collect::<Vec<_>>();
     let mut game = Game::new(stealing, a, b);
     game.show_board(true);
+    if let Some((path, options)) = dump
+        && let Err(e) = game.set_dump(&path, options)
+    {
+        eprintln!("{e}");
+        exit(1);
+    }
     game.first_sow(&first_sow);
     let (a, b) = game.run();
     println!("{a} {b}");
@@ -0,0 +1,29 @@
+use std::env::args;
+
+use mancala_rust::learn::compress_db;
+
+fn main() {
+    let usage = "USAGE: <stealing> <db> <compressed db> [memory]";
+    let args = args().skip(1).collect::<Vec<_>>();
+    assert!(args.len() == 3 || args.len() == 4, "{usage}");
+    let stealing = args[0].parse().expect(usage);
+    assert_ne!(args[1], args[2]);
+    // ソートしていない DB を外部ソートするときにメモリーに置く局面数
+    let memory = args.get(3).map_or(1 << 26, |s| s.parse().expect(usage));
+    match compress_db(&args[1], &args[2], stealing, memory) {
+        Ok(report) => {
+            println!("entries: {}", report.entries);
+            println!("raw: {} bytes", report.raw_bytes);
+            println!("compressed: {} bytes", report.compressed_bytes);
+            println!(
+                "ratio: {:.3} ({:.2} bytes/entry)",
+                report.ratio(),
+                report.bytes_per_entry()
+            );
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -3,9 +3,14 @@ use std::env::args;
 use mancala_rust::*;
 
 fn main() {
-    let list = ["mctree:800:2:2", "random", "dfs:nn6:4"];
     let args = args().collect::<Vec<_>>();
     let stealing = args[1].parse().unwrap();
+    // AI を指定しなければいつもの総当たり
+    let list = if args.len() > 2 {
+        args[2..].iter().map(|s| s.as_str()).collect::<Vec<_>>()
+    } else {
+        vec!["mctree:800:2:2", "random", "dfs:nn6:4"]
+    };
     loop {
         for &a in list.iter() {
             for &b in list.iter() {
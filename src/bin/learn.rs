@@ -1,72 +1,305 @@
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Write};
+use std::process::exit;
+use std::time::Instant;
 
 use ndarray::Array2;
-use rand::SeedableRng;
-use rand_pcg::Mcg128Xsl64;
 
-use mancala_rust::learn::{RepeatLod, ShuffledStream};
+use mancala_rust::learn::{Optimizer, *};
+use mancala_rust::{
+    Board, Evaluator, NeuralNet4Evaluator, NeuralNet6Evaluator, ValueModel, model_params,
+    model_widths, set_model_params,
+};
 use rust_nn::{Float, train::*};
 
-fn gen_case<I>(x: &mut Array2<Float>, t: &mut Array2<Float>, data: &mut I)
+const USAGE: &str = "USAGE: learn CONFIG
+
+CONFIG has one `key = value` per line:
+  db, out                  DB to learn and the model to write (required)
+  init                     model to continue from
+  arch = nn4|nn6, widths   e.g. widths = 12,64,64,64,64 (input first)
+  optimizer = sgd|momentum:BETA, lr, schedule = constant|step:N:GAMMA|exp:GAMMA|cosine:MIN_LR
+  batch, buffer, seed, validation, test, split_salt, seeds = MIN-MAX, depth = MIN-MAX
+  max_epochs, max_steps, patience, validation_rows, report_every
+
+The best model by validation MSE is written to `out`, the latest to `out.last`,
+and the progress to `out.meta`";
+
+enum Model {
+    NN4(NN4Regression<SGD>),
+    NN6(NN6Regression<SGD>),
+}
+
+impl Model {
+    fn new(config: &TrainConfig, lr: f64) -> Model {
+        let sgd = || SGD::default().learning_rate(lr as Float);
+        match config.arch {
+            Arch::NN4 => Model::NN4(NN4Regression::new(
+                config.widths.clone().try_into().unwrap(),
+                config.batch,
+                sgd(),
+                sgd(),
+            )),
+            Arch::NN6 => Model::NN6(NN6Regression::new(
+                config.widths.clone().try_into().unwrap(),
+                config.batch,
+                sgd(),
+                sgd(),
+            )),
+        }
+    }
+
+    /// 学習率を変えるときも読み直す。モデルの形が `config.widths` と違えばエラーを返す
+    fn decode(config: &TrainConfig, lr: f64, bytes: &[u8]) -> Result<Model, String> {
+        let widths = model_widths(bytes).map_err(|e| e.to_string())?;
+        if widths != config.widths {
+            return Err(format!(
+                "the model has widths {widths:?} but the config says {:?}",
+                config.widths
+            ));
+        }
+        let sgd = || SGD::default().learning_rate(lr as Float);
+        let mut r = bytes;
+        Ok(match config.arch {
+            Arch::NN4 => Model::NN4(NN4Regression::decode(&mut r, config.batch, sgd(), sgd())),
+            Arch::NN6 => Model::NN6(NN6Regression::decode(&mut r, config.batch, sgd(), sgd())),
+        })
+    }
+
+    /// 重みとバイアスを書き換える。形は変えない
+    fn set_params(&mut self, config: &TrainConfig, lr: f64, params: &[f64]) -> Result<(), String> {
+        let mut bytes = self.encode();
+        set_model_params(&mut bytes, params).map_err(|e| e.to_string())?;
+        *self = Model::decode(config, lr, &bytes)?;
+        Ok(())
+    }
+
+    fn params(&self) -> Result<Vec<f64>, String> {
+        model_params(&self.encode()).map_err(|e| e.to_string())
+    }
+
+    fn train(&mut self, x: &Array2<Float>, t: &Array2<Float>) -> Float {
+        match self {
+            Model::NN4(m) => m.train(x, t),
+            Model::NN6(m) => m.train(x, t),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Model::NN4(m) => m.encode(&mut buf),
+            Model::NN6(m) => m.encode(&mut buf),
+        }
+        buf
+    }
+
+    /// 対局で使うのと同じ評価関数にする
+    fn evaluator(&self) -> ValueModel {
+        let bytes = self.encode();
+        match self {
+            Model::NN4(_) => {
+                ValueModel::NN4(NeuralNet4Evaluator::from_reader(&mut bytes.as_slice()))
+            }
+            Model::NN6(_) => {
+                ValueModel::NN6(NeuralNet6Evaluator::from_reader(&mut bytes.as_slice()))
+            }
+        }
+    }
+}
+
+/// 1 バッチ分を埋める。足りなければ false
+fn gen_case<I>(x: &mut Array2<Float>, t: &mut Array2<Float>, data: &mut I) -> bool
 where
     I: Iterator<Item = ([u8; 12], i8, u8)>,
 {
     for (mut x, mut t) in x.rows_mut().into_iter().zip(t.rows_mut()) {
-        let (board, score, _) = data.next().unwrap();
+        let Some((board, score, _)) = data.next() else {
+            return false;
+        };
         for (x, b) in x.iter_mut().zip(board.iter()) {
             *x = Float::from(*b);
         }
         t[0] = Float::from(score);
     }
+    true
 }
 
-fn main() {
-    let args = std::env::args().skip(1).collect::<Vec<_>>();
-    let db_path = args[0].as_str();
-    let save_path = args[1].as_str();
-    assert_ne!(db_path, save_path);
-    let pow2 = args[2].parse::<i32>().unwrap();
-    let batch_size = args[3].parse().unwrap();
-    let lr = 2f64.powi(-pow2) as Float;
-    let mut model = match args.get(4) {
-        None => NN4Regression::new(
-            [12, 64, 64, 64, 64],
-            batch_size,
-            SGD::default().learning_rate(lr),
-            SGD::default().learning_rate(lr),
-        ),
+/// 絞り込んだあとの検証用の局面数を数える
+fn count_validation(dataset: &Dataset) -> Result<u64, String> {
+    let mut dataset = dataset.clone();
+    // 数えるだけなので並べ替えない
+    dataset.buffer = 1;
+    let mut rows = dataset
+        .epoch(Split::Validation, 0)
+        .map_err(|e| e.to_string())?;
+    let count = rows.by_ref().count() as u64;
+    if let Some(e) = rows.get_ref().error() {
+        return Err(e.to_string());
+    }
+    Ok(count)
+}
+
+/// 検証用の局面を `validation_rows` くらいになるようにキーのハッシュで間引いて使う
+/// ソートした DB の先頭から取るとキーの小さい局面に偏るので、DB 全体から選ぶ
+/// `total` は絞り込んだあとの検証用の局面数
+fn validate(
+    config: &TrainConfig,
+    dataset: &Dataset,
+    total: u64,
+    model: &Model,
+) -> Result<Validation, String> {
+    let mut eval = model.evaluator();
+    let mut dataset = dataset.clone();
+    dataset.sample = (config.validation_rows as f64 / total as f64).min(1.0);
+    let mut rows = dataset
+        .epoch(Split::Validation, 0)
+        .map_err(|e| e.to_string())?;
+    // ストアが空の局面なので、DB の値がそのまま評価関数の正解になる
+    let validation = Validation::new(rows.by_ref().map(|(pits, score, _)| {
+        let board = Board::from_seeds(dataset.stealing, &pits);
+        (eval.eval(&board), f64::from(score))
+    }));
+    if let Some(e) = rows.get_ref().error() {
+        return Err(e.to_string());
+    }
+    // 誤差 0 として扱うと、そのエポックより良いモデルがなくなる
+    if validation.rows == 0 {
+        return Err("no validation data; check the filters and validation".to_string());
+    }
+    Ok(validation)
+}
+
+fn write_model(path: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let mut f = BufWriter::new(File::create(path)?);
+    f.write_all(bytes)?;
+    f.flush()
+}
+
+fn run(config: TrainConfig) -> Result<(), String> {
+    let header = read_header(&config.db).map_err(|e| e.to_string())?;
+    let dataset = config.dataset(header.stealing);
+    let mut lr = config.lr_at(0);
+    let mut model = match config.init.as_ref() {
+        None => Model::new(&config, lr),
         Some(path) => {
-            let mut f = BufReader::new(File::open(path).unwrap());
-            NN4Regression::decode(
-                &mut f,
-                batch_size,
-                SGD::default().learning_rate(lr),
-                SGD::default().learning_rate(lr),
+            let mut bytes = Vec::new();
+            std::io::Read::read_to_end(
+                &mut BufReader::new(File::open(path).map_err(|e| format!("{path}: {e}"))?),
+                &mut bytes,
             )
+            .map_err(|e| format!("{path}: {e}"))?;
+            Model::decode(&config, lr, &bytes).map_err(|e| format!("{path}: {e}"))?
         }
     };
+    let start = Instant::now();
+    let mut meta = RunMeta {
+        config: config.clone(),
+        db_entries: header.entries,
+        db_checksum: header.checksum,
+        epoch: 0,
+        step: 0,
+        lr,
+        train_loss: 0.0,
+        last: Validation::default(),
+        best_epoch: None,
+        best: Validation::default(),
+        elapsed_secs: 0.0,
+        stopped: None,
+    };
+    let mut momentum = match config.optimizer {
+        Optimizer::Sgd => None,
+        Optimizer::Momentum { beta } => Some(Momentum::new(beta, model.params()?)),
+    };
+    let validation_total = count_validation(&dataset)?;
+    let mut best = Best::new(config.patience);
+    let mut x = Array2::zeros([config.batch, 12]);
+    let mut t = Array2::zeros([config.batch, 1]);
+    for epoch in 0..config.max_epochs {
+        if config.lr_at(epoch) != lr {
+            lr = config.lr_at(epoch);
+            model = Model::decode(&config, lr, &model.encode())?;
+        }
+        let mut data = dataset
+            .epoch(Split::Train, epoch)
+            .map_err(|e| e.to_string())?;
+        let (mut loss, mut batches) = (0.0, 0);
+        let (mut epoch_loss, mut epoch_batches) = (0.0, 0);
+        while gen_case(&mut x, &mut t, &mut data) {
+            let l = model.train(&x, &t);
+            if let Some(momentum) = momentum.as_mut() {
+                // rust_nn は SGD で更新するので、動いた分に慣性をかけて書き直す
+                let mut params = model.params()?;
+                momentum.step(&mut params);
+                model.set_params(&config, lr, &params)?;
+            }
+            loss += l;
+            epoch_loss += l;
+            batches += 1;
+            epoch_batches += 1;
+            meta.step += 1;
+            if meta.step.is_multiple_of(config.report_every) {
+                println!("step {} loss {}", meta.step, loss / batches as f64);
+                (loss, batches) = (0.0, 0);
+            }
+            if meta.step == config.max_steps {
+                meta.stopped = Some("max_steps".to_string());
+                break;
+            }
+        }
+        if let Some(e) = data.get_ref().error() {
+            return Err(e.to_string());
+        }
+        if epoch_batches == 0 {
+            return Err("no training data; check the filters and batch size".to_string());
+        }
 
-    let mut x = Array2::zeros([batch_size, 12]);
-    let mut t = Array2::zeros([batch_size, 1]);
-    let mut data = ShuffledStream::new(
-        RepeatLod::new(db_path),
-        Mcg128Xsl64::from_rng(&mut rand::rng()),
-        batch_size * 1024,
-    );
-    let mut epoch = 0_u64;
-    let mut loss = 0.0;
-    loop {
-        gen_case(&mut x, &mut t, &mut data);
-        loss += model.train(&x, &t);
-        epoch += 1;
-        if epoch % 1_000 == 0 {
-            println!("{} {}", epoch, loss / 1000.0);
-            loss = 0.0;
+        let validation = validate(&config, &dataset, validation_total, &model)?;
+        let bytes = model.encode();
+        write_model(&format!("{}.last", config.out), &bytes).map_err(|e| e.to_string())?;
+        let improved = best.update(epoch, validation);
+        if improved {
+            write_model(&config.out, &bytes).map_err(|e| e.to_string())?;
+        }
+        println!(
+            "epoch {} lr {} train {} validation mse {} mae {}{}",
+            epoch,
+            lr,
+            epoch_loss / epoch_batches as f64,
+            validation.mse,
+            validation.mae,
+            if improved { " *" } else { "" }
+        );
+        meta.epoch = epoch + 1;
+        meta.lr = lr;
+        meta.train_loss = epoch_loss / epoch_batches as f64;
+        meta.last = validation;
+        meta.best_epoch = best.epoch;
+        meta.best = best.validation;
+        meta.elapsed_secs = start.elapsed().as_secs_f64();
+        if meta.stopped.is_none() && best.should_stop(epoch) {
+            meta.stopped = Some("patience".to_string());
+        }
+        if meta.stopped.is_none() && epoch + 1 == config.max_epochs {
+            meta.stopped = Some("max_epochs".to_string());
         }
-        if epoch % 100_000 == 0 {
-            let mut f = BufWriter::new(File::create(save_path).unwrap());
-            model.encode(&mut f);
+        meta.save().map_err(|e| e.to_string())?;
+        if meta.stopped.is_some() {
+            break;
         }
     }
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [path] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        exit(1);
+    };
+    let result = TrainConfig::load(path).and_then(run);
+    if let Err(e) = result {
+        eprintln!("{e}");
+        exit(1);
+    }
 }
@@ -84,7 +84,8 @@ fn main() {
     drop(score_s);
 
     spawn(move || {
-        let db = iter_load(db_path).expect("DBが開けません");
+        let mut db = iter_load(db_path).expect("DBが開けません");
+        db.header().check_stealing(stealing).unwrap();
         let n = db.size_hint().1.unwrap();
         let bar = ProgressBar::new(n as u64);
         bar.set_style(
@@ -93,7 +94,7 @@ fn main() {
                 .unwrap(),
         );
         let mut r = Mcg128Xsl64::new(1);
-        for (i, (seeds, exact, _)) in db.enumerate() {
+        for (i, (seeds, exact, _)) in db.by_ref().enumerate() {
             if (i + 1) % 1048576 == 0 {
                 bar.inc(1048576);
             }
@@ -104,6 +105,7 @@ fn main() {
             }
         }
         bar.finish();
+        db.finish().expect("DBが壊れています");
     });
     let h = spawn(move || {
         let mut hist = Hist::new(-50.0, 50.0, 2f64.powi(-10));
@@ -1,32 +1,37 @@
 use std::env::args;
 
-use rand::SeedableRng;
-use rand_pcg::Mcg128Xsl64;
+use rand::Rng;
 
-use mancala_rust::{RandomSearcher, learn::*};
+use mancala_rust::{Board, compact_key, learn::*};
 
 fn main() {
-    let stealing = args().nth(1).expect("USAGE: <stealing>").parse().unwrap();
-    let mut ai = RandomSearcher::new(Mcg128Xsl64::from_rng(&mut rand::rng()));
-    let mut data = load(&db_name(stealing));
-    for i in 1..=30_000 {
-        let mut path = to_finish(stealing, &mut ai);
-        while let Some(board) = path.pop() {
-            if search(&mut data, board, 30).is_none() {
-                break;
-            }
-        }
-        if i % 100 == 0 {
-            println!("{} {}", i, data.len());
-        }
+    let usage = "USAGE: <stealing> [threads] [games] [seed|-] [memory]";
+    let args = args().skip(1).collect::<Vec<_>>();
+    let stealing = args.first().expect(usage).parse().expect(usage);
+    let threads = args.get(1).map_or(1, |s| s.parse().expect(usage));
+    let games = args.get(2).map_or(30_000, |s| s.parse().expect(usage));
+    let mut generator = Generator::new(stealing, threads);
+    // 同じ seed なら同じゲームを読むので、続きから増やすときは変える
+    generator.seed = args
+        .get(3)
+        .filter(|s| *s != "-")
+        .map_or_else(|| rand::rng().random(), |s| s.parse().expect(usage));
+    println!("seed: {}", generator.seed);
+    // メモリーに置く局面数。指定すると超えた分をディスクに書き出す
+    if let Some(memory) = args.get(4) {
+        let memory = memory.parse().expect(usage);
+        spilled(&generator, games, memory);
+        return;
     }
 
-    println!("save: {:?}", save(&db_name(stealing), &data));
+    let data = load_or_default(&db_name(stealing), stealing).unwrap();
+    let mut db = ShardedDb::from_map(256, data);
+    generator.run(&mut db, 0..games, |i, db| println!("{} {}", i, db.len()));
 
     println!("depth histogram");
     let mut hist = [0; 256];
-    for (_, (_, depth)) in data.iter() {
-        hist[*depth as usize] += 1;
+    for (_, (_, depth)) in db.iter() {
+        hist[depth as usize] += 1;
     }
     for (depth, count) in hist.iter().enumerate() {
         println!("{depth} {count}");
@@ -34,4 +39,32 @@ fn main() {
             break;
         }
     }
+
+    println!(
+        "save: {:?}",
+        write_sorted(&db_name(stealing), stealing, db.into_entries())
+    );
+}
+
+fn spilled(generator: &Generator, games: u64, memory: usize) {
+    let name = db_name(generator.stealing);
+    let mut db = SpillDb::new(format!("{name}.runs"), generator.stealing, memory).unwrap();
+    match iter_load(&name) {
+        Ok(mut old) => {
+            old.header().check_stealing(generator.stealing).unwrap();
+            for (pits, score, depth) in old.by_ref() {
+                let key = compact_key(&Board::from_seeds(generator.stealing, &pits));
+                db.put(key, (score, depth)).unwrap();
+            }
+            old.finish().unwrap();
+        }
+        Err(e) if e.is_not_found() => eprintln!("{name} is not exists ({e})"),
+        Err(e) => panic!("{e}"),
+    }
+    generator
+        .run_spilled(&mut db, 0..games, |i, db| {
+            println!("{} {} {}", i, db.hot_len(), db.runs())
+        })
+        .unwrap();
+    println!("save: {:?}", db.finish(&name));
 }
@@ -0,0 +1,263 @@
+use std::env::args;
+use std::process::exit;
+
+use rand::SeedableRng;
+use rand_pcg::Mcg128Xsl64;
+
+use mancala_rust::{
+    Board, NeuralNet4Evaluator, NeuralNet6Evaluator, PIT, PerfectSearcher, ScoreDiffEvaluator,
+    ScorePosEvaluator, Searcher, from_compact_key, learn::*,
+};
+
+const USAGE: &str = "Usage:
+  mancala-db stats DB
+  mancala-db merge STEAL OUT DB... [--last] [--layout=(plain|sorted|compressed)]
+  mancala-db diff STEAL A B
+  mancala-db query STEAL DB POSITION
+  mancala-db verify STEAL DB [SAMPLES] [MAX_DEPTH]
+  mancala-db export STEAL DB OUT [--seeds=MIN-MAX] [--depth=MIN-MAX] [--eval=(diff|pos|nn4|nn6|(nn4|nn6)=MODEL)[:DEPTH]]
+
+POSITION is 12 comma-separated seeds, side to move first
+export writes OUT as .npz if it ends with .npz, otherwise OUT_pits.npy, OUT_score.npy, ...";
+
+fn parse<T: std::str::FromStr>(s: Option<&str>, name: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    let s = s.ok_or_else(|| format!("{name} is required"))?;
+    s.parse().map_err(|e| format!("{name}: {e}"))
+}
+
+fn cmd_stats(args: &[&str]) -> Result<(), String> {
+    let path = args.first().ok_or("DB is required")?;
+    let (header, stats) = stats(path).map_err(|e| e.to_string())?;
+    println!(
+        "stealing: {} layout: {:?} entries: {}",
+        header.stealing, header.layout, stats.entries
+    );
+    println!("seeds histogram");
+    for (seeds, count) in stats.by_seeds.iter().enumerate() {
+        if *count > 0 {
+            println!("{seeds} {count}");
+        }
+    }
+    println!("depth histogram");
+    let last = stats.depth_hist.iter().rposition(|c| *c > 0).unwrap_or(0);
+    for (depth, count) in stats.depth_hist[..=last].iter().enumerate() {
+        println!("{depth} {count}");
+    }
+    Ok(())
+}
+
+fn cmd_merge(args: &[&str]) -> Result<(), String> {
+    let mut conflict = Conflict::First;
+    let mut layout = Layout::Plain;
+    let mut rest = Vec::new();
+    for a in args {
+        match *a {
+            "--last" => conflict = Conflict::Last,
+            "--layout=plain" => layout = Layout::Plain,
+            "--layout=sorted" => layout = Layout::Sorted,
+            "--layout=compressed" => layout = Layout::Compressed,
+            _ if a.starts_with("--") => return Err(format!("unknown option {a}")),
+            _ => rest.push(*a),
+        }
+    }
+    let stealing = parse(rest.first().copied(), "STEAL")?;
+    let out = rest.get(1).ok_or("OUT is required")?;
+    let inputs = &rest[2.min(rest.len())..];
+    if inputs.is_empty() {
+        return Err("DB is required".to_string());
+    }
+    if inputs.contains(out) {
+        return Err("OUT must differ from inputs".to_string());
+    }
+    let Merged { data, conflicts } =
+        merge(inputs, stealing, conflict).map_err(|e| e.to_string())?;
+    for (key, dropped) in conflicts.iter() {
+        println!(
+            "conflict {:?}: kept {:?} dropped {:?}",
+            from_compact_key(*key),
+            data[key],
+            dropped
+        );
+    }
+    println!("entries: {} conflicts: {}", data.len(), conflicts.len());
+    match layout {
+        Layout::Plain => save(out, stealing, &data),
+        Layout::Sorted => save_sorted(out, stealing, &data),
+        Layout::Compressed => save_compressed(out, stealing, &data),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn cmd_diff(args: &[&str]) -> Result<(), String> {
+    let stealing = parse(args.first().copied(), "STEAL")?;
+    let a = args.get(1).ok_or("A is required")?;
+    let b = args.get(2).ok_or("B is required")?;
+    let report = diff(a, b, stealing).map_err(|e| e.to_string())?;
+    for (key, va, vb) in report.differ.iter() {
+        println!("{:?}: {:?} != {:?}", from_compact_key(*key), va, vb);
+    }
+    println!(
+        "same: {} differ: {} only in A: {} only in B: {}",
+        report.same,
+        report.differ.len(),
+        report.only_a,
+        report.only_b
+    );
+    if report.differ.is_empty() && report.only_a == 0 && report.only_b == 0 {
+        Ok(())
+    } else {
+        exit(2);
+    }
+}
+
+fn cmd_query(args: &[&str]) -> Result<(), String> {
+    let stealing = parse(args.first().copied(), "STEAL")?;
+    let path = args.get(1).ok_or("DB is required")?;
+    let position = args.get(2).ok_or("POSITION is required")?;
+    let seeds = position
+        .split(',')
+        .map(|s| s.trim().parse::<u8>().map_err(|e| format!("POSITION: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    if seeds.len() != PIT * 2 {
+        return Err(format!("POSITION must have {} seeds", PIT * 2));
+    }
+    let board = Board::from_seeds(stealing, &seeds);
+    let mut ai = PerfectSearcher::load(path, stealing).map_err(|e| e.to_string())?;
+    let (score, depth) = ai.value(&board).map_err(|e| e.to_string())?;
+    println!("{}", board.notation());
+    println!("score: {score} depth: {depth}");
+    if board.is_finished() {
+        return Ok(());
+    }
+    let mut moves = board.list_next_with_pos().into_iter().collect::<Vec<_>>();
+    moves.sort_by(|a, b| a.1.cmp(&b.1));
+    for (next, pos_list) in moves {
+        let (s, d) = ai.value(&next).map_err(|e| e.to_string())?;
+        println!("{:?} score: {} depth: {}", pos_list, -s, d + 1);
+    }
+    println!("best: {:?}", ai.sow(&board));
+    Ok(())
+}
+
+fn cmd_verify(args: &[&str]) -> Result<(), String> {
+    let stealing = parse(args.first().copied(), "STEAL")?;
+    let path = args.get(1).ok_or("DB is required")?;
+    let samples = args
+        .get(2)
+        .map_or(Ok(1000), |s| parse(Some(s), "SAMPLES"))?;
+    let max_depth = args
+        .get(3)
+        .map_or(Ok(20), |s| parse(Some(s), "MAX_DEPTH"))?;
+    let mut random = Mcg128Xsl64::from_rng(&mut rand::rng());
+    let report = verify_sample(path, stealing, samples, max_depth, &mut random)
+        .map_err(|e| e.to_string())?;
+    for (key, stored, found) in report.mismatches.iter() {
+        println!(
+            "{:?}: stored {:?} searched {:?}",
+            from_compact_key(*key),
+            stored,
+            found
+        );
+    }
+    println!(
+        "checked: {} mismatches: {}",
+        report.checked,
+        report.mismatches.len()
+    );
+    if report.mismatches.is_empty() {
+        Ok(())
+    } else {
+        exit(2);
+    }
+}
+
+/// `MIN-MAX` か `N`
+fn parse_range<T: std::str::FromStr + Copy>(
+    s: &str,
+    name: &str,
+) -> Result<std::ops::RangeInclusive<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    let (min, max) = s.split_once('-').unwrap_or((s, s));
+    Ok(parse(Some(min), name)?..=parse(Some(max), name)?)
+}
+
+fn cmd_export(args: &[&str]) -> Result<(), String> {
+    let mut filter = DbFilter::default();
+    let mut eval = None;
+    let mut rest = Vec::new();
+    for a in args {
+        if let Some(s) = a.strip_prefix("--seeds=") {
+            filter.seeds = parse_range(s, "--seeds")?;
+        } else if let Some(s) = a.strip_prefix("--depth=") {
+            filter.depth = parse_range(s, "--depth")?;
+        } else if let Some(s) = a.strip_prefix("--eval=") {
+            let (name, depth) = s.split_once(':').unwrap_or((s, "0"));
+            eval = Some((name, parse::<usize>(Some(depth), "--eval depth")?));
+        } else if a.starts_with("--") {
+            return Err(format!("unknown option {a}"));
+        } else {
+            rest.push(*a);
+        }
+    }
+    let stealing = parse(rest.first().copied(), "STEAL")?;
+    let path = rest.get(1).ok_or("DB is required")?;
+    let out = rest.get(2).ok_or("OUT is required")?;
+    let columns = export_db(path, stealing, &filter).map_err(|e| e.to_string())?;
+    let mut arrays = columns.arrays();
+    if let Some((name, depth)) = eval {
+        let boards = columns.boards(stealing);
+        let pred = match name {
+            "diff" => predict(&mut ScoreDiffEvaluator::new(), boards, depth),
+            "pos" => predict(&mut ScorePosEvaluator::new(), boards, depth),
+            "nn4" => predict(&mut NeuralNet4Evaluator::new(stealing), boards, depth),
+            "nn6" => predict(&mut NeuralNet6Evaluator::new(stealing), boards, depth),
+            _ => match name.split_once('=') {
+                Some(("nn4", model)) => {
+                    let mut eval = NeuralNet4Evaluator::load(model).map_err(|e| e.to_string())?;
+                    predict(&mut eval, boards, depth)
+                }
+                Some(("nn6", model)) => {
+                    let mut eval = NeuralNet6Evaluator::load(model).map_err(|e| e.to_string())?;
+                    predict(&mut eval, boards, depth)
+                }
+                _ => return Err(format!("unknown evaluator {name}")),
+            },
+        };
+        arrays.push(("pred", NpyArray::new(&[pred.len()], &pred)));
+    }
+    if out.ends_with(".npz") {
+        let arrays = arrays.iter().map(|(n, a)| (*n, a)).collect::<Vec<_>>();
+        save_npz(out, &arrays).map_err(|e| e.to_string())?;
+    } else {
+        for (name, array) in arrays.iter() {
+            array
+                .save(&format!("{out}_{name}.npy"))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    println!("rows: {}", columns.len());
+    Ok(())
+}
+
+fn main() {
+    let args = args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let result = match args.first().copied() {
+        Some("stats") => cmd_stats(&args[1..]),
+        Some("merge") => cmd_merge(&args[1..]),
+        Some("diff") => cmd_diff(&args[1..]),
+        Some("query") => cmd_query(&args[1..]),
+        Some("verify") => cmd_verify(&args[1..]),
+        Some("export") => cmd_export(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        exit(1);
+    }
+}
@@ -0,0 +1,37 @@
+use std::env::args;
+
+use instant::Duration;
+
+use mancala_rust::{Board, compact_key, learn::*};
+
+fn main() {
+    let usage = "USAGE: <stealing> [checkpoint minutes] [memory]";
+    let stealing = args().nth(1).expect(usage).parse().expect(usage);
+    let minutes = args().nth(2).map_or(10, |s| s.parse().expect(usage));
+    // メモリーに置く局面数。超えた分は run としてディスクに書き出す
+    let memory = args().nth(3).map_or(1 << 26, |s| s.parse().expect(usage));
+    let name = db_name(stealing);
+    let runs = format!("{name}.runs");
+    let mut solver =
+        Solver::resume(&runs, stealing, memory, Duration::from_secs(minutes * 60)).unwrap();
+    if solver.data().runs() == 0 {
+        // 初めて読むときは、前に読み切った DB があれば取り込んでおく
+        match iter_load(&name) {
+            Ok(mut old) => {
+                old.header().check_stealing(stealing).unwrap();
+                for (pits, score, depth) in old.by_ref() {
+                    let key = compact_key(&Board::from_seeds(stealing, &pits));
+                    solver.data_mut().put(key, (score, depth)).unwrap();
+                }
+                old.finish().unwrap();
+                solver.save_checkpoint().unwrap();
+            }
+            Err(e) if e.is_not_found() => eprintln!("{name} is not exists ({e})"),
+            Err(e) => panic!("{e}"),
+        }
+    }
+    solver.set_progress(1 << 20);
+    let (score, depth) = solver.solve(&Board::new(stealing)).unwrap();
+    println!("score: {score} depth: {depth}");
+    println!("save: {:?}", solver.into_data().finish(&name));
+}
@@ -0,0 +1,20 @@
+use std::env::args;
+
+use mancala_rust::learn::sort_db;
+
+fn main() {
+    let usage = "USAGE: <stealing> <db> <sorted db> [memory]";
+    let args = args().skip(1).collect::<Vec<_>>();
+    assert!(args.len() == 3 || args.len() == 4, "{usage}");
+    let stealing = args[0].parse().expect(usage);
+    assert_ne!(args[1], args[2]);
+    // メモリーに置く局面数
+    let memory = args.get(3).map_or(1 << 26, |s| s.parse().expect(usage));
+    match sort_db(&args[1], &args[2], stealing, memory) {
+        Ok(n) => println!("{n} entries"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
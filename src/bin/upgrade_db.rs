@@ -0,0 +1,18 @@
+use std::env::args;
+
+use mancala_rust::learn::upgrade;
+
+fn main() {
+    let usage = "USAGE: <stealing> <old> <new>";
+    let args = args().skip(1).collect::<Vec<_>>();
+    assert_eq!(args.len(), 3, "{usage}");
+    let stealing = args[0].parse().expect(usage);
+    assert_ne!(args[1], args[2]);
+    match upgrade(&args[1], &args[2], stealing) {
+        Ok(n) => println!("{n} entries"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
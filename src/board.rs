@@ -136,6 +136,25 @@ impl Board {
         self.side
     }
 
+    /// 1 行で書いた盤面。先手、後手の順に穴とストアの石の数を並べ、手番側に `*` を付ける
+    ///
+    /// 例: `*4,4,4,4,4,4|0 4,4,4,4,4,4|0`
+    pub fn notation(&self) -> String {
+        let side = |side: Side| {
+            format!(
+                "{}{}|{}",
+                if self.side == side { "*" } else { "" },
+                self.seeds[side.as_usize()]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                self.score[side.as_usize()]
+            )
+        };
+        format!("{} {}", side(First), side(Second))
+    }
+
     pub fn stealing(&self) -> bool {
         self.stealing
     }
@@ -213,13 +232,18 @@ impl Board {
     }
 
     pub fn sow(&mut self, pos: usize) {
+        self.sow_and_steal(pos);
+    }
+
+    /// `sow` して、横取りした石の数を返す
+    fn sow_and_steal(&mut self, pos: usize) -> u8 {
         let num = self.seeds[self.side.as_usize()][pos];
         self.seeds[self.side.as_usize()][pos] = 0;
         let (side, end_pos) = self.move_seed(self.side, pos + 1, num as usize);
         if side == self.side {
             if end_pos == PIT {
                 if !self.is_finished() {
-                    return;
+                    return 0;
                 }
             } else if self.stealing && self.seeds[side.as_usize()][end_pos] == 1 {
                 let opposite_pos = PIT - 1 - end_pos;
@@ -228,10 +252,38 @@ impl Board {
                     self.seeds[side.as_usize()][end_pos] = 0;
                     self.seeds[side.turned().as_usize()][opposite_pos] = 0;
                     self.score[side.as_usize()] += opposite_num + 1;
+                    self.side = self.side.turned();
+                    return opposite_num;
                 }
             }
         }
         self.side = self.side.turned();
+        0
+    }
+
+    /// `side` 側の手番だったとして、一回蒔くだけで横取りできる穴があるかどうか
+    fn can_steal_by(&self, side: Side) -> bool {
+        if !self.stealing || self.is_finished() {
+            return false;
+        }
+        (0..PIT).any(|pos| {
+            if self.seeds[side.as_usize()][pos] == 0 {
+                return false;
+            }
+            let mut copied = self.clone();
+            copied.side = side;
+            copied.sow_and_steal(pos) > 0
+        })
+    }
+
+    /// 手番側が一回蒔くだけで横取りできるかどうか
+    pub fn can_steal(&self) -> bool {
+        self.can_steal_by(self.side)
+    }
+
+    /// 手番でない側が次のターンに一回蒔くだけで横取りできるかどうか
+    pub fn threatens_steal(&self) -> bool {
+        self.can_steal_by(self.side.turned())
     }
 
     /// 次のターンの盤面の一覧を返す
@@ -260,6 +312,32 @@ impl Board {
         set
     }
 
+    /// 次のターンの盤面のうち、横取りする・横取りを狙う・ゲームを終わらせるものの一覧を返す
+    /// 静止探索で使う
+    pub fn list_next_tactical(&self) -> FnvHashSet<Board> {
+        let mut set = FnvHashSet::default();
+        if self.is_finished() {
+            return set;
+        }
+        let mut stack = Vec::with_capacity(4);
+        stack.push(self.clone());
+        while let Some(board) = stack.pop() {
+            for (pos, &s) in board.seeds[board.side.as_usize()].iter().enumerate() {
+                if s == 0 {
+                    continue;
+                }
+                let mut copied = board.clone();
+                let stolen = copied.sow_and_steal(pos);
+                if copied.side == self.side {
+                    stack.push(copied);
+                } else if stolen > 0 || copied.is_finished() || copied.threatens_steal() {
+                    set.insert(copied);
+                }
+            }
+        }
+        set
+    }
+
     /// 次のターンの盤面とその盤面にするために必要な打ち手のペアの一覧を返す
     /// `std::collections::HashMap` を返すので、返り値を `iter` した順序は毎回異なることを期待して良い
     pub fn list_next_with_pos(&self) -> HashMap<Board, Vec<usize>> {
@@ -447,6 +525,31 @@ mod tests {
         assert_eq!(board.last_score(), (5 - 1) * 2);
     }
 
+    #[test]
+    fn steal_detection() {
+        // skip
+        if !(PIT == 6 && SEED == 4) {
+            return;
+        }
+        let board = Board::from_seeds(true, &[1, 0, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4]);
+        assert!(board.can_steal());
+        assert!(!board.threatens_steal());
+        let no_stealing = Board::from_seeds(false, &[1, 0, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4]);
+        assert!(!no_stealing.can_steal());
+        assert!(no_stealing.list_next_tactical().is_empty());
+
+        let mut copied = board.clone();
+        assert_eq!(copied.sow_and_steal(0), 4);
+        assert_eq!(copied.scores(), (5, 0));
+        assert!(
+            board
+                .list_next_tactical()
+                .iter()
+                .all(|next| board.list_next().contains(next))
+        );
+        assert!(board.list_next_tactical().contains(&copied));
+    }
+
     #[test]
     fn from_seeds() {
         let mut board = Board::new(true);
@@ -1,6 +1,10 @@
-use std::time::Instant;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Instant,
+};
 
-use super::Searcher;
+use super::{DumpFormat, DumpOptions, Searcher, build_ai};
 use crate::board::{Board, Side};
 
 pub struct Game {
@@ -9,6 +13,7 @@ pub struct Game {
     show_board: bool,
     ai_a: Box<dyn Searcher>,
     ai_b: Box<dyn Searcher>,
+    dump: Option<(String, DumpFormat)>,
 }
 
 impl Game {
@@ -19,6 +24,7 @@ impl Game {
             show_board: false,
             ai_a,
             ai_b,
+            dump: None,
         }
     }
 
@@ -26,6 +32,45 @@ impl Game {
         self.show_board = show;
     }
 
+    /// 手番ごとに探索木を `path` に番号を付けたファイルに書き出す
+    /// 形式は拡張子（`.dot` か `.json`）で決める
+    pub fn set_dump(&mut self, path: &str, options: DumpOptions) -> Result<(), String> {
+        let format = DumpFormat::from_path(path)
+            .ok_or_else(|| format!("{path}: extension must be .dot or .json"))?;
+        self.ai_a.set_dump(Some(options));
+        self.ai_b.set_dump(Some(options));
+        self.dump = Some((path.to_owned(), format));
+        Ok(())
+    }
+
+    /// tree.dot なら tree-003.dot のように手数を付ける
+    fn write_dump(&mut self, side: Side) {
+        let Some((path, format)) = &self.dump else {
+            return;
+        };
+        let tree = if side == Side::First {
+            self.ai_a.take_dump()
+        } else {
+            self.ai_b.take_dump()
+        };
+        let Some(tree) = tree else {
+            return;
+        };
+        let path = match path.rfind('.') {
+            Some(i) => format!("{}-{:03}{}", &path[..i], self.turn, &path[i..]),
+            None => format!("{path}-{:03}", self.turn),
+        };
+        // 最後の書き出しの失敗も拾うように flush まで行う
+        let result = File::create(&path).and_then(|f| {
+            let mut f = BufWriter::new(f);
+            tree.write(&mut f, *format)?;
+            f.flush()
+        });
+        if let Err(e) = result {
+            eprintln!("{path}: {e}");
+        }
+    }
+
     pub fn first_sow(&mut self, pos_list: &[usize]) {
         for pos in pos_list {
             self.board.sow(*pos);
@@ -45,6 +90,9 @@ impl Game {
             assert_eq!(self.board.side(), side);
             self.board.sow(pos);
         }
+        self.write_dump(side);
+        self.ai_a.observe(&self.board);
+        self.ai_b.observe(&self.board);
         if self.show_board {
             println!("{:?} ({}ms)", pos_list, time.elapsed().as_millis());
             println!("{}", self.board);
@@ -65,3 +113,58 @@ impl Game {
         }
     }
 }
+
+/// `a` から見た対戦成績
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MatchResult {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl MatchResult {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    /// 引き分けを半分の勝ちとした勝率
+    pub fn win_rate(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+}
+
+/// `build_ai` の仕様 `a` と `b` の AI を、先手と後手を入れ替えながら `games` 局対戦させる
+/// 毎局 AI を作り直すので、前の対局の探索木は持ち越さない
+pub fn play_match(stealing: bool, a: &str, b: &str, games: usize) -> Result<MatchResult, String> {
+    let mut result = MatchResult::default();
+    for i in 0..games {
+        let a_first = i % 2 == 0;
+        let (first, second) = if a_first { (a, b) } else { (b, a) };
+        let mut game = Game::new(
+            stealing,
+            build_ai(stealing, first)?,
+            build_ai(stealing, second)?,
+        );
+        let (s0, s1) = game.run();
+        let (sa, sb) = if a_first { (s0, s1) } else { (s1, s0) };
+        match sa.cmp(&sb) {
+            std::cmp::Ordering::Greater => result.wins += 1,
+            std::cmp::Ordering::Equal => result.draws += 1,
+            std::cmp::Ordering::Less => result.losses += 1,
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_swaps_sides() {
+        let result = play_match(true, "greedy", "random", 4).unwrap();
+        assert_eq!(result.games(), 4);
+        assert!(result.win_rate() >= 0.0 && result.win_rate() <= 1.0);
+        assert!(play_match(true, "greedy", "unknown", 1).is_err());
+    }
+}
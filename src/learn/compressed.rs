@@ -0,0 +1,528 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use fnv::FnvHashMap;
+use memmap2::Mmap;
+
+use super::db::{Checksum, DbError, DbHeader, HEADER_LEN, Layout, RECORD_LEN, read_header};
+use super::sorted::{DbLookup, SortedDb, sort_db};
+
+/// 圧縮の 1 ブロックに入る局面数。ブロックの中は先頭から順に読むので小さめにする
+pub const COMPRESSED_BLOCK: u64 = 256;
+
+/// ブロックごとの先頭のキーとファイル先頭からの位置を並べた索引のバイト数
+pub(super) fn compressed_index_len(entries: u64) -> u64 {
+    entries.div_ceil(COMPRESSED_BLOCK) * 16
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        buf.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    buf.push(x as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut x = 0;
+    for shift in (0..64).step_by(7) {
+        let b = *bytes.get(*pos)?;
+        *pos += 1;
+        x |= u64::from(b & 0x7f) << shift;
+        if b < 0x80 {
+            return Some(x);
+        }
+    }
+    None
+}
+
+fn bits(range: u8) -> u8 {
+    (u8::BITS - range.leading_zeros()) as u8
+}
+
+/// 1 ブロック分を書く
+///
+/// 先頭の 4 バイトが得点差の最小値とビット数、ターン数の最小値とビット数。
+/// 続けて最小値との差を詰めた値、最後に先頭のキーと差分のキーを varint で並べる。
+/// 値の位置がキーを読まずに決まるので、キーを順に読んで見つけた番号の値だけを取り出せる
+fn encode_block(entries: &[(u64, (i8, u8))], buf: &mut Vec<u8>) {
+    let min_score = entries.iter().map(|(_, (s, _))| *s).min().unwrap();
+    let max_score = entries.iter().map(|(_, (s, _))| *s).max().unwrap();
+    let min_depth = entries.iter().map(|(_, (_, d))| *d).min().unwrap();
+    let max_depth = entries.iter().map(|(_, (_, d))| *d).max().unwrap();
+    let score_bits = bits(max_score.wrapping_sub(min_score) as u8);
+    let depth_bits = bits(max_depth - min_depth);
+    buf.extend_from_slice(&[min_score as u8, score_bits, min_depth, depth_bits]);
+
+    let mut acc = 0u32;
+    let mut n = 0;
+    for (_, (s, d)) in entries.iter() {
+        let s = u32::from(s.wrapping_sub(min_score) as u8);
+        let d = u32::from(d - min_depth);
+        acc |= (s | d << score_bits) << n;
+        n += score_bits + depth_bits;
+        while n >= 8 {
+            buf.push(acc as u8);
+            acc >>= 8;
+            n -= 8;
+        }
+    }
+    if n > 0 {
+        buf.push(acc as u8);
+    }
+
+    let mut prev = entries[0].0;
+    write_varint(buf, prev);
+    for (key, _) in entries[1..].iter() {
+        write_varint(buf, key - prev);
+        prev = *key;
+    }
+}
+
+/// `encode_block` で書いた 1 ブロック
+struct Block<'a> {
+    bytes: &'a [u8],
+    len: usize,
+    min_score: i8,
+    score_bits: u8,
+    min_depth: u8,
+    depth_bits: u8,
+}
+
+impl<'a> Block<'a> {
+    fn new(bytes: &'a [u8], len: usize) -> Option<Block<'a>> {
+        let block = Block {
+            bytes,
+            len,
+            min_score: *bytes.first()? as i8,
+            score_bits: *bytes.get(1)?,
+            min_depth: *bytes.get(2)?,
+            depth_bits: *bytes.get(3)?,
+        };
+        if block.score_bits > 8 || block.depth_bits > 8 || block.keys_start() > bytes.len() {
+            return None;
+        }
+        Some(block)
+    }
+
+    fn keys_start(&self) -> usize {
+        4 + (self.len * usize::from(self.score_bits + self.depth_bits)).div_ceil(8)
+    }
+
+    fn value(&self, i: usize) -> (i8, u8) {
+        let width = usize::from(self.score_bits + self.depth_bits);
+        let bit = i * width;
+        let mut word = 0u32;
+        for (j, b) in self.bytes[4 + bit / 8..self.keys_start()]
+            .iter()
+            .take(3)
+            .enumerate()
+        {
+            word |= u32::from(*b) << (8 * j);
+        }
+        let word = word >> (bit % 8);
+        let s = word & ((1 << self.score_bits) - 1);
+        let d = (word >> self.score_bits) & ((1 << self.depth_bits) - 1);
+        (
+            self.min_score.wrapping_add(s as i8),
+            self.min_depth + d as u8,
+        )
+    }
+
+    /// キーを順に読む
+    fn keys(&self) -> impl Iterator<Item = Option<u64>> + '_ {
+        let mut pos = self.keys_start();
+        let mut key = 0u64;
+        (0..self.len).map(move |i| {
+            let x = read_varint(self.bytes, &mut pos)?;
+            key = if i == 0 { x } else { key.checked_add(x)? };
+            Some(key)
+        })
+    }
+
+    fn decode(&self) -> Option<Vec<(u64, (i8, u8))>> {
+        self.keys()
+            .enumerate()
+            .map(|(i, key)| Some((key?, self.value(i))))
+            .collect()
+    }
+}
+
+/// キーの差分と値を詰めたブロックを並べた DB をメモリーマップして引く
+pub struct CompressedDb {
+    header: DbHeader,
+    mmap: Mmap,
+}
+
+impl CompressedDb {
+    pub fn open<P: AsRef<Path>>(path: P, stealing: bool) -> Result<CompressedDb, DbError> {
+        let db = CompressedDb::map(path)?;
+        db.header.check_stealing(stealing)?;
+        Ok(db)
+    }
+
+    /// 手番のルールは確かめずに開く
+    pub(super) fn map<P: AsRef<Path>>(path: P) -> Result<CompressedDb, DbError> {
+        let file = File::open(path)?;
+        // SAFETY: 開いている間にファイルを書き換えないことを前提にする
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = DbHeader::read(&mut &mmap[..])?;
+        if header.layout != Layout::Compressed {
+            return Err(DbError::NotCompressed);
+        }
+        header.check_len(mmap.len() as u64)?;
+        Ok(CompressedDb { header, mmap })
+    }
+
+    pub fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.entries as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// ファイル全体のバイト数
+    pub fn file_len(&self) -> u64 {
+        self.mmap.len() as u64
+    }
+
+    pub(super) fn blocks(&self) -> usize {
+        self.header.entries.div_ceil(COMPRESSED_BLOCK) as usize
+    }
+
+    fn index_start(&self) -> usize {
+        self.mmap.len() - compressed_index_len(self.header.entries) as usize
+    }
+
+    fn index(&self, b: usize) -> (u64, usize) {
+        let start = self.index_start() + b * 16;
+        let key = u64::from_le_bytes(self.mmap[start..start + 8].try_into().unwrap());
+        let offset = u64::from_le_bytes(self.mmap[start + 8..start + 16].try_into().unwrap());
+        (key, offset as usize)
+    }
+
+    fn block(&self, b: usize) -> Option<Block<'_>> {
+        let start = self.index(b).1;
+        let end = if b + 1 < self.blocks() {
+            self.index(b + 1).1
+        } else {
+            self.index_start()
+        };
+        let len = (self.len() - b * COMPRESSED_BLOCK as usize).min(COMPRESSED_BLOCK as usize);
+        Block::new(self.mmap.get(start..end)?, len)
+    }
+
+    /// `b` 番目のブロックを全部読む。壊れていれば `None`
+    pub(super) fn decode_block(&self, b: usize) -> Option<Vec<(u64, (i8, u8))>> {
+        self.block(b)?.decode()
+    }
+
+    pub fn get(&self, key: u64) -> Option<(i8, u8)> {
+        // 先頭のキーが key 以下の最後のブロック
+        let (mut lo, mut hi) = (0, self.blocks());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.index(mid).0 <= key {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let block = self.block(lo.checked_sub(1)?)?;
+        for (i, k) in block.keys().enumerate() {
+            let k = k?;
+            if k == key {
+                return Some(block.value(i));
+            }
+            if k > key {
+                break;
+            }
+        }
+        None
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, (i8, u8))> + '_ {
+        (0..self.blocks()).flat_map(|b| self.decode_block(b).unwrap_or_default())
+    }
+
+    /// チェックサムと並び順、索引を確かめる
+    pub fn verify(&self) -> Result<(), DbError> {
+        let mut checksum = Checksum::default();
+        let mut prev = None;
+        let mut count = 0;
+        for b in 0..self.blocks() {
+            let entries = self.decode_block(b).ok_or(DbError::Truncated {
+                entries: self.header.entries,
+                len: self.file_len(),
+            })?;
+            if entries[0].0 != self.index(b).0 {
+                return Err(DbError::NotSorted);
+            }
+            for (key, value) in entries {
+                if prev.is_some_and(|prev| prev >= key) {
+                    return Err(DbError::NotSorted);
+                }
+                checksum.add(key, value);
+                prev = Some(key);
+                count += 1;
+            }
+        }
+        if count != self.header.entries {
+            return Err(DbError::Truncated {
+                entries: self.header.entries,
+                len: self.file_len(),
+            });
+        }
+        if checksum.value() != self.header.checksum {
+            return Err(DbError::ChecksumMismatch {
+                expected: self.header.checksum,
+                found: checksum.value(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl DbLookup for CompressedDb {
+    fn get(&self, key: u64) -> Option<(i8, u8)> {
+        CompressedDb::get(self, key)
+    }
+}
+
+/// `iter_load` で圧縮した DB を順に読む
+pub(super) struct Blocks {
+    db: CompressedDb,
+    next: usize,
+    buf: std::vec::IntoIter<(u64, (i8, u8))>,
+}
+
+impl Blocks {
+    pub(super) fn new(db: CompressedDb) -> Blocks {
+        Blocks {
+            db,
+            next: 0,
+            buf: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Iterator for Blocks {
+    type Item = (u64, (i8, u8));
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.buf.next() {
+                return Some(item);
+            }
+            if self.next == self.db.blocks() {
+                return None;
+            }
+            self.buf = self.db.decode_block(self.next)?.into_iter();
+            self.next += 1;
+        }
+    }
+}
+
+/// キーの順に並べて圧縮して書き出す
+pub fn save_compressed(
+    name: &str,
+    stealing: bool,
+    data: &FnvHashMap<u64, (i8, u8)>,
+) -> io::Result<()> {
+    let mut entries = data.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(key, _)| *key);
+    write_compressed_iter(name, stealing, entries).map(|_| ())
+}
+
+/// キーの昇順に並んだ局面を 1 ブロックずつ圧縮して書き出す。書いた局面数を返す
+/// メモリーに置くのは 1 ブロックと索引だけ
+pub(super) fn write_compressed_iter<I>(name: &str, stealing: bool, entries: I) -> io::Result<u64>
+where
+    I: IntoIterator<Item = (u64, (i8, u8))>,
+{
+    let mut f = BufWriter::new(File::create(name)?);
+    // 後で書き直す
+    DbHeader::new(stealing, Layout::Compressed, 0, 0).write(&mut f)?;
+    let mut checksum = Checksum::default();
+    let mut index = Vec::new();
+    let mut offset = HEADER_LEN;
+    let mut n: u64 = 0;
+    let mut block = Vec::with_capacity(COMPRESSED_BLOCK as usize);
+    let mut buf = Vec::new();
+    let mut write_block = |block: &mut Vec<(u64, (i8, u8))>, f: &mut BufWriter<File>| {
+        buf.clear();
+        encode_block(block, &mut buf);
+        f.write_all(&buf)?;
+        index.push((block[0].0, offset));
+        offset += buf.len() as u64;
+        block.clear();
+        io::Result::Ok(())
+    };
+    for (key, value) in entries {
+        checksum.add(key, value);
+        block.push((key, value));
+        n += 1;
+        if block.len() == COMPRESSED_BLOCK as usize {
+            write_block(&mut block, &mut f)?;
+        }
+    }
+    if !block.is_empty() {
+        write_block(&mut block, &mut f)?;
+    }
+    for (key, offset) in index {
+        f.write_all(&key.to_le_bytes())?;
+        f.write_all(&offset.to_le_bytes())?;
+    }
+    let mut f = f.into_inner().map_err(|e| e.into_error())?;
+    f.seek(SeekFrom::Start(0))?;
+    DbHeader::new(stealing, Layout::Compressed, n, checksum.value()).write(&mut f)?;
+    Ok(n)
+}
+
+/// 圧縮前後の大きさ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionReport {
+    pub entries: u64,
+    /// 1 局面 10 バイトで並べたときのバイト数
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl CompressionReport {
+    pub fn ratio(&self) -> f64 {
+        self.compressed_bytes as f64 / self.raw_bytes as f64
+    }
+
+    pub fn bytes_per_entry(&self) -> f64 {
+        self.compressed_bytes as f64 / self.entries as f64
+    }
+}
+
+/// DB を圧縮した DB に変換する
+///
+/// ソートした DB ならメモリーマップしてそのまま順に圧縮する。
+/// そうでなければ `memory` 局面ずつ外部ソートした `{new}.sorted` を作ってから圧縮する
+///
+/// `make_learn_data true 1 4000` で作った p6s4_true.dat (69,255,706 局面, 693 MB) は
+/// 162 MB になった。比は 0.234、1 局面あたり 2.34 バイト
+pub fn compress_db(
+    old: &str,
+    new: &str,
+    stealing: bool,
+    memory: usize,
+) -> Result<CompressionReport, DbError> {
+    let sorted = if read_header(old)?.layout == Layout::Sorted {
+        None
+    } else {
+        let tmp = format!("{new}.sorted");
+        sort_db(old, &tmp, stealing, memory)?;
+        Some(tmp)
+    };
+    let db = SortedDb::open(sorted.as_deref().unwrap_or(old), stealing)?;
+    let entries = write_compressed_iter(new, stealing, db.iter())?;
+    drop(db);
+    if let Some(tmp) = sorted {
+        fs::remove_file(tmp)?;
+    }
+    Ok(CompressionReport {
+        entries,
+        raw_bytes: HEADER_LEN + entries * RECORD_LEN,
+        compressed_bytes: fs::metadata(new)?.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use rand_pcg::Mcg128Xsl64;
+
+    use super::*;
+    use crate::learn::test_utils::temp_path;
+    use crate::learn::{iter_load, save};
+
+    #[test]
+    fn varint() {
+        let mut buf = Vec::new();
+        for x in [0, 1, 127, 128, 300, u64::MAX] {
+            buf.clear();
+            write_varint(&mut buf, x);
+            let mut pos = 0;
+            assert_eq!(read_varint(&buf, &mut pos), Some(x));
+            assert_eq!(pos, buf.len());
+        }
+    }
+
+    #[test]
+    fn compress_and_lookup() {
+        let mut random = Mcg128Xsl64::new(1);
+        let data = (0..3000)
+            .map(|_| {
+                let key = random.random::<u64>() >> random.random_range(0..40);
+                let value = match random.random_range(0..3) {
+                    0 => (random.random(), random.random()),
+                    _ => (random.random_range(-3..3), random.random_range(0..8)),
+                };
+                (key, value)
+            })
+            .collect::<FnvHashMap<u64, (i8, u8)>>();
+        let plain = temp_path("plain-c.dat");
+        let compressed = temp_path("compressed.dat");
+        save(&plain, true, &data).unwrap();
+        let report = compress_db(&plain, &compressed, true, 1000).unwrap();
+        assert_eq!(report.entries, data.len() as u64);
+        assert!(report.ratio() < 1.0, "{report:?}");
+
+        let db = CompressedDb::open(&compressed, true).unwrap();
+        db.verify().unwrap();
+        for (key, value) in data.iter() {
+            assert_eq!(db.get(*key), Some(*value));
+            assert_eq!(db.get(key ^ 1), data.get(&(key ^ 1)).copied());
+        }
+        assert_eq!(db.iter().collect::<FnvHashMap<_, _>>(), data);
+
+        // 今までと同じように順に読める
+        let mut load = iter_load(&compressed).unwrap();
+        assert_eq!(load.by_ref().count(), data.len());
+        load.finish().unwrap();
+        assert_eq!(crate::learn::load(&compressed, true).unwrap(), data);
+        assert!(!Path::new(&format!("{compressed}.sorted")).exists());
+
+        // ソートした DB からはそのまま圧縮し、同じファイルになる
+        let sorted = temp_path("sorted-c.dat");
+        let compressed2 = temp_path("compressed2.dat");
+        crate::learn::save_sorted(&sorted, true, &data).unwrap();
+        assert_eq!(
+            compress_db(&sorted, &compressed2, true, 1000).unwrap(),
+            report
+        );
+        assert_eq!(
+            std::fs::read(&compressed).unwrap(),
+            std::fs::read(&compressed2).unwrap()
+        );
+
+        std::fs::remove_file(&plain).unwrap();
+        std::fs::remove_file(&compressed).unwrap();
+        std::fs::remove_file(&sorted).unwrap();
+        std::fs::remove_file(&compressed2).unwrap();
+    }
+
+    #[test]
+    fn constant_values() {
+        // 値が全部同じブロックは値に 1 bit も使わない
+        let data = (0..1000u64).map(|i| (i * 3, (0, 0))).collect();
+        let path = temp_path("constant.dat");
+        save_compressed(&path, false, &data).unwrap();
+        let db = CompressedDb::open(&path, false).unwrap();
+        db.verify().unwrap();
+        assert_eq!(db.get(300), Some((0, 0)));
+        assert_eq!(db.get(301), None);
+        assert!(db.file_len() < HEADER_LEN + 1000 + compressed_index_len(1000) + 4 * 6);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_pcg::Mcg128Xsl64;
+
+use super::compressed::CompressedDb;
+use super::db::{
+    Checksum, DbError, DbHeader, HEADER_LEN, Layout, Load, RECORD_LEN, iter_load, read_header,
+};
+use super::npy::DbFilter;
+use super::sorted::SortedDb;
+use super::utils::ShuffledStream;
+use crate::board::from_compact_key;
+
+/// 学習用、検証用、テスト用のどれに使う局面か
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Split {
+    Train,
+    Validation,
+    Test,
+}
+
+/// 局面のキーのハッシュで分ける。読む順番やファイルの並べ方によらない
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Splitter {
+    pub validation: f64,
+    pub test: f64,
+    /// 変えると別の分け方になる
+    pub salt: u64,
+}
+
+impl Default for Splitter {
+    fn default() -> Splitter {
+        Splitter {
+            validation: 0.05,
+            test: 0.05,
+            salt: 0,
+        }
+    }
+}
+
+/// キーを [0, 1) に一様にばらまく
+fn unit_hash(key: u64, salt: u64) -> f64 {
+    // splitmix64 の最後の混ぜ方
+    let mut h = key ^ salt;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// `sample` で間引くときのハッシュを分け方と独立にするために混ぜる
+const SAMPLE_SALT: u64 = 0x6a09_e667_f3bc_c908;
+
+impl Splitter {
+    pub fn split_of(&self, key: u64) -> Split {
+        let x = unit_hash(key, self.salt);
+        if x < self.test {
+            Split::Test
+        } else if x < self.test + self.validation {
+            Split::Validation
+        } else {
+            Split::Train
+        }
+    }
+}
+
+/// DB から学習データを読む設定。同じ設定なら同じ順番で同じ局面を返す
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    pub path: String,
+    pub stealing: bool,
+    pub filter: DbFilter,
+    pub split: Splitter,
+    /// シャッフルのために先読みする局面数
+    /// ソートした DB や圧縮した DB はキーの順に並んでいるので、これとは別にブロックの順番と
+    /// ブロックの中の順番も並べ替える。順不同の DB で 1 ならファイルの順のまま
+    pub buffer: usize,
+    pub seed: u64,
+    /// 返す局面の割合。キーのハッシュで間引くので、DB の並べ方によらず全体から偏りなく選ぶ
+    pub sample: f64,
+}
+
+impl Dataset {
+    pub fn new(path: &str, stealing: bool) -> Dataset {
+        Dataset {
+            path: path.to_string(),
+            stealing,
+            filter: DbFilter::default(),
+            split: Splitter::default(),
+            buffer: 1 << 20,
+            seed: 0,
+            sample: 1.0,
+        }
+    }
+
+    /// `part` の局面を 1 周分、`epoch` ごとに違う順番で返す
+    pub fn epoch(&self, part: Split, epoch: u64) -> Result<Epoch, DbError> {
+        let seed =
+            (self.seed ^ epoch.wrapping_mul(0x9e37_79b9_7f4a_7c15)).wrapping_add(part as u64);
+        let source = match read_header(&self.path)?.layout {
+            Layout::Plain => {
+                let load = iter_load(&self.path)?;
+                load.header().check_stealing(self.stealing)?;
+                Source::Load(load)
+            }
+            Layout::Sorted => Source::Blocks(ShuffledBlocks::new(
+                BlockDb::Sorted(SortedDb::open(&self.path, self.stealing)?),
+                Mcg128Xsl64::seed_from_u64(!seed),
+            )),
+            Layout::Compressed => Source::Blocks(ShuffledBlocks::new(
+                BlockDb::Compressed(CompressedDb::open(&self.path, self.stealing)?),
+                Mcg128Xsl64::seed_from_u64(!seed),
+            )),
+        };
+        let rows = Rows {
+            source: Some(source),
+            filter: self.filter.clone(),
+            split: self.split,
+            part,
+            sample: self.sample,
+            error: None,
+        };
+        Ok(ShuffledStream::new(
+            rows,
+            Mcg128Xsl64::seed_from_u64(seed),
+            self.buffer,
+        ))
+    }
+}
+
+pub type Epoch = ShuffledStream<Rows, Mcg128Xsl64>;
+
+/// ブロックごとに読める、キーの順に並んだ DB
+enum BlockDb {
+    Sorted(SortedDb),
+    Compressed(CompressedDb),
+}
+
+impl BlockDb {
+    fn header(&self) -> &DbHeader {
+        match self {
+            BlockDb::Sorted(db) => db.header(),
+            BlockDb::Compressed(db) => db.header(),
+        }
+    }
+
+    fn blocks(&self) -> usize {
+        match self {
+            BlockDb::Sorted(db) => db.blocks(),
+            BlockDb::Compressed(db) => db.blocks(),
+        }
+    }
+
+    fn block(&self, b: usize) -> Option<Vec<(u64, (i8, u8))>> {
+        match self {
+            BlockDb::Sorted(db) => Some(db.block(b)),
+            BlockDb::Compressed(db) => db.decode_block(b),
+        }
+    }
+}
+
+/// ブロックを乱数で決めた順に読み、ブロックの中も並べ替えて返す
+///
+/// キーの順に並んだ DB をファイルの順に読むと、先読みしてもキーの近い局面ばかりが続くので、
+/// 索引を使って DB 全体を並べ替える。チェックサムは並び順によらないので最後に確かめられる
+struct ShuffledBlocks {
+    db: BlockDb,
+    random: Mcg128Xsl64,
+    order: Vec<usize>,
+    next: usize,
+    buf: Vec<(u64, (i8, u8))>,
+    read: u64,
+    checksum: Checksum,
+}
+
+impl ShuffledBlocks {
+    fn new(db: BlockDb, mut random: Mcg128Xsl64) -> ShuffledBlocks {
+        let mut order = (0..db.blocks()).collect::<Vec<_>>();
+        order.shuffle(&mut random);
+        ShuffledBlocks {
+            db,
+            random,
+            order,
+            next: 0,
+            buf: Vec::new(),
+            read: 0,
+            checksum: Checksum::default(),
+        }
+    }
+
+    fn next_record(&mut self) -> Option<(u64, (i8, u8))> {
+        while self.buf.is_empty() {
+            let &b = self.order.get(self.next)?;
+            self.buf = self.db.block(b)?;
+            self.buf.shuffle(&mut self.random);
+            self.next += 1;
+        }
+        let (key, value) = self.buf.pop()?;
+        self.read += 1;
+        self.checksum.add(key, value);
+        Some((key, value))
+    }
+
+    /// 全部読めたかとチェックサムを確かめる
+    fn finish(self) -> Result<(), DbError> {
+        let header = self.db.header();
+        if self.read != header.entries {
+            return Err(DbError::Truncated {
+                entries: header.entries,
+                len: HEADER_LEN + self.read * RECORD_LEN,
+            });
+        }
+        if self.checksum.value() != header.checksum {
+            return Err(DbError::ChecksumMismatch {
+                expected: header.checksum,
+                found: self.checksum.value(),
+            });
+        }
+        Ok(())
+    }
+}
+
+enum Source {
+    /// 順不同の DB はファイルの順に読む
+    Load(Load),
+    Blocks(ShuffledBlocks),
+}
+
+impl Source {
+    fn next_record(&mut self) -> Option<(u64, (i8, u8))> {
+        match self {
+            Source::Load(load) => load.next_record(),
+            Source::Blocks(blocks) => blocks.next_record(),
+        }
+    }
+
+    fn finish(self) -> Result<(), DbError> {
+        match self {
+            Source::Load(load) => load.finish(),
+            Source::Blocks(blocks) => blocks.finish(),
+        }
+    }
+}
+
+/// 条件に合う局面を返す。順不同の DB はファイルの順、キーの順に並んだ DB はブロックごとに並べ替えた順
+pub struct Rows {
+    source: Option<Source>,
+    filter: DbFilter,
+    split: Splitter,
+    part: Split,
+    sample: f64,
+    error: Option<DbError>,
+}
+
+impl Rows {
+    /// DB が壊れていて途中で終わったときのエラー
+    pub fn error(&self) -> Option<&DbError> {
+        self.error.as_ref()
+    }
+}
+
+impl Iterator for Rows {
+    type Item = ([u8; 12], i8, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let source = self.source.as_mut()?;
+            let Some((key, (score, depth))) = source.next_record() else {
+                if let Err(e) = self.source.take().unwrap().finish() {
+                    self.error = Some(e);
+                }
+                return None;
+            };
+            if self.split.split_of(key) != self.part
+                || (self.sample < 1.0
+                    && unit_hash(key, self.split.salt ^ SAMPLE_SALT) >= self.sample)
+            {
+                continue;
+            }
+            let pits = from_compact_key(key);
+            if self.filter.matches(&pits, depth) {
+                return Some((pits, score, depth));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fnv::FnvHashMap;
+
+    use super::*;
+    use crate::learn::test_utils::temp_path;
+    use crate::learn::{save, save_compressed, save_sorted};
+
+    fn data() -> FnvHashMap<u64, (i8, u8)> {
+        (0..3000u64)
+            .map(|i| {
+                let mut pits = [0u8; 12];
+                for (j, p) in pits.iter_mut().enumerate() {
+                    *p = ((i >> j) & 1) as u8 + (i % 3 == j as u64 % 3) as u8;
+                }
+                let board = crate::Board::from_seeds(true, &pits);
+                (
+                    crate::compact_key(&board),
+                    ((i % 7) as i8 - 3, (i % 11) as u8),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_and_epochs() {
+        let data = data();
+        let plain = temp_path("dataset.dat");
+        let compressed = temp_path("dataset-c.dat");
+        save(&plain, true, &data).unwrap();
+        save_compressed(&compressed, true, &data).unwrap();
+        let mut dataset = Dataset::new(&plain, true);
+        dataset.buffer = 100;
+        dataset.seed = 3;
+
+        // 3 つに重ならずに分かれ、ファイルの形式によらない
+        let mut all = Vec::new();
+        let mut sizes = Vec::new();
+        for part in [Split::Train, Split::Validation, Split::Test] {
+            let mut rows = dataset.epoch(part, 0).unwrap().collect::<Vec<_>>();
+            sizes.push(rows.len());
+            rows.sort_unstable();
+            let mut other = Dataset {
+                path: compressed.clone(),
+                ..dataset.clone()
+            }
+            .epoch(part, 5)
+            .unwrap()
+            .collect::<Vec<_>>();
+            other.sort_unstable();
+            assert_eq!(rows, other);
+            all.extend(rows);
+        }
+        assert_eq!(all.len(), data.len());
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), data.len());
+        assert!(sizes[1] > 50 && sizes[2] > 50 && sizes[0] > 2000);
+
+        // 同じ seed と epoch なら同じ順番、epoch が違えば違う順番
+        let a = dataset.epoch(Split::Train, 1).unwrap().collect::<Vec<_>>();
+        let b = dataset.epoch(Split::Train, 1).unwrap().collect::<Vec<_>>();
+        let c = dataset.epoch(Split::Train, 2).unwrap().collect::<Vec<_>>();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        dataset.filter = DbFilter {
+            seeds: 0..=usize::MAX,
+            depth: 0..=2,
+        };
+        let mut epoch = dataset.epoch(Split::Train, 0).unwrap();
+        assert!(epoch.by_ref().all(|(_, _, depth)| depth <= 2));
+        assert!(epoch.get_ref().error().is_none());
+
+        std::fs::remove_file(&plain).unwrap();
+        std::fs::remove_file(&compressed).unwrap();
+    }
+
+    #[test]
+    fn shuffle_sorted_blocks() {
+        let data = data();
+        let sorted = temp_path("dataset-s.dat");
+        let compressed = temp_path("dataset-sc.dat");
+        save_sorted(&sorted, true, &data).unwrap();
+        save_compressed(&compressed, true, &data).unwrap();
+        for path in [&sorted, &compressed] {
+            let mut dataset = Dataset::new(path, true);
+            dataset.split.validation = 0.0;
+            dataset.split.test = 0.0;
+            // 先読みしなくても、キーの順ではなく DB 全体から並べ替えた順になる
+            dataset.buffer = 1;
+            let keys = |epoch| {
+                dataset
+                    .epoch(Split::Train, epoch)
+                    .unwrap()
+                    .map(|(pits, _, _)| crate::compact_key(&crate::Board::from_seeds(true, &pits)))
+                    .collect::<Vec<_>>()
+            };
+            let a = keys(0);
+            assert!(a.windows(2).any(|w| w[0] > w[1]));
+            let mut all = a.clone();
+            all.sort_unstable();
+            let mut expected = data.keys().copied().collect::<Vec<_>>();
+            expected.sort_unstable();
+            assert_eq!(all, expected);
+            // 最初に読むブロックが epoch ごとに変わる
+            let firsts = (0..8).map(|epoch| keys(epoch)[0]).collect::<Vec<_>>();
+            assert!(firsts.iter().any(|&k| k > expected[expected.len() / 2]));
+            assert!(firsts.iter().any(|&k| k < expected[expected.len() / 2]));
+
+            let mut epoch = dataset.epoch(Split::Train, 0).unwrap();
+            assert_eq!(epoch.by_ref().count(), data.len());
+            assert!(epoch.get_ref().error().is_none());
+        }
+        std::fs::remove_file(&sorted).unwrap();
+        std::fs::remove_file(&compressed).unwrap();
+    }
+
+    #[test]
+    fn sample_whole_db() {
+        let data = data();
+        let sorted = temp_path("dataset-sample.dat");
+        save_sorted(&sorted, true, &data).unwrap();
+        let mut dataset = Dataset::new(&sorted, true);
+        dataset.split.validation = 0.0;
+        dataset.split.test = 0.0;
+        let key = |(pits, _, _): ([u8; 12], i8, u8)| {
+            crate::compact_key(&crate::Board::from_seeds(true, &pits))
+        };
+        let mut all = dataset
+            .epoch(Split::Train, 0)
+            .unwrap()
+            .map(key)
+            .collect::<Vec<_>>();
+        all.sort_unstable();
+
+        dataset.sample = 0.25;
+        let mut sample = dataset
+            .epoch(Split::Train, 0)
+            .unwrap()
+            .map(key)
+            .collect::<Vec<_>>();
+        sample.sort_unstable();
+        assert!((600..900).contains(&sample.len()), "{}", sample.len());
+        assert!(sample.iter().all(|k| all.binary_search(k).is_ok()));
+        // 先頭に偏らず、キーの範囲全体から選ばれる
+        assert!(sample[0] < all[all.len() / 10]);
+        assert!(sample[sample.len() - 1] > all[all.len() * 9 / 10]);
+        // epoch によらず同じ局面を選ぶ
+        let mut again = dataset
+            .epoch(Split::Train, 7)
+            .unwrap()
+            .map(key)
+            .collect::<Vec<_>>();
+        again.sort_unstable();
+        assert_eq!(sample, again);
+        std::fs::remove_file(&sorted).unwrap();
+    }
+
+    #[test]
+    fn finite_streams() {
+        let path = temp_path("repeat.dat");
+        let data = data();
+        save(&path, true, &data).unwrap();
+        let mut repeat = crate::learn::RepeatLod::new(&path, 3).unwrap();
+        assert_eq!(repeat.by_ref().count(), data.len() * 3);
+        assert_eq!(repeat.epoch(), 3);
+        assert!(repeat.error().is_none());
+
+        // 先読みより短くても全部出して終わる
+        let stream = ShuffledStream::new(0..10, Mcg128Xsl64::new(1), 100);
+        let mut items = stream.collect::<Vec<_>>();
+        items.sort_unstable();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
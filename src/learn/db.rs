@@ -0,0 +1,507 @@
+use std::fmt;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use fnv::{FnvHashMap, FnvHasher};
+
+use super::compressed::{Blocks, CompressedDb, compressed_index_len};
+use super::sorted::block_index_len;
+use crate::board::{PIT, SEED};
+use crate::from_compact_key;
+
+const MAGIC: &[u8; 4] = b"MCDB";
+const VERSION: u8 = 1;
+/// ヘッダーのバイト数
+pub const HEADER_LEN: u64 = 28;
+/// キー 8 バイトと値 2 バイト
+pub(super) const RECORD_LEN: u64 = 10;
+
+/// 局面をキーにする方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyScheme {
+    /// `compact_key`。穴 1 つを 5 bit で表す
+    Compact5 = 1,
+}
+
+/// ヘッダーの後ろの並べ方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// 局面を順不同に並べる
+    Plain = 0,
+    /// キーの順に並べ、後ろにブロックの索引を置く。`SortedDb` で開ける
+    Sorted = 1,
+    /// キーの順に並べて圧縮したブロックと索引を置く。`CompressedDb` で開ける
+    Compressed = 2,
+}
+
+impl Layout {
+    fn from_u8(layout: u8) -> Result<Layout, DbError> {
+        match layout {
+            0 => Ok(Layout::Plain),
+            1 => Ok(Layout::Sorted),
+            2 => Ok(Layout::Compressed),
+            _ => Err(DbError::UnknownLayout(layout)),
+        }
+    }
+}
+
+/// DB ファイルの先頭に置くヘッダー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbHeader {
+    pub version: u8,
+    pub pit: u8,
+    pub seed: u8,
+    pub stealing: bool,
+    pub key_scheme: KeyScheme,
+    pub layout: Layout,
+    pub entries: u64,
+    pub checksum: u64,
+}
+
+#[derive(Debug)]
+pub enum DbError {
+    Io(io::Error),
+    /// ヘッダーのない古い形式。`upgrade` で変換できる
+    Legacy,
+    BadMagic,
+    UnsupportedVersion(u8),
+    PitSeedMismatch {
+        pit: u8,
+        seed: u8,
+    },
+    StealingMismatch {
+        expected: bool,
+    },
+    UnknownKeyScheme(u8),
+    UnknownLayout(u8),
+    /// ソートされていない DB を `SortedDb` で開こうとした
+    NotSorted,
+    /// 圧縮していない DB を `CompressedDb` で開こうとした
+    NotCompressed,
+    /// ファイルの大きさがヘッダーの局面数と合わない
+    Truncated {
+        entries: u64,
+        len: u64,
+    },
+    ChecksumMismatch {
+        expected: u64,
+        found: u64,
+    },
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io(e) => write!(f, "{e}"),
+            DbError::Legacy => write!(f, "legacy DB without header, convert it with upgrade"),
+            DbError::BadMagic => write!(f, "not a DB file"),
+            DbError::UnsupportedVersion(v) => write!(f, "unsupported version {v}"),
+            DbError::PitSeedMismatch { pit, seed } => {
+                write!(
+                    f,
+                    "DB is for PIT={pit} SEED={seed}, expected PIT={PIT} SEED={SEED}"
+                )
+            }
+            DbError::StealingMismatch { expected } => {
+                write!(f, "DB is for stealing={}, expected {expected}", !expected)
+            }
+            DbError::UnknownKeyScheme(k) => write!(f, "unknown key scheme {k}"),
+            DbError::UnknownLayout(l) => write!(f, "unknown layout {l}"),
+            DbError::NotSorted => write!(f, "DB is not sorted, convert it with sort_db"),
+            DbError::NotCompressed => {
+                write!(f, "DB is not compressed, convert it with compress_db")
+            }
+            DbError::Truncated { entries, len } => {
+                write!(f, "{entries} entries in header but file is {len} bytes")
+            }
+            DbError::ChecksumMismatch { expected, found } => {
+                write!(f, "checksum mismatch: {expected:016x} != {found:016x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<io::Error> for DbError {
+    fn from(e: io::Error) -> DbError {
+        DbError::Io(e)
+    }
+}
+
+impl DbError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, DbError::Io(e) if e.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+/// 1 局面ずつのハッシュの和。並び順によらないので、同じ中身なら書き出し方が違っても一致する
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checksum(u64);
+
+impl Checksum {
+    pub fn add(&mut self, key: u64, value: (i8, u8)) {
+        let mut h = FnvHasher::default();
+        h.write(&key.to_le_bytes());
+        h.write(&[value.0 as u8, value.1]);
+        self.0 = self.0.wrapping_add(h.finish());
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl DbHeader {
+    pub fn new(stealing: bool, layout: Layout, entries: u64, checksum: u64) -> DbHeader {
+        DbHeader {
+            version: VERSION,
+            pit: PIT as u8,
+            seed: SEED,
+            stealing,
+            key_scheme: KeyScheme::Compact5,
+            layout,
+            entries,
+            checksum,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[
+            self.version,
+            self.pit,
+            self.seed,
+            u8::from(self.stealing),
+            self.key_scheme as u8,
+            self.layout as u8,
+            0,
+            0,
+        ])?;
+        writer.write_all(&self.entries.to_le_bytes())?;
+        writer.write_all(&self.checksum.to_le_bytes())
+    }
+
+    /// PIT や SEED、キーの作り方がこのビルドと合わなければエラーにする
+    pub fn read<R: Read>(reader: &mut R) -> Result<DbHeader, DbError> {
+        let mut buf = [0; HEADER_LEN as usize];
+        reader.read_exact(&mut buf)?;
+        if &buf[..4] != MAGIC {
+            return Err(DbError::BadMagic);
+        }
+        let [version, pit, seed, stealing, key_scheme, layout] =
+            [buf[4], buf[5], buf[6], buf[7], buf[8], buf[9]];
+        if version != VERSION {
+            return Err(DbError::UnsupportedVersion(version));
+        }
+        if usize::from(pit) != PIT || seed != SEED {
+            return Err(DbError::PitSeedMismatch { pit, seed });
+        }
+        if key_scheme != KeyScheme::Compact5 as u8 {
+            return Err(DbError::UnknownKeyScheme(key_scheme));
+        }
+        Ok(DbHeader {
+            version,
+            pit,
+            seed,
+            stealing: stealing != 0,
+            key_scheme: KeyScheme::Compact5,
+            layout: Layout::from_u8(layout)?,
+            entries: u64::from_le_bytes(buf[12..20].try_into().unwrap()),
+            checksum: u64::from_le_bytes(buf[20..28].try_into().unwrap()),
+        })
+    }
+
+    /// ファイルの大きさがヘッダーと合うか確かめる。圧縮した DB は索引が入るかどうかだけ見る
+    pub(super) fn check_len(&self, len: u64) -> Result<(), DbError> {
+        let records = match self.layout {
+            Layout::Compressed => Some(0),
+            _ => self.entries.checked_mul(RECORD_LEN),
+        };
+        let index = match self.layout {
+            Layout::Plain => 0,
+            Layout::Sorted => block_index_len(self.entries),
+            Layout::Compressed => compressed_index_len(self.entries),
+        };
+        let expected = records.and_then(|r| r.checked_add(HEADER_LEN)?.checked_add(index));
+        let ok = match (self.layout, expected) {
+            (_, None) => false,
+            (Layout::Compressed, Some(min)) => min <= len,
+            (_, Some(expected)) => expected == len,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(DbError::Truncated {
+                entries: self.entries,
+                len,
+            })
+        }
+    }
+
+    pub fn check_stealing(&self, stealing: bool) -> Result<(), DbError> {
+        if self.stealing == stealing {
+            Ok(())
+        } else {
+            Err(DbError::StealingMismatch { expected: stealing })
+        }
+    }
+}
+
+/// ヘッダーを読んでファイルの大きさまで確かめる
+fn open<P: AsRef<Path>>(path: P) -> Result<(DbHeader, BufReader<File>), DbError> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut f = BufReader::new(file);
+    let header = match DbHeader::read(&mut f) {
+        Err(DbError::BadMagic) => {
+            // 古い形式は先頭が局面数
+            f.seek(SeekFrom::Start(0))?;
+            let mut buf = [0; 8];
+            f.read_exact(&mut buf)?;
+            let n = u64::from_le_bytes(buf);
+            return Err(
+                if n.checked_mul(RECORD_LEN).and_then(|b| b.checked_add(8)) == Some(len) {
+                    DbError::Legacy
+                } else {
+                    DbError::BadMagic
+                },
+            );
+        }
+        r => r?,
+    };
+    header.check_len(len)?;
+    Ok((header, f))
+}
+
+pub(super) fn read_record<R: Read>(f: &mut R) -> io::Result<(u64, (i8, u8))> {
+    let mut buf = [0; RECORD_LEN as usize];
+    f.read_exact(&mut buf)?;
+    let key = u64::from_le_bytes(buf[..8].try_into().unwrap());
+    Ok((key, (buf[8] as i8, buf[9])))
+}
+
+pub(super) fn write_record<W: Write>(f: &mut W, key: u64, value: (i8, u8)) -> io::Result<()> {
+    f.write_all(&key.to_le_bytes())?;
+    f.write_all(&[value.0 as u8, value.1])
+}
+
+pub fn load(name: &str, stealing: bool) -> Result<FnvHashMap<u64, (i8, u8)>, DbError> {
+    let mut db = iter_load(name)?;
+    db.header.check_stealing(stealing)?;
+    let n = db.header.entries as usize;
+    let cap = 7 * (n / 7).next_power_of_two();
+    let mut data = FnvHashMap::with_capacity_and_hasher(cap, Default::default());
+    while let Some((key, value)) = db.next_record() {
+        data.insert(key, value);
+    }
+    db.finish()?;
+    Ok(data)
+}
+
+/// ヘッダーだけを読む
+pub fn read_header<P: AsRef<Path>>(path: P) -> Result<DbHeader, DbError> {
+    Ok(open(path)?.0)
+}
+
+/// `load` と同じだが、ファイルがなければ空の DB から始める
+pub fn load_or_default(name: &str, stealing: bool) -> Result<FnvHashMap<u64, (i8, u8)>, DbError> {
+    match load(name, stealing) {
+        Err(e) if e.is_not_found() => {
+            eprintln!("{name} is not exists ({e})");
+            Ok(FnvHashMap::with_capacity_and_hasher(
+                7 * 1024,
+                Default::default(),
+            ))
+        }
+        r => r,
+    }
+}
+
+pub fn save(name: &str, stealing: bool, data: &FnvHashMap<u64, (i8, u8)>) -> io::Result<()> {
+    let mut checksum = Checksum::default();
+    for (key, value) in data.iter() {
+        checksum.add(*key, *value);
+    }
+    let mut f = BufWriter::new(File::create(name)?);
+    DbHeader::new(stealing, Layout::Plain, data.len() as u64, checksum.value()).write(&mut f)?;
+    for (key, value) in data.iter() {
+        write_record(&mut f, *key, *value)?;
+    }
+    f.flush()
+}
+
+/// ヘッダーのない古い形式を変換する。変換した局面数を返す
+pub fn upgrade(old: &str, new: &str, stealing: bool) -> Result<u64, DbError> {
+    let mut r = BufReader::new(File::open(old)?);
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    let n = u64::from_le_bytes(buf);
+    let mut f = BufWriter::new(File::create(new)?);
+    // 後で書き直す
+    DbHeader::new(stealing, Layout::Plain, n, 0).write(&mut f)?;
+    let mut checksum = Checksum::default();
+    for _ in 0..n {
+        let (key, value) = read_record(&mut r)?;
+        checksum.add(key, value);
+        write_record(&mut f, key, value)?;
+    }
+    let mut f = f.into_inner().map_err(|e| e.into_error())?;
+    f.seek(SeekFrom::Start(0))?;
+    DbHeader::new(stealing, Layout::Plain, n, checksum.value()).write(&mut f)?;
+    Ok(n)
+}
+
+/// どの並べ方の DB でも先頭から順に読む
+pub fn iter_load<P: AsRef<Path>>(path: P) -> Result<Load, DbError> {
+    let (header, f) = open(&path)?;
+    let source = match header.layout {
+        Layout::Plain | Layout::Sorted => Source::Records(f),
+        Layout::Compressed => Source::Blocks(Blocks::new(CompressedDb::map(&path)?)),
+    };
+    Ok(Load {
+        header,
+        read: 0,
+        checksum: Checksum::default(),
+        source,
+    })
+}
+
+enum Source {
+    /// 局面がヘッダーの直後に同じ形で並んでいる
+    Records(BufReader<File>),
+    Blocks(Blocks),
+}
+
+pub struct Load {
+    header: DbHeader,
+    read: u64,
+    checksum: Checksum,
+    source: Source,
+}
+
+impl Load {
+    pub fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    pub(super) fn next_record(&mut self) -> Option<(u64, (i8, u8))> {
+        if self.read == self.header.entries {
+            return None;
+        }
+        let (key, value) = match &mut self.source {
+            Source::Records(f) => read_record(f).ok()?,
+            Source::Blocks(blocks) => blocks.next()?,
+        };
+        self.read += 1;
+        self.checksum.add(key, value);
+        Some((key, value))
+    }
+
+    /// 残りを読み飛ばしてチェックサムを確かめる
+    pub fn finish(mut self) -> Result<(), DbError> {
+        while self.next_record().is_some() {}
+        if self.read != self.header.entries {
+            return Err(DbError::Truncated {
+                entries: self.header.entries,
+                len: HEADER_LEN + self.read * RECORD_LEN,
+            });
+        }
+        if self.checksum.value() != self.header.checksum {
+            return Err(DbError::ChecksumMismatch {
+                expected: self.header.checksum,
+                found: self.checksum.value(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Load {
+    type Item = ([u8; 12], i8, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.next_record()?;
+        Some((from_compact_key(key), value.0, value.1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = (self.header.entries - self.read) as usize;
+        (n, Some(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::learn::test_utils::temp_path;
+
+    fn sample() -> FnvHashMap<u64, (i8, u8)> {
+        (0..100u64)
+            .map(|i| (i * 7919, (i as i8 - 50, i as u8)))
+            .collect()
+    }
+
+    #[test]
+    fn save_load() {
+        let path = temp_path("save_load.dat");
+        let data = sample();
+        save(&path, true, &data).unwrap();
+        assert_eq!(load(&path, true).unwrap(), data);
+        assert!(matches!(
+            load(&path, false),
+            Err(DbError::StealingMismatch { expected: false })
+        ));
+        let mut db = iter_load(&path).unwrap();
+        assert_eq!(db.header().entries, 100);
+        assert_eq!(db.by_ref().count(), 100);
+        db.finish().unwrap();
+
+        // 値を 1 つ壊す
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[HEADER_LEN as usize + 8] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            load(&path, true),
+            Err(DbError::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            iter_load(&path).unwrap().finish(),
+            Err(DbError::ChecksumMismatch { .. })
+        ));
+        // 切れている
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(load(&path, true), Err(DbError::Truncated { .. })));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(load(&path, true).unwrap_err().is_not_found());
+        assert!(load_or_default(&path, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn upgrade_legacy() {
+        let old = temp_path("legacy.dat");
+        let new = temp_path("upgraded.dat");
+        let data = sample();
+        let mut bytes = (data.len() as u64).to_le_bytes().to_vec();
+        for (key, value) in data.iter() {
+            write_record(&mut bytes, *key, *value).unwrap();
+        }
+        std::fs::write(&old, &bytes).unwrap();
+        assert!(matches!(load(&old, false), Err(DbError::Legacy)));
+        assert_eq!(upgrade(&old, &new, false).unwrap(), 100);
+        assert_eq!(load(&new, false).unwrap(), data);
+        // 書き出し方によらず同じチェックサムになる
+        let saved = temp_path("saved.dat");
+        save(&saved, false, &data).unwrap();
+        assert_eq!(
+            iter_load(&saved).unwrap().header(),
+            iter_load(&new).unwrap().header()
+        );
+        for path in [old, new, saved] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::ops::Range;
+use std::sync::Mutex;
+use std::thread;
+
+use fnv::FnvHashMap;
+use rand::SeedableRng;
+use rand_pcg::Mcg128Xsl64;
+
+use super::db::DbError;
+use super::search::{search_with, to_finish};
+use super::spill::SpillDb;
+use super::tools::Entry;
+use crate::ai::RandomSearcher;
+
+/// キーのハッシュで分けた DB。分け方はスレッド数によらない
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardedDb {
+    shards: Vec<FnvHashMap<u64, (i8, u8)>>,
+}
+
+impl ShardedDb {
+    pub fn new(shards: usize) -> ShardedDb {
+        ShardedDb {
+            shards: vec![FnvHashMap::default(); shards.max(1)],
+        }
+    }
+
+    pub fn from_map(shards: usize, data: FnvHashMap<u64, (i8, u8)>) -> ShardedDb {
+        let mut db = ShardedDb::new(shards);
+        for (key, value) in data {
+            db.insert(key, value);
+        }
+        db
+    }
+
+    fn shard_of(&self, key: u64) -> usize {
+        // compact_key の下位ビットは偏るので混ぜる
+        (key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % self.shards.len()
+    }
+
+    pub fn get(&self, key: u64) -> Option<(i8, u8)> {
+        self.shards[self.shard_of(key)].get(&key).copied()
+    }
+
+    pub fn insert(&mut self, key: u64, value: (i8, u8)) {
+        let s = self.shard_of(key);
+        self.shards[s].insert(key, value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(FnvHashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, (i8, u8))> + '_ {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.iter().map(|(k, v)| (*k, *v)))
+    }
+
+    pub fn into_entries(self) -> Vec<(u64, (i8, u8))> {
+        let mut entries = Vec::with_capacity(self.len());
+        for shard in self.shards {
+            entries.extend(shard);
+        }
+        entries
+    }
+
+    /// ゲームごとの局面を、シャードに分けてからゲームの順にシャードごと並列に入れる
+    fn merge(&mut self, games: Vec<Vec<Entry>>, threads: usize) {
+        let mut by_shard = vec![Vec::new(); self.shards.len()];
+        for game in games {
+            for (key, value) in game {
+                by_shard[self.shard_of(key)].push((key, value));
+            }
+        }
+        let chunk = self.shards.len().div_ceil(threads.max(1));
+        thread::scope(|scope| {
+            for (shards, parts) in self
+                .shards
+                .chunks_mut(chunk)
+                .zip(by_shard.chunks_mut(chunk))
+            {
+                scope.spawn(move || {
+                    for (shard, part) in shards.iter_mut().zip(parts.iter_mut()) {
+                        for (key, value) in part.drain(..) {
+                            // 値は読み切った値なので、どのゲームで見つけても同じ
+                            shard.entry(key).or_insert(value);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
+
+/// ランダムなゲームの終局側から読み切って DB を増やす設定
+#[derive(Debug, Clone, Copy)]
+pub struct Generator {
+    pub stealing: bool,
+    /// `learn::search` の読む深さ
+    pub depth: u8,
+    pub threads: usize,
+    /// この数のゲームごとに DB を更新する。結果はこの値によるがスレッド数にはよらない
+    pub batch: usize,
+    pub seed: u64,
+}
+
+impl Generator {
+    pub fn new(stealing: bool, threads: usize) -> Generator {
+        Generator {
+            stealing,
+            depth: 30,
+            threads,
+            batch: 100,
+            seed: 0,
+        }
+    }
+
+    /// `i` 番目のゲームの乱数。スレッドの割り当てによらない
+    fn random(&self, i: u64) -> Mcg128Xsl64 {
+        Mcg128Xsl64::seed_from_u64(self.seed ^ i.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    /// 1 ゲーム分。`base` は引くだけで、新しく読み切った局面を返す
+    fn play<G>(&self, base: &G, i: u64) -> Vec<Entry>
+    where
+        G: Fn(u64) -> Option<(i8, u8)>,
+    {
+        let mut ai = RandomSearcher::new(self.random(i));
+        let mut local = FnvHashMap::default();
+        let mut path = to_finish(self.stealing, &mut ai);
+        while let Some(board) = path.pop() {
+            if search_with(base, &mut local, board, self.depth).is_none() {
+                break;
+            }
+        }
+        local.into_iter().collect()
+    }
+
+    /// `games` のゲームを並列に読み、ゲームの順に並べて返す
+    fn batch<G>(&self, base: &G, games: Range<u64>) -> Vec<Vec<Entry>>
+    where
+        G: Fn(u64) -> Option<(i8, u8)> + Sync,
+    {
+        let next = Mutex::new(games.start);
+        let mut results = Vec::with_capacity((games.end - games.start) as usize);
+        thread::scope(|scope| {
+            let handles = (0..self.threads.max(1))
+                .map(|_| {
+                    let next = &next;
+                    let end = games.end;
+                    scope.spawn(move || {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = {
+                                let mut next = next.lock().unwrap();
+                                if *next == end {
+                                    break;
+                                }
+                                *next += 1;
+                                *next - 1
+                            };
+                            done.push((i, self.play(base, i)));
+                        }
+                        done
+                    })
+                })
+                .collect::<Vec<_>>();
+            for h in handles {
+                results.extend(h.join().unwrap());
+            }
+        });
+        results.sort_unstable_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, entries)| entries).collect()
+    }
+
+    /// `games` のゲームを `batch` ごとに並列に読み、バッチが終わるたびに `report` を呼ぶ
+    ///
+    /// バッチの中のゲームはバッチの前の DB だけを見て読み、終わってからゲームの順に DB に入れるので、
+    /// スレッド数を変えても同じ DB になる
+    pub fn run<F>(&self, db: &mut ShardedDb, games: Range<u64>, mut report: F)
+    where
+        F: FnMut(u64, &ShardedDb),
+    {
+        let mut start = games.start;
+        while start < games.end {
+            let end = (start + self.batch.max(1) as u64).min(games.end);
+            let results = self.batch(&|key| db.get(key), start..end);
+            db.merge(results, self.threads);
+            report(end, db);
+            start = end;
+        }
+    }
+
+    /// `run` と同じだが、メモリーに収まらない分をディスクに書き出す `SpillDb` に入れる
+    pub fn run_spilled<F>(
+        &self,
+        db: &mut SpillDb,
+        games: Range<u64>,
+        mut report: F,
+    ) -> Result<(), DbError>
+    where
+        F: FnMut(u64, &SpillDb),
+    {
+        let mut start = games.start;
+        while start < games.end {
+            let end = (start + self.batch.max(1) as u64).min(games.end);
+            for entries in self.batch(&|key| db.get(key), start..end) {
+                for (key, value) in entries {
+                    if db.get(key).is_none() {
+                        db.put(key, value)?;
+                    }
+                }
+            }
+            report(end, db);
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Board, compact_key,
+        learn::{search, test_utils::temp_path},
+    };
+
+    fn generate(threads: usize, batch: usize) -> ShardedDb {
+        let mut generator = Generator::new(true, threads);
+        generator.depth = 12;
+        generator.batch = batch;
+        let mut db = ShardedDb::new(8);
+        let mut reports = 0;
+        generator.run(&mut db, 0..6, |_, _| reports += 1);
+        assert_eq!(reports, 6usize.div_ceil(batch));
+        db
+    }
+
+    #[test]
+    fn same_for_any_threads() {
+        let one = generate(1, 4);
+        assert!(!one.is_empty());
+        assert_eq!(generate(3, 4), one);
+        let mut entries = one.clone().into_entries();
+        entries.sort_unstable();
+        let mut other = generate(2, 4).into_entries();
+        other.sort_unstable();
+        assert_eq!(entries, other);
+
+        // 値はどれも読み切った値
+        let mut memo = FnvHashMap::default();
+        for (key, value) in entries.iter().step_by(97) {
+            let pits = crate::from_compact_key(*key);
+            let board = Board::from_seeds(true, &pits);
+            assert_eq!(compact_key(&board), *key);
+            assert_eq!(search(&mut memo, board, 100), Some(*value));
+        }
+    }
+
+    #[test]
+    fn spilled_same_as_sharded() {
+        let mut entries = generate(1, 4).into_entries();
+        entries.sort_unstable();
+
+        let mut generator = Generator::new(true, 2);
+        generator.depth = 12;
+        generator.batch = 4;
+        let dir = std::path::PathBuf::from(temp_path("gen-spill"));
+        let mut db = SpillDb::new(&dir, true, entries.len() / 5).unwrap();
+        generator.run_spilled(&mut db, 0..6, |_, _| ()).unwrap();
+        assert!(db.runs() > 0);
+        let name = dir.join("merged.dat");
+        let name = name.to_str().unwrap();
+        assert_eq!(db.finish(name).unwrap(), entries.len() as u64);
+        let db = crate::learn::SortedDb::open(name, true).unwrap();
+        assert_eq!(db.iter().collect::<Vec<_>>(), entries);
+        drop(db);
+        std::fs::remove_file(name).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
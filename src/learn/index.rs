@@ -0,0 +1,95 @@
+use crate::board::{PIT, SEED};
+
+use super::Pits;
+
+const PITS: usize = PIT * 2;
+const MAX_SEEDS: usize = PITS * SEED as usize;
+
+lazy_static! {
+    /// BINOMIAL[n][k] = C(n, k)
+    static ref BINOMIAL: Vec<[u64; PITS]> = {
+        let mut table = vec![[0; PITS]; MAX_SEEDS + PITS];
+        for n in 0..table.len() {
+            table[n][0] = 1;
+            for k in 1..PITS.min(n + 1) {
+                table[n][k] = table[n - 1][k - 1] + table[n - 1][k];
+            }
+        }
+        table
+    };
+}
+
+/// `total` 個の石を `parts` 個の穴に分ける方法の数
+fn count_parts(total: usize, parts: usize) -> u64 {
+    BINOMIAL[total + parts - 1][parts - 1]
+}
+
+/// 穴の石の合計が `total` の局面の数
+pub fn count(total: usize) -> u64 {
+    count_parts(total, PITS)
+}
+
+/// 穴の石の合計が同じ局面の中での通し番号。0 から `count(total) - 1` までの値になる
+///
+/// 先頭の穴から順に辞書順に並べたときの順番で、石の数を仕切りで区切る並べ方（stars and bars）を数えて求める
+pub fn rank(pits: &Pits) -> u64 {
+    let mut rest = pits.iter().map(|s| usize::from(*s)).sum::<usize>();
+    let mut ret = 0;
+    for (i, &s) in pits[..PITS - 1].iter().enumerate() {
+        let parts = PITS - i;
+        // この穴の石が s 未満の並べ方の数
+        ret += count_parts(rest, parts) - count_parts(rest - usize::from(s), parts);
+        rest -= usize::from(s);
+    }
+    ret
+}
+
+/// `rank` の逆
+pub fn unrank(total: usize, rank: u64) -> Pits {
+    debug_assert!(rank < count(total));
+    let mut pits = [0; PITS];
+    let mut rest = total;
+    let mut rank = rank;
+    for (i, pit) in pits[..PITS - 1].iter_mut().enumerate() {
+        let parts = PITS - i;
+        let all = count_parts(rest, parts);
+        let mut s = 0;
+        while s < rest && all - count_parts(rest - s - 1, parts) <= rank {
+            s += 1;
+        }
+        rank -= all - count_parts(rest - s, parts);
+        *pit = s as u8;
+        rest -= s;
+    }
+    pits[PITS - 1] = rest as u8;
+    pits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_unrank() {
+        for total in 0..=5 {
+            let mut prev = None;
+            for r in 0..count(total) {
+                let pits = unrank(total, r);
+                assert_eq!(pits.iter().map(|s| usize::from(*s)).sum::<usize>(), total);
+                assert_eq!(rank(&pits), r);
+                // 辞書順に並ぶ
+                if let Some(prev) = prev {
+                    assert!(prev < pits);
+                }
+                prev = Some(pits);
+            }
+        }
+        let total = MAX_SEEDS;
+        let mut pits = [0; PITS];
+        pits[PITS - 1] = total as u8;
+        assert_eq!(rank(&pits), 0);
+        pits.swap(0, PITS - 1);
+        assert_eq!(rank(&pits), count(total) - 1);
+        assert_eq!(unrank(total, count(total) - 1), pits);
+    }
+}
@@ -1,5 +1,31 @@
+mod compressed;
+mod dataset;
+mod db;
+mod generate;
+mod index;
+mod npy;
 mod search;
+mod solver;
+mod sorted;
+mod spill;
+mod tablebase;
+#[cfg(test)]
+pub(crate) mod test_utils;
+mod tools;
+mod trainer;
 mod utils;
 
+pub use compressed::*;
+pub use dataset::{Dataset, Epoch, Rows, Split, Splitter};
+pub use db::*;
+pub use generate::{Generator, ShardedDb};
+pub use index::{count, rank, unrank};
+pub use npy::*;
 pub use search::*;
+pub use solver::{SolveError, Solver, SolverMemo};
+pub use sorted::*;
+pub use spill::SpillDb;
+pub use tablebase::*;
+pub use tools::*;
+pub use trainer::*;
 pub use utils::*;
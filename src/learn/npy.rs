@@ -0,0 +1,360 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+
+use super::db::{DbError, iter_load};
+use crate::ai::{Evaluator, Score, ab_search};
+use crate::board::{Board, PIT};
+
+/// `.npy` に書ける要素の型
+pub trait NpyElement: Copy {
+    /// NumPy の dtype の文字列
+    const DESCR: &'static str;
+    fn extend_le(self, out: &mut Vec<u8>);
+}
+
+macro_rules! impl_npy_element {
+    ($($t:ty => $descr:expr),* $(,)?) => {
+        $(
+            impl NpyElement for $t {
+                const DESCR: &'static str = $descr;
+                fn extend_le(self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_npy_element! {
+    u8 => "|u1",
+    i8 => "|i1",
+    u16 => "<u2",
+    i16 => "<i2",
+    u32 => "<u4",
+    i32 => "<i4",
+    u64 => "<u8",
+    i64 => "<i8",
+    f32 => "<f4",
+    f64 => "<f8",
+}
+
+/// C 順に並べた 1 つの配列
+#[derive(Debug, Clone, PartialEq)]
+pub struct NpyArray {
+    descr: &'static str,
+    shape: Vec<usize>,
+    data: Vec<u8>,
+}
+
+impl NpyArray {
+    pub fn new<T: NpyElement>(shape: &[usize], values: &[T]) -> NpyArray {
+        assert_eq!(shape.iter().product::<usize>(), values.len());
+        let mut data = Vec::with_capacity(size_of_val(values));
+        for v in values {
+            v.extend_le(&mut data);
+        }
+        NpyArray {
+            descr: T::DESCR,
+            shape: shape.to_vec(),
+            data,
+        }
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// `.npy` 形式 (version 1.0) で書く
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let shape = match self.shape.as_slice() {
+            [n] => format!("({n},)"),
+            dims => format!(
+                "({})",
+                dims.iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            self.descr, shape
+        );
+        // マジック 6 + バージョン 2 + 長さ 2 を合わせて 64 バイト境界にそろえ、改行で終える
+        let len = 10 + header.len() + 1;
+        header.extend(std::iter::repeat_n(' ', len.next_multiple_of(64) - len));
+        header.push('\n');
+        writer.write_all(b"\x93NUMPY\x01\x00")?;
+        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        writer.write_all(&self.data)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        self.write(&mut f)?;
+        f.flush()
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// `name.npy` を無圧縮で並べた `.npz` (zip) を書く。`numpy.load` で読める
+pub fn save_npz(path: &str, arrays: &[(&str, &NpyArray)]) -> io::Result<()> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "npz over 4 GiB");
+    let mut f = BufWriter::new(File::create(path)?);
+    let mut central = Vec::new();
+    let mut offset = 0u64;
+    for (name, array) in arrays {
+        let name = format!("{name}.npy");
+        let mut data = Vec::with_capacity(array.data.len() + 128);
+        array.write(&mut data)?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let local_offset = u32::try_from(offset).map_err(|_| too_large())?;
+        // 必要なバージョン、フラグ、無圧縮、時刻、日付 (1980-01-01)、CRC、サイズ 2 つ、名前と拡張の長さ
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0x21u16.to_le_bytes());
+        common.extend_from_slice(&crc32(&data).to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        f.write_all(&0x0403_4b50u32.to_le_bytes())?;
+        f.write_all(&common)?;
+        f.write_all(name.as_bytes())?;
+        f.write_all(&data)?;
+        offset += (30 + name.len() + data.len()) as u64;
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&common);
+        // コメントの長さ、ディスク番号、内部属性、外部属性、ローカルヘッダーの位置
+        central.extend_from_slice(&[0; 6]);
+        central.extend_from_slice(&0u32.to_le_bytes());
+        central.extend_from_slice(&local_offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+    let central_offset = u32::try_from(offset).map_err(|_| too_large())?;
+    f.write_all(&central)?;
+    f.write_all(&0x0605_4b50u32.to_le_bytes())?;
+    f.write_all(&[0; 4])?;
+    f.write_all(&(arrays.len() as u16).to_le_bytes())?;
+    f.write_all(&(arrays.len() as u16).to_le_bytes())?;
+    f.write_all(&(central.len() as u32).to_le_bytes())?;
+    f.write_all(&central_offset.to_le_bytes())?;
+    f.write_all(&0u16.to_le_bytes())?;
+    f.flush()
+}
+
+/// DB から読む局面の条件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbFilter {
+    /// 穴に残っている石の数
+    pub seeds: RangeInclusive<usize>,
+    /// 終局までのターン数
+    pub depth: RangeInclusive<u8>,
+}
+
+impl Default for DbFilter {
+    fn default() -> DbFilter {
+        DbFilter {
+            seeds: 0..=usize::MAX,
+            depth: 0..=u8::MAX,
+        }
+    }
+}
+
+impl DbFilter {
+    pub fn matches(&self, pits: &[u8], depth: u8) -> bool {
+        let seeds = pits.iter().map(|s| usize::from(*s)).sum::<usize>();
+        self.seeds.contains(&seeds) && self.depth.contains(&depth)
+    }
+}
+
+/// DB の中身を列ごとに並べたもの。局面は手番側の穴から並ぶ
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbColumns {
+    pub pits: Vec<u8>,
+    pub score: Vec<i8>,
+    pub depth: Vec<u8>,
+}
+
+impl DbColumns {
+    pub fn len(&self) -> usize {
+        self.score.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn boards(&self, stealing: bool) -> impl Iterator<Item = Board> + '_ {
+        self.pits
+            .chunks(PIT * 2)
+            .map(move |seeds| Board::from_seeds(stealing, seeds))
+    }
+
+    /// `pits` (n, 12) u8, `score` (n,) i8, `depth` (n,) u8 の配列にする
+    pub fn arrays(&self) -> Vec<(&'static str, NpyArray)> {
+        let n = self.len();
+        vec![
+            ("pits", NpyArray::new(&[n, PIT * 2], &self.pits)),
+            ("score", NpyArray::new(&[n], &self.score)),
+            ("depth", NpyArray::new(&[n], &self.depth)),
+        ]
+    }
+}
+
+/// DB から `filter` に合う局面を読み出す
+pub fn export_db(path: &str, stealing: bool, filter: &DbFilter) -> Result<DbColumns, DbError> {
+    let mut db = iter_load(path)?;
+    db.header().check_stealing(stealing)?;
+    let mut columns = DbColumns::default();
+    for (pits, score, depth) in db.by_ref() {
+        if filter.matches(&pits, depth) {
+            columns.pits.extend_from_slice(&pits);
+            columns.score.push(score);
+            columns.depth.push(depth);
+        }
+    }
+    db.finish()?;
+    Ok(columns)
+}
+
+/// 評価関数の値を並べる。`depth` が 0 でなければその深さまで読んだ値
+pub fn predict<E>(eval: &mut E, boards: impl Iterator<Item = Board>, depth: usize) -> Vec<f64>
+where
+    E: Evaluator,
+    E::Score: Into<f64>,
+{
+    boards
+        .map(|board| {
+            if depth == 0 {
+                eval.eval(&board).into()
+            } else {
+                ab_search(board, eval, depth, E::Score::MIN, E::Score::MAX).into()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::ScoreDiffEvaluator;
+    use crate::learn::save;
+    use crate::learn::test_utils::{solved, temp_path};
+
+    #[test]
+    fn npy_header() {
+        let array = NpyArray::new(&[2, 3], &[1i8, -1, 2, -2, 3, -3]);
+        let mut buf = Vec::new();
+        array.write(&mut buf).unwrap();
+        assert_eq!(&buf[..8], b"\x93NUMPY\x01\x00");
+        let len = u16::from_le_bytes([buf[8], buf[9]]) as usize;
+        assert_eq!((10 + len) % 64, 0);
+        let header = std::str::from_utf8(&buf[10..10 + len]).unwrap();
+        assert!(header.starts_with("{'descr': '|i1', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with('\n'));
+        assert_eq!(&buf[10 + len..], &[1, 255, 2, 254, 3, 253]);
+
+        let mut buf = Vec::new();
+        NpyArray::new(&[1], &[1.5f64]).write(&mut buf).unwrap();
+        assert!(std::str::from_utf8(&buf[10..40]).unwrap().contains("'<f8'"));
+        assert!(String::from_utf8_lossy(&buf).contains("'shape': (1,)"));
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn npz_layout() {
+        let a = NpyArray::new(&[3], &[1u8, 2, 3]);
+        let b = NpyArray::new(&[2], &[-1i32, 1]);
+        let path = temp_path("npz.npz");
+        let path = path.as_str();
+        save_npz(path, &[("a", &a), ("b", &b)]).unwrap();
+        let buf = std::fs::read(path).unwrap();
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]) as usize;
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap()) as usize;
+
+        // 末尾の end of central directory から中央ディレクトリをたどる
+        let end = buf.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50);
+        assert_eq!(u16_at(end + 10), 2);
+        let mut at = u32_at(end + 16);
+        assert_eq!(at + u32_at(end + 12), end);
+        for (name, array) in [("a.npy", &a), ("b.npy", &b)] {
+            assert_eq!(u32_at(at), 0x0201_4b50);
+            let name_len = u16_at(at + 28);
+            assert_eq!(&buf[at + 46..at + 46 + name_len], name.as_bytes());
+            let local = u32_at(at + 42);
+            assert_eq!(u32_at(local), 0x0403_4b50);
+            let size = u32_at(local + 18);
+            let data = &buf[local + 30 + name_len..local + 30 + name_len + size];
+            assert_eq!(u32_at(local + 14), crc32(data) as usize);
+            let mut npy = Vec::new();
+            array.write(&mut npy).unwrap();
+            assert_eq!(data, npy.as_slice());
+            at += 46 + name_len;
+        }
+        assert_eq!(at, end);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn export_with_filter() {
+        let data = solved(true);
+        let path = temp_path("npy.dat");
+        let path = path.as_str();
+        save(path, true, &data).unwrap();
+
+        let all = export_db(path, true, &DbFilter::default()).unwrap();
+        assert_eq!(all.len(), data.len());
+        assert_eq!(all.pits.len(), data.len() * PIT * 2);
+        let filter = DbFilter {
+            seeds: 4..=6,
+            depth: 1..=3,
+        };
+        let some = export_db(path, true, &filter).unwrap();
+        assert!(!some.is_empty() && some.len() < all.len());
+        for (board, depth) in some.boards(true).zip(some.depth.iter()) {
+            let seeds = board
+                .self_seeds()
+                .iter()
+                .chain(board.opposite_seed())
+                .sum::<u8>();
+            assert!((4..=6).contains(&seeds));
+            assert!((1..=3).contains(depth));
+        }
+
+        // 石の差の評価関数は、終局した局面なら読み切った値と同じ
+        let pred = predict(&mut ScoreDiffEvaluator::new(), all.boards(true), 0);
+        for ((p, s), d) in pred.iter().zip(all.score.iter()).zip(all.depth.iter()) {
+            if *d == 0 {
+                assert_eq!(*p, f64::from(*s));
+            }
+        }
+        let arrays = all.arrays();
+        assert_eq!(arrays[0].1.shape(), &[all.len(), PIT * 2]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -4,11 +4,11 @@ use rand::Rng;
 use crate::ai::{RandomSearcher, Searcher};
 use crate::board::{Board, compact_key};
 
-fn raw_scores(board: &Board) -> i8 {
+pub(super) fn raw_scores(board: &Board) -> i8 {
     board.score()
 }
 
-fn seed_scores(board: &Board) -> i8 {
+pub(super) fn seed_scores(board: &Board) -> i8 {
     let l0 = board.self_seeds().iter().sum::<u8>() as i8;
     let l1 = board.opposite_seed().iter().sum::<u8>() as i8;
     l0 - l1
@@ -16,18 +16,47 @@ fn seed_scores(board: &Board) -> i8 {
 
 const LIMIT: usize = 3_758_096_384;
 
-pub fn search(data: &mut FnvHashMap<u64, (i8, u8)>, board: Board, depth: u8) -> Option<(i8, u8)> {
-    if data.len() == LIMIT {
-        return None;
+/// `search` が読んだ局面を覚えておく表
+pub trait Memo {
+    fn get(&self, key: u64) -> Option<(i8, u8)>;
+    /// 入れられなければ `false` を返し、`search` は読むのをやめる
+    fn insert(&mut self, key: u64, value: (i8, u8)) -> bool;
+}
+
+impl Memo for FnvHashMap<u64, (i8, u8)> {
+    fn get(&self, key: u64) -> Option<(i8, u8)> {
+        self.get(&key).copied()
+    }
+
+    fn insert(&mut self, key: u64, value: (i8, u8)) -> bool {
+        if self.len() == LIMIT {
+            return false;
+        }
+        self.insert(key, value);
+        true
     }
+}
+
+pub fn search<M: Memo>(data: &mut M, board: Board, depth: u8) -> Option<(i8, u8)> {
+    search_with(&|_| None, data, board, depth)
+}
+
+/// `data` になければ `base` も引く `search`。新しく読んだ局面は `data` にだけ入れる
+pub fn search_with<F, M>(base: &F, data: &mut M, board: Board, depth: u8) -> Option<(i8, u8)>
+where
+    F: Fn(u64) -> Option<(i8, u8)>,
+    M: Memo,
+{
     let key = compact_key(&board);
-    if let Some((l, d)) = data.get(&key) {
-        return Some((raw_scores(&board) + *l, *d));
+    if let Some((l, d)) = data.get(key).or_else(|| base(key)) {
+        return Some((raw_scores(&board) + l, d));
     }
     if board.is_finished() {
         let s = raw_scores(&board);
         let l = seed_scores(&board);
-        data.insert(key, (l, 0));
+        if !data.insert(key, (l, 0)) {
+            return None;
+        }
         return Some((s + l, 0));
     }
     if depth == 0 {
@@ -36,7 +65,7 @@ pub fn search(data: &mut FnvHashMap<u64, (i8, u8)>, board: Board, depth: u8) ->
     let mut best_score = -128;
     let mut best_depth = 127;
     for next in board.list_next() {
-        let a = search(data, next, depth - 1);
+        let a = search_with(base, data, next, depth - 1);
         match a {
             None => return None,
             Some((s, d)) => {
@@ -51,10 +80,9 @@ pub fn search(data: &mut FnvHashMap<u64, (i8, u8)>, board: Board, depth: u8) ->
             }
         }
     }
-    if data.len() == LIMIT {
+    if !data.insert(key, (best_score - raw_scores(&board), best_depth)) {
         return None;
     }
-    data.insert(key, (best_score - raw_scores(&board), best_depth));
     Some((best_score, best_depth))
 }
 
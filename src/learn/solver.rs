@@ -0,0 +1,326 @@
+use std::{fmt, io, path::Path};
+
+use fnv::FnvHashMap;
+use instant::{Duration, Instant};
+
+use super::{
+    db::DbError,
+    search::{Memo, raw_scores, seed_scores},
+    sorted::DbLookup,
+    spill::SpillDb,
+};
+use crate::board::{Board, compact_key};
+
+#[derive(Debug)]
+pub enum SolveError {
+    /// `set_budget` で決めたノード数か時間を使い切った
+    BudgetExceeded,
+    /// メモリーに置ける局面数の上限に達した
+    MemoFull,
+    Db(DbError),
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::BudgetExceeded => write!(f, "solver budget exceeded"),
+            SolveError::MemoFull => write!(f, "solver memo is full, use SpillDb"),
+            SolveError::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+impl From<DbError> for SolveError {
+    fn from(e: DbError) -> SolveError {
+        SolveError::Db(e)
+    }
+}
+
+impl From<io::Error> for SolveError {
+    fn from(e: io::Error) -> SolveError {
+        SolveError::Db(DbError::Io(e))
+    }
+}
+
+/// ソルバーが読み切った局面を貯める先
+pub trait SolverMemo: Memo {
+    fn put(&mut self, key: u64, value: (i8, u8)) -> Result<(), SolveError>;
+
+    /// 止めても続きから読めるように書き出す
+    fn checkpoint(&mut self) -> Result<(), DbError> {
+        Ok(())
+    }
+}
+
+impl SolverMemo for FnvHashMap<u64, (i8, u8)> {
+    fn put(&mut self, key: u64, value: (i8, u8)) -> Result<(), SolveError> {
+        if Memo::insert(self, key, value) {
+            Ok(())
+        } else {
+            Err(SolveError::MemoFull)
+        }
+    }
+}
+
+impl SolverMemo for SpillDb {
+    fn put(&mut self, key: u64, value: (i8, u8)) -> Result<(), SolveError> {
+        Ok(SpillDb::put(self, key, value)?)
+    }
+
+    fn checkpoint(&mut self) -> Result<(), DbError> {
+        self.flush()
+    }
+}
+
+struct Checkpoint {
+    interval: Duration,
+    last: Instant,
+}
+
+/// 1 回の `solve` で使えるノード数と時間
+#[derive(Debug, Clone, Copy, Default)]
+struct Budget {
+    nodes: Option<u64>,
+    time: Option<Duration>,
+}
+
+/// 時間を確かめる間隔のノード数
+const TIME_CHECK: u64 = 1 << 12;
+
+/// 終局まで全部読み切るソルバー
+///
+/// 読み切った局面の値を `learn::search` と同じ形式で `data` に貯める。
+/// `SpillDb` に貯めればメモリーに収まらない局面数でも読め、途中で止めてもチェックポイントから続きを読める
+pub struct Solver<M: SolverMemo = FnvHashMap<u64, (i8, u8)>> {
+    data: M,
+    /// `data` になければ引く、読み込まずに使う DB
+    base: Option<Box<dyn DbLookup>>,
+    checkpoint: Option<Checkpoint>,
+    budget: Budget,
+    /// 今の `solve` で訪問したノード数と始めた時刻
+    nodes: u64,
+    solve_start: Instant,
+    /// 読み切った局面数
+    solved: usize,
+    progress: usize,
+    start: Instant,
+    last_report: usize,
+}
+
+impl<M: SolverMemo> Solver<M> {
+    pub fn new(data: M) -> Solver<M> {
+        Solver {
+            data,
+            base: None,
+            checkpoint: None,
+            budget: Budget::default(),
+            nodes: 0,
+            solve_start: Instant::now(),
+            solved: 0,
+            progress: 0,
+            start: Instant::now(),
+            last_report: 0,
+        }
+    }
+
+    /// 局面が `every` 個増えるごとに進捗を標準エラー出力に出す。0 なら出さない
+    pub fn set_progress(&mut self, every: usize) {
+        self.progress = every;
+    }
+
+    /// 1 回の `solve` で訪問するノード数と時間の上限。超えたら `SolveError::BudgetExceeded` を返す
+    /// 途中まで読み切った局面は `data` に残るので、次の `solve` はその続きから読める
+    pub fn set_budget(&mut self, nodes: Option<u64>, time: Option<Duration>) {
+        self.budget = Budget { nodes, time };
+    }
+
+    pub fn data(&self) -> &M {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut M {
+        &mut self.data
+    }
+
+    pub fn into_data(self) -> M {
+        self.data
+    }
+
+    /// 手番側から見た最終的な得点差と、終局までのターン数を返す
+    pub fn solve(&mut self, board: &Board) -> Result<(i8, u8), SolveError> {
+        self.nodes = 0;
+        self.solve_start = Instant::now();
+        self.search(board.clone())
+    }
+
+    pub fn save_checkpoint(&mut self) -> Result<(), DbError> {
+        self.data.checkpoint()?;
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.last = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn check_budget(&self) -> Result<(), SolveError> {
+        let over_nodes = self.budget.nodes.is_some_and(|n| self.nodes > n);
+        let over_time = self.nodes.is_multiple_of(TIME_CHECK)
+            && self
+                .budget
+                .time
+                .is_some_and(|t| self.solve_start.elapsed() >= t);
+        if over_nodes || over_time {
+            Err(SolveError::BudgetExceeded)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn insert(&mut self, key: u64, value: (i8, u8)) -> Result<(), SolveError> {
+        self.data.put(key, value)?;
+        self.solved += 1;
+        if self.progress > 0 && self.solved >= self.last_report + self.progress {
+            self.last_report = self.solved;
+            let elapsed = self.start.elapsed().as_secs_f64();
+            eprintln!(
+                "{} positions ({:.0}s, {:.0}/s)",
+                self.solved,
+                elapsed,
+                self.solved as f64 / elapsed
+            );
+        }
+        if let Some(checkpoint) = &self.checkpoint
+            && checkpoint.last.elapsed() >= checkpoint.interval
+        {
+            self.save_checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn search(&mut self, board: Board) -> Result<(i8, u8), SolveError> {
+        let key = compact_key(&board);
+        if let Some((l, d)) = self.data.get(key).or_else(|| self.base.as_ref()?.get(key)) {
+            return Ok((raw_scores(&board) + l, d));
+        }
+        self.nodes += 1;
+        self.check_budget()?;
+        if board.is_finished() {
+            let l = seed_scores(&board);
+            self.insert(key, (l, 0))?;
+            return Ok((raw_scores(&board) + l, 0));
+        }
+        let mut best_score = i8::MIN;
+        let mut best_depth = u8::MAX;
+        for next in board.list_next() {
+            let (s, d) = self.search(next)?;
+            let s = -s;
+            let d = d + 1;
+            if s > best_score || (s == best_score && d < best_depth) {
+                best_score = s;
+                best_depth = d;
+            }
+        }
+        self.insert(key, (best_score - raw_scores(&board), best_depth))?;
+        Ok((best_score, best_depth))
+    }
+}
+
+impl Solver {
+    /// メモリーマップした DB を引きながら読む。新しく読んだ局面だけを `data` に貯める
+    pub fn with_base<D: DbLookup + 'static>(base: D) -> Solver {
+        let mut solver = Solver::new(FnvHashMap::default());
+        solver.base = Some(Box::new(base));
+        solver
+    }
+}
+
+impl Solver<SpillDb> {
+    /// `dir` の `SpillDb` に貯めながら読む。`dir` に前の run があれば続きから読み、
+    /// `interval` ごとにメモリーの局面を run として書き出す。`limit` はメモリーに置く局面数
+    pub fn resume<P: AsRef<Path>>(
+        dir: P,
+        stealing: bool,
+        limit: usize,
+        interval: Duration,
+    ) -> Result<Solver<SpillDb>, DbError> {
+        let mut solver = Solver::new(SpillDb::open(dir, stealing, limit)?);
+        solver.checkpoint = Some(Checkpoint {
+            interval,
+            last: Instant::now(),
+        });
+        Ok(solver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::learn::{
+        Tablebase, search,
+        test_utils::{endgame, temp_path},
+    };
+
+    #[test]
+    fn same_as_tablebase_and_search() {
+        for stealing in [true, false] {
+            let board = endgame(stealing);
+            let tb = Tablebase::generate(stealing, 9);
+            let mut solver = Solver::new(FnvHashMap::default());
+            let (score, depth) = solver.solve(&board).unwrap();
+            assert_eq!(Some(score), tb.probe(&board));
+            let mut data = FnvHashMap::default();
+            assert_eq!(search(&mut data, board, 100), Some((score, depth)));
+            assert_eq!(&data, solver.data());
+        }
+    }
+
+    #[test]
+    fn resume_from_checkpoint() {
+        let dir = std::path::PathBuf::from(temp_path("solver"));
+        let board = endgame(true);
+        let expected = Solver::new(FnvHashMap::default()).solve(&board).unwrap();
+        // メモリーに置くのは 10 局面まで
+        let mut solver = Solver::resume(&dir, true, 10, Duration::from_secs(3600)).unwrap();
+        assert_eq!(solver.solve(&board).unwrap(), expected);
+        assert!(solver.data().runs() > 0);
+        solver.save_checkpoint().unwrap();
+        drop(solver);
+
+        // 読み切った局面は全部 run にあるので、何も読まずに引ける
+        let mut solver = Solver::resume(&dir, true, 10, Duration::from_secs(3600)).unwrap();
+        solver.set_budget(Some(0), None);
+        assert_eq!(solver.solve(&board).unwrap(), expected);
+        let path = dir.join("solved.dat");
+        let path = path.to_str().unwrap();
+        let n = solver.into_data().finish(path).unwrap();
+        let mut data = FnvHashMap::default();
+        search(&mut data, board, 100);
+        assert_eq!(n, data.len() as u64);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn stop_at_budget() {
+        let board = endgame(false);
+        let mut solver = Solver::new(FnvHashMap::default());
+        solver.set_budget(Some(10), None);
+        assert!(matches!(
+            solver.solve(&board),
+            Err(SolveError::BudgetExceeded)
+        ));
+        // 読み切った局面は残るので、終局までの手数より多く読めれば繰り返すうちに読み切れる
+        solver.set_budget(Some(100), None);
+        let expected = Solver::new(FnvHashMap::default()).solve(&board).unwrap();
+        let value = loop {
+            let len = solver.data().len();
+            match solver.solve(&board) {
+                Ok(value) => break value,
+                Err(SolveError::BudgetExceeded) => assert!(solver.data().len() > len),
+                Err(e) => panic!("{e}"),
+            }
+        };
+        assert_eq!(value, expected);
+    }
+}
@@ -0,0 +1,287 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use fnv::FnvHashMap;
+use memmap2::Mmap;
+
+use super::db::{
+    Checksum, DbError, DbHeader, HEADER_LEN, Layout, RECORD_LEN, iter_load, write_record,
+};
+use super::spill::SpillDb;
+
+/// 索引の 1 ブロックに入る局面数
+pub const BLOCK: u64 = 1024;
+
+/// ブロックごとの先頭のキーを並べた索引のバイト数
+pub(super) fn block_index_len(entries: u64) -> u64 {
+    entries.div_ceil(BLOCK) * 8
+}
+
+/// 読み込まずに引ける DB
+pub trait DbLookup: Send + Sync {
+    fn get(&self, key: u64) -> Option<(i8, u8)>;
+}
+
+/// キーの順に並べた DB をメモリーマップして、全体を読み込まずに引く
+///
+/// 索引を二分探索してブロックを決め、ブロックの中は補間探索と二分探索を交互に使って探す
+pub struct SortedDb {
+    header: DbHeader,
+    mmap: Mmap,
+}
+
+impl SortedDb {
+    pub fn open<P: AsRef<Path>>(path: P, stealing: bool) -> Result<SortedDb, DbError> {
+        let file = File::open(path)?;
+        // SAFETY: 開いている間にファイルを書き換えないことを前提にする
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = DbHeader::read(&mut &mmap[..])?;
+        header.check_stealing(stealing)?;
+        if header.layout != Layout::Sorted {
+            return Err(DbError::NotSorted);
+        }
+        header.check_len(mmap.len() as u64)?;
+        Ok(SortedDb { header, mmap })
+    }
+
+    pub fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.entries as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn record(&self, i: usize) -> &[u8] {
+        let start = HEADER_LEN as usize + i * RECORD_LEN as usize;
+        &self.mmap[start..start + RECORD_LEN as usize]
+    }
+
+    pub fn key_at(&self, i: usize) -> u64 {
+        u64::from_le_bytes(self.record(i)[..8].try_into().unwrap())
+    }
+
+    pub fn value_at(&self, i: usize) -> (i8, u8) {
+        let r = self.record(i);
+        (r[8] as i8, r[9])
+    }
+
+    pub(super) fn blocks(&self) -> usize {
+        self.header.entries.div_ceil(BLOCK) as usize
+    }
+
+    /// `b` 番目のブロックを全部読む
+    pub(super) fn block(&self, b: usize) -> Vec<(u64, (i8, u8))> {
+        let start = b * BLOCK as usize;
+        let end = (start + BLOCK as usize).min(self.len());
+        (start..end)
+            .map(|i| (self.key_at(i), self.value_at(i)))
+            .collect()
+    }
+
+    fn block_key(&self, b: usize) -> u64 {
+        let start = (HEADER_LEN + self.header.entries * RECORD_LEN) as usize + b * 8;
+        u64::from_le_bytes(self.mmap[start..start + 8].try_into().unwrap())
+    }
+
+    pub fn get(&self, key: u64) -> Option<(i8, u8)> {
+        // 先頭のキーが key 以下の最後のブロック
+        let (mut lo, mut hi) = (0, self.blocks());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.block_key(mid) <= key {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let b = lo.checked_sub(1)?;
+        let start = b * BLOCK as usize;
+        let end = (start + BLOCK as usize).min(self.len());
+        self.search_block(key, start, end).map(|i| self.value_at(i))
+    }
+
+    fn search_block(&self, key: u64, mut lo: usize, mut hi: usize) -> Option<usize> {
+        let mut interpolate = true;
+        while lo < hi {
+            let (k_lo, k_hi) = (self.key_at(lo), self.key_at(hi - 1));
+            if key < k_lo || k_hi < key {
+                return None;
+            }
+            // キーが偏っていても最悪で二分探索の 2 倍で済むように交互に使う
+            let mid = if interpolate && k_lo < k_hi {
+                let width = (hi - 1 - lo) as u128;
+                lo + (u128::from(key - k_lo) * width / u128::from(k_hi - k_lo)) as usize
+            } else {
+                (lo + hi) / 2
+            };
+            interpolate = !interpolate;
+            match self.key_at(mid).cmp(&key) {
+                std::cmp::Ordering::Equal => return Some(mid),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, (i8, u8))> + '_ {
+        (0..self.len()).map(|i| (self.key_at(i), self.value_at(i)))
+    }
+
+    /// チェックサムと並び順、索引を確かめる
+    pub fn verify(&self) -> Result<(), DbError> {
+        let mut checksum = Checksum::default();
+        let mut prev = None;
+        for (i, (key, value)) in self.iter().enumerate() {
+            if prev.is_some_and(|prev| prev >= key)
+                || ((i as u64).is_multiple_of(BLOCK) && self.block_key(i / BLOCK as usize) != key)
+            {
+                return Err(DbError::NotSorted);
+            }
+            checksum.add(key, value);
+            prev = Some(key);
+        }
+        if checksum.value() != self.header.checksum {
+            return Err(DbError::ChecksumMismatch {
+                expected: self.header.checksum,
+                found: checksum.value(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl DbLookup for SortedDb {
+    fn get(&self, key: u64) -> Option<(i8, u8)> {
+        SortedDb::get(self, key)
+    }
+}
+
+/// キーの順に並べて索引を付けて書き出す
+pub fn save_sorted(name: &str, stealing: bool, data: &FnvHashMap<u64, (i8, u8)>) -> io::Result<()> {
+    write_sorted(name, stealing, data.iter().map(|(k, v)| (*k, *v)).collect())
+}
+
+/// `save_sorted` と同じだが、キーが重ならない局面の並びを受け取る
+pub fn write_sorted(
+    name: &str,
+    stealing: bool,
+    mut entries: Vec<(u64, (i8, u8))>,
+) -> io::Result<()> {
+    entries.sort_unstable_by_key(|(key, _)| *key);
+    write_sorted_iter(name, stealing, entries).map(|_| ())
+}
+
+/// キーの昇順に並んだ局面をそのまま書き出す。書いた局面数を返す
+pub(super) fn write_sorted_iter<I>(name: &str, stealing: bool, entries: I) -> io::Result<u64>
+where
+    I: IntoIterator<Item = (u64, (i8, u8))>,
+{
+    let mut f = BufWriter::new(File::create(name)?);
+    // 後で書き直す
+    DbHeader::new(stealing, Layout::Sorted, 0, 0).write(&mut f)?;
+    let mut checksum = Checksum::default();
+    let mut index = Vec::new();
+    let mut n: u64 = 0;
+    for (key, value) in entries {
+        if n.is_multiple_of(BLOCK) {
+            index.push(key);
+        }
+        checksum.add(key, value);
+        write_record(&mut f, key, value)?;
+        n += 1;
+    }
+    for key in index {
+        f.write_all(&key.to_le_bytes())?;
+    }
+    let mut f = f.into_inner().map_err(|e| e.into_error())?;
+    f.seek(SeekFrom::Start(0))?;
+    DbHeader::new(stealing, Layout::Sorted, n, checksum.value()).write(&mut f)?;
+    Ok(n)
+}
+
+/// 順不同の DB をソートした DB に変換する。変換した局面数を返す
+///
+/// メモリーには `memory` 局面ずつしか置かず、`{new}.runs` にソートした run を書き出してからマージする
+pub fn sort_db(old: &str, new: &str, stealing: bool, memory: usize) -> Result<u64, DbError> {
+    let mut load = iter_load(old)?;
+    load.header().check_stealing(stealing)?;
+    let mut db = SpillDb::new(format!("{new}.runs"), stealing, memory)?;
+    while let Some((key, value)) = load.next_record() {
+        db.put(key, value)?;
+    }
+    load.finish()?;
+    let n = db.finish(new)?;
+    let _ = fs::remove_dir(format!("{new}.runs"));
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use rand_pcg::Mcg128Xsl64;
+
+    use super::*;
+    use crate::learn::save;
+    use crate::learn::test_utils::temp_path;
+
+    #[test]
+    fn lookup() {
+        let mut random = Mcg128Xsl64::new(1);
+        let data = (0..5000)
+            .map(|_| {
+                let key = random.random::<u64>() >> random.random_range(0..40);
+                (key, (random.random(), random.random()))
+            })
+            .collect::<FnvHashMap<u64, (i8, u8)>>();
+        let plain = temp_path("plain.dat");
+        let sorted = temp_path("sorted.dat");
+        save(&plain, true, &data).unwrap();
+        assert!(matches!(
+            SortedDb::open(&plain, true),
+            Err(DbError::NotSorted)
+        ));
+        // 1000 局面ずつの run に分けてからマージする
+        assert_eq!(
+            sort_db(&plain, &sorted, true, 1000).unwrap(),
+            data.len() as u64
+        );
+        assert!(!Path::new(&format!("{sorted}.runs")).exists());
+
+        let db = SortedDb::open(&sorted, true).unwrap();
+        db.verify().unwrap();
+        assert_eq!(db.len(), data.len());
+        for (key, value) in data.iter() {
+            assert_eq!(db.get(*key), Some(*value));
+            assert_eq!(db.get(key ^ 1), data.get(&(key ^ 1)).copied());
+        }
+        assert_eq!(db.get(u64::MAX), data.get(&u64::MAX).copied());
+        assert!(db.iter().zip(db.iter().skip(1)).all(|(a, b)| a.0 < b.0));
+
+        // 順に読むだけなら今までの読み込みで読める
+        let mut load = iter_load(&sorted).unwrap();
+        assert_eq!(load.header().checksum, db.header().checksum);
+        assert_eq!(load.by_ref().count(), data.len());
+        load.finish().unwrap();
+
+        std::fs::remove_file(&plain).unwrap();
+        std::fs::remove_file(&sorted).unwrap();
+    }
+
+    #[test]
+    fn empty() {
+        let path = temp_path("empty.dat");
+        save_sorted(&path, false, &FnvHashMap::default()).unwrap();
+        let db = SortedDb::open(&path, false).unwrap();
+        assert!(db.is_empty());
+        assert_eq!(db.get(0), None);
+        db.verify().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use fnv::FnvHashMap;
+
+use super::db::DbError;
+use super::search::Memo;
+use super::sorted::{SortedDb, write_sorted, write_sorted_iter};
+
+/// ソート済みの run がこの数を超えたら 1 つにまとめる
+const MAX_RUNS: usize = 8;
+
+/// メモリーに置く局面数に上限のある `Memo`
+///
+/// 上限に達したらメモリーの表をソートした run としてディスクに書き出して空にし、
+/// 引くときはメモリーの表の後に run を新しい順に引く。最後に `finish` で run をマージして 1 つの DB にする
+pub struct SpillDb {
+    stealing: bool,
+    dir: PathBuf,
+    limit: usize,
+    hot: FnvHashMap<u64, (i8, u8)>,
+    runs: Vec<(PathBuf, SortedDb)>,
+    next_run: u64,
+    /// `open` で開いた場合は、途中で止めても続きから使えるように run を消さない
+    keep: bool,
+    /// `Memo::insert` で書き出しに失敗したときのエラー
+    error: Option<DbError>,
+}
+
+impl SpillDb {
+    /// `dir` に run を置く。`limit` はメモリーに置く局面数
+    pub fn new<P: AsRef<Path>>(dir: P, stealing: bool, limit: usize) -> Result<SpillDb, DbError> {
+        fs::create_dir_all(&dir)?;
+        Ok(SpillDb {
+            stealing,
+            dir: dir.as_ref().to_path_buf(),
+            limit: limit.max(1),
+            hot: FnvHashMap::default(),
+            runs: Vec::new(),
+            next_run: 0,
+            keep: false,
+            error: None,
+        })
+    }
+
+    /// `new` と同じだが、`dir` に前に書き出した run があればそれも引く
+    /// `flush` した run は `finish` するまで消さないので、止めても続きから使える
+    pub fn open<P: AsRef<Path>>(dir: P, stealing: bool, limit: usize) -> Result<SpillDb, DbError> {
+        let mut db = SpillDb::new(&dir, stealing, limit)?;
+        db.keep = true;
+        let mut runs = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if name.ends_with(".tmp") {
+                // 書き出している途中で止まった run
+                fs::remove_file(&path)?;
+            } else if let Some(n) = name
+                .strip_prefix("run-")
+                .and_then(|n| n.strip_suffix(".dat"))
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                runs.push((n, path));
+            }
+        }
+        runs.sort_unstable();
+        for (n, path) in runs {
+            let run = SortedDb::open(&path, stealing)?;
+            db.runs.push((path, run));
+            db.next_run = n;
+        }
+        Ok(db)
+    }
+
+    pub fn get(&self, key: u64) -> Option<(i8, u8)> {
+        self.hot
+            .get(&key)
+            .copied()
+            .or_else(|| self.runs.iter().rev().find_map(|(_, run)| run.get(key)))
+    }
+
+    /// メモリーの表に入れ、上限に達したら書き出す
+    pub fn put(&mut self, key: u64, value: (i8, u8)) -> Result<(), DbError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.hot.insert(key, value);
+        if self.hot.len() >= self.limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// メモリーにある局面数
+    pub fn hot_len(&self) -> usize {
+        self.hot.len()
+    }
+
+    /// ディスクにある run の数
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// メモリーにある局面を run として書き出す
+    pub fn flush(&mut self) -> Result<(), DbError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.spill()
+    }
+
+    /// 書き出し用の一時ファイルと、書き終えた後の run のパス
+    fn run_path(&mut self) -> (PathBuf, PathBuf) {
+        self.next_run += 1;
+        let path = self.dir.join(format!("run-{}.dat", self.next_run));
+        (path.with_extension("dat.tmp"), path)
+    }
+
+    fn spill(&mut self) -> Result<(), DbError> {
+        if self.hot.is_empty() {
+            return Ok(());
+        }
+        let (tmp, path) = self.run_path();
+        let hot = std::mem::take(&mut self.hot);
+        write_sorted(
+            tmp.to_str().unwrap(),
+            self.stealing,
+            hot.into_iter().collect(),
+        )?;
+        fs::rename(&tmp, &path)?;
+        let run = SortedDb::open(&path, self.stealing)?;
+        self.runs.push((path, run));
+        if self.runs.len() > MAX_RUNS {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// run を全部マージして 1 つにする
+    fn compact(&mut self) -> Result<(), DbError> {
+        let (tmp, path) = self.run_path();
+        write_sorted_iter(tmp.to_str().unwrap(), self.stealing, Merge::new(&self.runs))?;
+        fs::rename(&tmp, &path)?;
+        let run = SortedDb::open(&path, self.stealing)?;
+        for (old, _) in std::mem::replace(&mut self.runs, vec![(path, run)]) {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+
+    /// 残りを書き出して run をマージし、`name` にソートした DB として保存する。局面数を返す
+    pub fn finish(mut self, name: &str) -> Result<u64, DbError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.runs.is_empty() {
+            let n = self.hot.len() as u64;
+            write_sorted(name, self.stealing, self.hot.drain().collect())?;
+            return Ok(n);
+        }
+        self.spill()?;
+        let n = write_sorted_iter(name, self.stealing, Merge::new(&self.runs))?;
+        for (path, _) in self.runs.drain(..) {
+            fs::remove_file(path)?;
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for SpillDb {
+    fn drop(&mut self) {
+        // finish しなかったときの後始末
+        if self.keep {
+            return;
+        }
+        for (path, _) in self.runs.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Memo for SpillDb {
+    fn get(&self, key: u64) -> Option<(i8, u8)> {
+        SpillDb::get(self, key)
+    }
+
+    fn insert(&mut self, key: u64, value: (i8, u8)) -> bool {
+        match self.put(key, value) {
+            Ok(()) => true,
+            Err(e) => {
+                self.error = Some(e);
+                false
+            }
+        }
+    }
+}
+
+/// run をキーの順にマージする。同じキーは新しい run の値を残す
+struct Merge<'a> {
+    runs: &'a [(PathBuf, SortedDb)],
+    heap: BinaryHeap<Reverse<(u64, Reverse<usize>, usize)>>,
+    last: Option<u64>,
+}
+
+impl Merge<'_> {
+    fn new(runs: &[(PathBuf, SortedDb)]) -> Merge<'_> {
+        let heap = runs
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, run))| !run.is_empty())
+            .map(|(r, (_, run))| Reverse((run.key_at(0), Reverse(r), 0)))
+            .collect();
+        Merge {
+            runs,
+            heap,
+            last: None,
+        }
+    }
+}
+
+impl Iterator for Merge<'_> {
+    type Item = (u64, (i8, u8));
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Reverse((key, Reverse(r), i)) = self.heap.pop()?;
+            let run = &self.runs[r].1;
+            if i + 1 < run.len() {
+                self.heap
+                    .push(Reverse((run.key_at(i + 1), Reverse(r), i + 1)));
+            }
+            if self.last != Some(key) {
+                self.last = Some(key);
+                return Some((key, run.value_at(i)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Board;
+    use crate::learn::{SortedDb, search, test_utils::temp_path};
+
+    #[test]
+    fn same_as_in_memory() {
+        let mut seeds = [0; crate::PIT * 2];
+        seeds[0] = 3;
+        seeds[2] = 1;
+        seeds[4] = 2;
+        seeds[crate::PIT + 1] = 3;
+        seeds[crate::PIT + 3] = 1;
+        let board = Board::from_seeds(true, &seeds);
+        let mut data = FnvHashMap::default();
+        let expected = search(&mut data, board.clone(), 100);
+        assert!(expected.is_some());
+
+        let dir = PathBuf::from(temp_path("spill"));
+        let mut spill = SpillDb::new(&dir, true, data.len() / 40).unwrap();
+        assert_eq!(search(&mut spill, board, 100), expected);
+        // 何度か書き出してまとめている
+        assert!(spill.runs() >= 1);
+        assert!(spill.hot_len() < data.len() / 40);
+        for (key, value) in data.iter() {
+            assert_eq!(spill.get(*key), Some(*value));
+        }
+
+        let name = dir.join("merged.dat");
+        let name = name.to_str().unwrap();
+        assert_eq!(spill.finish(name).unwrap(), data.len() as u64);
+        let db = SortedDb::open(name, true).unwrap();
+        db.verify().unwrap();
+        assert!(db.iter().all(|(key, value)| data[&key] == value));
+        drop(db);
+        fs::remove_file(name).unwrap();
+        // run は消えている
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn reopen_flushed_runs() {
+        let dir = PathBuf::from(temp_path("spill-open"));
+        let mut db = SpillDb::open(&dir, true, 100).unwrap();
+        db.put(1, (2, 3)).unwrap();
+        db.put(5, (-1, 0)).unwrap();
+        db.flush().unwrap();
+        db.put(1, (4, 3)).unwrap();
+        drop(db);
+
+        // flush した分だけが残っている
+        let mut db = SpillDb::open(&dir, true, 100).unwrap();
+        assert_eq!(db.runs(), 1);
+        assert_eq!(db.get(1), Some((2, 3)));
+        assert_eq!(db.get(5), Some((-1, 0)));
+        db.put(1, (4, 3)).unwrap();
+        db.flush().unwrap();
+        assert!(SpillDb::open(&dir, false, 100).is_err());
+
+        let name = dir.join("merged.dat");
+        let name = name.to_str().unwrap();
+        assert_eq!(db.finish(name).unwrap(), 2);
+        let merged = SortedDb::open(name, true).unwrap();
+        assert_eq!(merged.get(1), Some((4, 3)));
+        drop(merged);
+        fs::remove_file(name).unwrap();
+        fs::remove_dir(&dir).unwrap();
+    }
+}
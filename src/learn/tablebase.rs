@@ -0,0 +1,231 @@
+use std::io::{self, Read, Write};
+
+use super::index::{count, rank, unrank};
+use crate::board::{Board, PIT, SEED};
+
+/// 手番側、相手側の順に並べた穴の石の数
+pub type Pits = [u8; PIT * 2];
+
+/// 穴に残っている石が少ない局面を全部読み切った終盤データベース
+///
+/// 値はストアの石を除いた、手番側から見たこれから先の得点差。
+/// 終局までに得られる得点差はストアに入っている石の数によらないので、穴の石の並びだけで引ける
+#[derive(Debug, Clone)]
+pub struct Tablebase {
+    stealing: bool,
+    /// 穴の石の合計ごとに、`rank` の順に並べた値
+    levels: Vec<Vec<i8>>,
+}
+
+const MAGIC: &[u8; 4] = b"MCTB";
+const VERSION: u8 = 1;
+/// まだ計算していない印
+const UNKNOWN: i8 = i8::MIN;
+
+/// 手番側から見た穴の石の並び
+pub fn pits_of(board: &Board) -> Pits {
+    let mut pits = [0; PIT * 2];
+    pits[..PIT].copy_from_slice(board.self_seeds());
+    pits[PIT..].copy_from_slice(board.opposite_seed());
+    pits
+}
+
+fn level_of(pits: &Pits) -> usize {
+    pits.iter().map(|s| usize::from(*s)).sum()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+impl Tablebase {
+    /// 穴の石が `max_seeds` 個以下の局面を全部読み切る
+    ///
+    /// 石の少ない方から順に埋める。ターンで石の合計は増えないので、次の局面は同じ合計の表か計算済みの表にある。
+    /// 石の合計を変えないターンは打った側の石を右に動かすだけなので、同じ表の中で局面が循環することはない
+    pub fn generate(stealing: bool, max_seeds: usize) -> Tablebase {
+        let mut tb = Tablebase {
+            stealing,
+            levels: Vec::with_capacity(max_seeds + 1),
+        };
+        for total in 0..=max_seeds {
+            let mut level = vec![UNKNOWN; count(total) as usize];
+            for r in 0..level.len() {
+                if level[r] == UNKNOWN {
+                    tb.solve(&unrank(total, r as u64), &mut level);
+                }
+            }
+            tb.levels.push(level);
+        }
+        tb
+    }
+
+    /// 同じ石の合計の局面は `level` に、それより少ない局面は計算済みの表から引く
+    fn solve(&self, pits: &Pits, level: &mut [i8]) -> i8 {
+        let board = Board::from_seeds(self.stealing, pits);
+        let value = if board.is_finished() {
+            let s0 = pits[..PIT].iter().sum::<u8>();
+            let s1 = pits[PIT..].iter().sum::<u8>();
+            s0 as i8 - s1 as i8
+        } else {
+            let total = level_of(pits);
+            let mut best = i8::MIN;
+            for next in board.list_next() {
+                // from_seeds の盤面は先手番でストアが空なので、先手のストアがこのターンで得た石の数
+                let gain = next.scores().0 as i8;
+                let next_pits = pits_of(&next);
+                let next_total = level_of(&next_pits);
+                let value = if next_total == total {
+                    match level[rank(&next_pits) as usize] {
+                        UNKNOWN => self.solve(&next_pits, level),
+                        value => value,
+                    }
+                } else {
+                    self.levels[next_total][rank(&next_pits) as usize]
+                };
+                best = best.max(gain - value);
+            }
+            best
+        };
+        level[rank(pits) as usize] = value;
+        value
+    }
+
+    pub fn stealing(&self) -> bool {
+        self.stealing
+    }
+
+    /// 読み切っている穴の石の数の上限
+    pub fn max_seeds(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// 全局面数
+    pub fn len(&self) -> usize {
+        self.levels.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 手番側から見たこれから先の得点差。ストアに入っている分は含まない
+    pub fn probe_remaining(&self, board: &Board) -> Option<i8> {
+        if board.stealing() != self.stealing {
+            return None;
+        }
+        let pits = pits_of(board);
+        let level = self.levels.get(level_of(&pits))?;
+        Some(level[rank(&pits) as usize])
+    }
+
+    /// 最善を尽くした場合の、手番側から見た最終的な得点差
+    pub fn probe(&self, board: &Board) -> Option<i8> {
+        Some(board.score() + self.probe_remaining(board)?)
+    }
+
+    /// 石の合計ごとの表をそのまま書き出す。1 局面 1 バイト
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[
+            VERSION,
+            PIT as u8,
+            SEED,
+            u8::from(self.stealing),
+            self.max_seeds() as u8,
+        ])?;
+        for level in self.levels.iter() {
+            writer.write_all(&level.iter().map(|v| *v as u8).collect::<Vec<_>>())?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Tablebase> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a tablebase"));
+        }
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        let [version, pit, seed, stealing, max_seeds] = header;
+        if version != VERSION {
+            return Err(invalid_data("unsupported version"));
+        }
+        if usize::from(pit) != PIT || seed != SEED {
+            return Err(invalid_data("PIT or SEED mismatch"));
+        }
+        let mut levels = Vec::with_capacity(usize::from(max_seeds) + 1);
+        for total in 0..=usize::from(max_seeds) {
+            let mut buf = vec![0; count(total) as usize];
+            reader.read_exact(&mut buf)?;
+            levels.push(buf.into_iter().map(|v| v as i8).collect());
+        }
+        Ok(Tablebase {
+            stealing: stealing != 0,
+            levels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ScoreDiffEvaluator, TablebaseEvaluator, ab_search};
+
+    fn solve(board: &Board) -> i8 {
+        if board.is_finished() {
+            return board.last_score();
+        }
+        board.list_next().iter().map(|b| -solve(b)).max().unwrap()
+    }
+
+    #[test]
+    fn matches_minimax() {
+        for stealing in [true, false] {
+            let tb = Tablebase::generate(stealing, 4);
+            assert_eq!(tb.max_seeds(), 4);
+            for total in 0..=4 {
+                for r in (0..count(total)).step_by(7) {
+                    let mut board = Board::from_seeds(stealing, &unrank(total, r));
+                    assert_eq!(tb.probe(&board), Some(solve(&board)), "{board}");
+                    // ストアの石や手番が違っても引ける
+                    if !board.is_finished() {
+                        board = board.list_next().into_iter().next().unwrap();
+                        assert_eq!(tb.probe(&board), Some(solve(&board)), "{board}");
+                    }
+                }
+            }
+            assert_eq!(tb.probe(&Board::new(stealing)), None);
+            assert_eq!(tb.probe(&Board::from_seeds(!stealing, &[0; PIT * 2])), None);
+        }
+    }
+
+    #[test]
+    fn write_read() {
+        let tb = Tablebase::generate(false, 4);
+        let mut buf = Vec::new();
+        tb.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 9 + tb.len());
+        let read = Tablebase::read(&mut buf.as_slice()).unwrap();
+        assert!(!read.stealing());
+        assert_eq!(read.levels, tb.levels);
+        buf[0] = b'X';
+        assert!(Tablebase::read(&mut buf.as_slice()).is_err());
+    }
+
+    #[test]
+    fn ab_search_uses_tablebase() {
+        let tb = Arc::new(Tablebase::generate(true, 6));
+        let mut eval = TablebaseEvaluator::new(ScoreDiffEvaluator::new(), tb);
+        let mut seeds = [0; PIT * 2];
+        seeds[0] = 2;
+        seeds[PIT - 2] = 1;
+        seeds[PIT + 3] = 3;
+        let board = Board::from_seeds(true, &seeds);
+        let s = ab_search(board.clone(), &mut eval, 1, i32::MIN + 1, i32::MAX);
+        assert_eq!(s, i32::from(solve(&board)));
+    }
+}
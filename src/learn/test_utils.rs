@@ -0,0 +1,27 @@
+use fnv::FnvHashMap;
+
+use super::Solver;
+use crate::{Board, PIT};
+
+/// テストごとに名前を変えた一時ファイルのパス。同時に走るテストと重ならないようにプロセス ID を付ける
+pub(crate) fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("mancala-{}-{name}", std::process::id()));
+    path.to_str().unwrap().to_string()
+}
+
+/// すぐに読み切れる終盤の局面
+pub(crate) fn endgame(stealing: bool) -> Board {
+    let mut seeds = [0; PIT * 2];
+    seeds[0] = 2;
+    seeds[2] = 1;
+    seeds[PIT - 1] = 2;
+    seeds[PIT + 1] = 3;
+    Board::from_seeds(stealing, &seeds)
+}
+
+/// `endgame` から読み切った局面の DB
+pub(crate) fn solved(stealing: bool) -> FnvHashMap<u64, (i8, u8)> {
+    let mut solver = Solver::new(FnvHashMap::default());
+    solver.solve(&endgame(stealing)).unwrap();
+    solver.into_data()
+}
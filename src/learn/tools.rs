@@ -0,0 +1,250 @@
+use fnv::FnvHashMap;
+use rand::Rng;
+
+use super::db::{DbError, DbHeader, iter_load, load};
+use super::search::search;
+use crate::board::{Board, PIT, SEED, compact_key};
+
+/// 穴に残っている石の数ごとの局面数と、終局までのターン数の分布
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbStats {
+    pub entries: u64,
+    pub by_seeds: Vec<u64>,
+    pub depth_hist: Vec<u64>,
+}
+
+pub fn stats(path: &str) -> Result<(DbHeader, DbStats), DbError> {
+    let mut db = iter_load(path)?;
+    let header = db.header().clone();
+    let mut stats = DbStats {
+        entries: 0,
+        by_seeds: vec![0; PIT * 2 * SEED as usize + 1],
+        depth_hist: vec![0; 256],
+    };
+    for (pits, _, depth) in db.by_ref() {
+        let seeds = pits.iter().map(|s| usize::from(*s)).sum::<usize>();
+        stats.entries += 1;
+        let max = stats.by_seeds.len() - 1;
+        stats.by_seeds[seeds.min(max)] += 1;
+        stats.depth_hist[usize::from(depth)] += 1;
+    }
+    db.finish()?;
+    Ok((header, stats))
+}
+
+/// 同じ局面で値が食い違ったときにどちらを残すか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// 先に並べた DB の値
+    First,
+    /// 後に並べた DB の値
+    Last,
+}
+
+/// 局面のキーと値
+pub type Entry = (u64, (i8, u8));
+
+/// 局面のキーと、食い違った 2 つの値
+pub type Mismatch = (u64, (i8, u8), (i8, u8));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    pub data: FnvHashMap<u64, (i8, u8)>,
+    /// 値が食い違った局面のキーと、捨てた値
+    pub conflicts: Vec<Entry>,
+}
+
+pub fn merge(paths: &[&str], stealing: bool, conflict: Conflict) -> Result<Merged, DbError> {
+    let mut data = FnvHashMap::default();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        for (key, value) in load(path, stealing)? {
+            match data.get(&key) {
+                None => {
+                    data.insert(key, value);
+                }
+                Some(old) if *old == value => (),
+                Some(old) => match conflict {
+                    Conflict::First => conflicts.push((key, value)),
+                    Conflict::Last => {
+                        conflicts.push((key, *old));
+                        data.insert(key, value);
+                    }
+                },
+            }
+        }
+    }
+    Ok(Merged { data, conflicts })
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffReport {
+    pub same: u64,
+    pub only_a: u64,
+    pub only_b: u64,
+    /// 両方にあるが値が違う局面のキーと値
+    pub differ: Vec<Mismatch>,
+}
+
+pub fn diff(a: &str, b: &str, stealing: bool) -> Result<DiffReport, DbError> {
+    let mut a = load(a, stealing)?;
+    let mut report = DiffReport::default();
+    for (key, value) in load(b, stealing)? {
+        match a.remove(&key) {
+            None => report.only_b += 1,
+            Some(old) if old == value => report.same += 1,
+            Some(old) => report.differ.push((key, old, value)),
+        }
+    }
+    report.only_a = a.len() as u64;
+    report.differ.sort_unstable();
+    Ok(report)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub checked: u64,
+    /// 読み直した値と合わなかった局面のキーと、DB の値と読み直した値
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// 最善手順で終局まで `max_depth` ターン以内の局面から `samples` 個を選んで `learn::search` で読み直す。
+/// チェックサムも確かめる
+pub fn verify_sample<R: Rng>(
+    path: &str,
+    stealing: bool,
+    samples: usize,
+    max_depth: u8,
+    random: &mut R,
+) -> Result<VerifyReport, DbError> {
+    let mut db = iter_load(path)?;
+    db.header().check_stealing(stealing)?;
+    // 読みながら一様に選ぶ
+    let mut picked = Vec::with_capacity(samples);
+    let mut seen = 0;
+    for (pits, score, depth) in db.by_ref() {
+        if depth > max_depth {
+            continue;
+        }
+        seen += 1;
+        if picked.len() < samples {
+            picked.push((pits, (score, depth)));
+        } else {
+            let i = random.random_range(0..seen);
+            if i < samples {
+                picked[i] = (pits, (score, depth));
+            }
+        }
+    }
+    db.finish()?;
+
+    let mut memo = FnvHashMap::default();
+    let mut report = VerifyReport::default();
+    for (pits, value) in picked {
+        // ストアが空の局面なので、search の値がそのまま DB の値になる。
+        // DB の深さは最短の最善手順なので、ほかの枝はもっと長いことがある。深さは制限しない
+        let board = Board::from_seeds(stealing, &pits);
+        let key = compact_key(&board);
+        let found = search(&mut memo, board, u8::MAX).unwrap_or((i8::MIN, u8::MAX));
+        report.checked += 1;
+        if found != value {
+            report.mismatches.push((key, value, found));
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_pcg::Mcg128Xsl64;
+
+    use super::*;
+    use crate::board::from_compact_key;
+    use crate::learn::test_utils::{solved, temp_path};
+    use crate::learn::{save, save_compressed};
+
+    #[test]
+    fn stats_merge_diff() {
+        let data = solved(true);
+        let a = temp_path("tools-a.dat");
+        let b = temp_path("tools-b.dat");
+        save(&a, true, &data).unwrap();
+        let (header, s) = stats(&a).unwrap();
+        assert!(header.stealing);
+        assert_eq!(s.entries, data.len() as u64);
+        assert_eq!(s.by_seeds.iter().sum::<u64>(), s.entries);
+        assert_eq!(s.depth_hist.iter().sum::<u64>(), s.entries);
+        assert!(s.by_seeds[8] > 0);
+        assert_eq!(s.by_seeds[9..].iter().sum::<u64>(), 0);
+
+        // 半分だけ持ち、1 つ値を変えた DB
+        let mut keys = data.keys().copied().collect::<Vec<_>>();
+        keys.sort_unstable();
+        let mut half = keys[..keys.len() / 2]
+            .iter()
+            .map(|k| (*k, data[k]))
+            .collect::<FnvHashMap<_, _>>();
+        let changed = keys[0];
+        half.insert(changed, (data[&changed].0 + 1, data[&changed].1));
+        save_compressed(&b, true, &half).unwrap();
+
+        let report = diff(&a, &b, true).unwrap();
+        assert_eq!(report.only_a, (keys.len() - keys.len() / 2) as u64);
+        assert_eq!(report.only_b, 0);
+        assert_eq!(report.same, (keys.len() / 2 - 1) as u64);
+        assert_eq!(
+            report.differ,
+            vec![(changed, data[&changed], half[&changed])]
+        );
+
+        let merged = merge(&[&a, &b], true, Conflict::First).unwrap();
+        assert_eq!(merged.data, data);
+        assert_eq!(merged.conflicts, vec![(changed, half[&changed])]);
+        let merged = merge(&[&a, &b], true, Conflict::Last).unwrap();
+        assert_eq!(merged.data[&changed], half[&changed]);
+
+        std::fs::remove_file(&a).unwrap();
+        std::fs::remove_file(&b).unwrap();
+    }
+
+    #[test]
+    fn verify_finds_corruption() {
+        let mut data = solved(false);
+        let path = temp_path("tools-verify.dat");
+        save(&path, false, &data).unwrap();
+        let mut random = Mcg128Xsl64::new(1);
+        let report = verify_sample(&path, false, data.len(), 30, &mut random).unwrap();
+        assert_eq!(report.checked, data.len() as u64);
+        assert!(report.mismatches.is_empty());
+
+        // 値を書き換えて保存し直すとチェックサムは合うが読み直すと合わない
+        let (key, value) = data.iter().find(|(_, (_, d))| *d > 0).unwrap();
+        let (key, value) = (*key, *value);
+        data.insert(key, (value.0 - 1, value.1));
+        save(&path, false, &data).unwrap();
+        let report = verify_sample(&path, false, data.len(), 30, &mut random).unwrap();
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].0, key);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn verify_longer_branches() {
+        let data = solved(true);
+        let path = temp_path("tools-verify-longer.dat");
+        save(&path, true, &data).unwrap();
+        // 最善手順は max_depth 以内だが、ほかの枝が max_depth より長い局面
+        let longer = data.iter().find_map(|(&key, &(_, depth))| {
+            let board = Board::from_seeds(true, &from_compact_key(key));
+            search(&mut FnvHashMap::default(), board, depth)
+                .is_none()
+                .then_some(depth)
+        });
+        let max_depth = longer.unwrap();
+        let mut random = Mcg128Xsl64::new(1);
+        let report = verify_sample(&path, true, data.len(), max_depth, &mut random).unwrap();
+        assert!(report.checked > 0);
+        assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,576 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::dataset::{Dataset, Splitter};
+use super::npy::DbFilter;
+
+/// 学習するネットワークの形
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    NN4,
+    NN6,
+}
+
+impl Arch {
+    /// 入力を含めた層の幅の数
+    pub fn widths(self) -> usize {
+        match self {
+            Arch::NN4 => 5,
+            Arch::NN6 => 7,
+        }
+    }
+}
+
+/// エポックごとの学習率の決め方
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    Constant,
+    /// `every` エポックごとに `gamma` 倍する
+    Step {
+        every: u64,
+        gamma: f64,
+    },
+    /// 毎エポック `gamma` 倍する
+    Exp {
+        gamma: f64,
+    },
+    /// `max_epochs` で `min` になるように cos で下げる
+    Cosine {
+        min: f64,
+    },
+}
+
+/// 重みの更新の仕方。`rust_nn` で学習するのは SGD だけで、ほかはその更新をもとに重みを書き直す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Optimizer {
+    Sgd,
+    /// SGD で動いた分に `beta` の慣性をかける
+    Momentum {
+        beta: f64,
+    },
+}
+
+/// 重み空間でかける慣性
+///
+/// SGD の 1 歩は `-lr * grad` なので、`v = beta * v + (SGD で動いた分)` だけ動かせば
+/// 慣性付きの SGD と同じになる
+#[derive(Debug, Clone, PartialEq)]
+pub struct Momentum {
+    beta: f64,
+    /// 前の歩のあとの重み
+    params: Vec<f64>,
+    velocity: Vec<f64>,
+}
+
+impl Momentum {
+    pub fn new(beta: f64, params: Vec<f64>) -> Momentum {
+        let velocity = vec![0.0; params.len()];
+        Momentum {
+            beta,
+            params,
+            velocity,
+        }
+    }
+
+    /// SGD で 1 歩動いたあとの重み `params` を、慣性をかけた重みに書き換える
+    pub fn step(&mut self, params: &mut [f64]) {
+        assert_eq!(params.len(), self.params.len());
+        for ((p, last), v) in params
+            .iter_mut()
+            .zip(self.params.iter_mut())
+            .zip(self.velocity.iter_mut())
+        {
+            *v = self.beta * *v + (*p - *last);
+            *p = *last + *v;
+            *last = *p;
+        }
+    }
+}
+
+/// `bin/learn` の設定
+///
+/// 1 行に 1 つ `key = value` と書く。`#` から後は無視する
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainConfig {
+    pub db: String,
+    pub out: String,
+    /// 続きから学習するモデル
+    pub init: Option<String>,
+    pub arch: Arch,
+    pub widths: Vec<usize>,
+    pub optimizer: Optimizer,
+    pub lr: f64,
+    pub schedule: Schedule,
+    pub batch: usize,
+    pub buffer: usize,
+    pub seed: u64,
+    pub split: Splitter,
+    pub seeds: RangeInclusive<usize>,
+    pub depth: RangeInclusive<u8>,
+    pub max_epochs: u64,
+    /// 0 なら制限しない
+    pub max_steps: u64,
+    /// 検証の誤差がこのエポック数だけ良くならなければやめる。0 なら続ける
+    pub patience: u64,
+    /// 検証に使う局面数の目安。検証用の局面からキーのハッシュで間引いてこのくらいにする
+    pub validation_rows: usize,
+    /// この数のバッチごとに学習の誤差を表示する
+    pub report_every: u64,
+}
+
+impl Default for TrainConfig {
+    fn default() -> TrainConfig {
+        TrainConfig {
+            db: String::new(),
+            out: String::new(),
+            init: None,
+            arch: Arch::NN4,
+            widths: vec![12, 64, 64, 64, 64],
+            optimizer: Optimizer::Sgd,
+            lr: 2f64.powi(-20),
+            schedule: Schedule::Constant,
+            batch: 128,
+            buffer: 128 * 1024,
+            seed: 0,
+            split: Splitter::default(),
+            seeds: 0..=usize::MAX,
+            depth: 0..=u8::MAX,
+            max_epochs: 100,
+            max_steps: 0,
+            patience: 5,
+            validation_rows: 100_000,
+            report_every: 1_000,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e| format!("{key}: {e}"))
+}
+
+fn parse_range<T: std::str::FromStr + Copy>(
+    key: &str,
+    value: &str,
+) -> Result<RangeInclusive<T>, String>
+where
+    T::Err: fmt::Display,
+{
+    let (min, max) = value.split_once('-').unwrap_or((value, value));
+    Ok(parse_value(key, min.trim())?..=parse_value(key, max.trim())?)
+}
+
+impl TrainConfig {
+    pub fn parse(s: &str) -> Result<TrainConfig, String> {
+        let mut config = TrainConfig::default();
+        let mut widths = None;
+        for (n, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected key = value", n + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "db" => config.db = value.to_string(),
+                "out" => config.out = value.to_string(),
+                "init" => config.init = Some(value.to_string()),
+                "arch" => {
+                    config.arch = match value {
+                        "nn4" => Arch::NN4,
+                        "nn6" => Arch::NN6,
+                        _ => return Err(format!("arch must be nn4 or nn6: {value}")),
+                    }
+                }
+                "widths" => {
+                    widths = Some(
+                        value
+                            .split(',')
+                            .map(|w| parse_value(key, w.trim()))
+                            .collect::<Result<Vec<_>, _>>()?,
+                    )
+                }
+                "optimizer" => config.optimizer = parse_optimizer(value)?,
+                "lr" => config.lr = parse_value(key, value)?,
+                "schedule" => config.schedule = parse_schedule(value)?,
+                "batch" => config.batch = parse_value(key, value)?,
+                "buffer" => config.buffer = parse_value(key, value)?,
+                "seed" => config.seed = parse_value(key, value)?,
+                "validation" => config.split.validation = parse_value(key, value)?,
+                "test" => config.split.test = parse_value(key, value)?,
+                "split_salt" => config.split.salt = parse_value(key, value)?,
+                "seeds" => config.seeds = parse_range(key, value)?,
+                "depth" => config.depth = parse_range(key, value)?,
+                "max_epochs" => config.max_epochs = parse_value(key, value)?,
+                "max_steps" => config.max_steps = parse_value(key, value)?,
+                "patience" => config.patience = parse_value(key, value)?,
+                "validation_rows" => config.validation_rows = parse_value(key, value)?,
+                "report_every" => config.report_every = parse_value(key, value)?,
+                _ => return Err(format!("line {}: unknown key {key}", n + 1)),
+            }
+        }
+        config.widths = match widths {
+            Some(w) => w,
+            None if config.arch == Arch::NN6 => vec![12, 64, 64, 64, 64, 64, 64],
+            None => config.widths,
+        };
+        config.check()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<TrainConfig, String> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        TrainConfig::parse(&s)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.db.is_empty() || self.out.is_empty() {
+            return Err("db and out are required".to_string());
+        }
+        if self.db == self.out || self.init.as_ref() == Some(&self.out) {
+            return Err("out must differ from db and init".to_string());
+        }
+        if self.widths.len() != self.arch.widths() {
+            return Err(format!(
+                "{:?} needs {} widths including the input",
+                self.arch,
+                self.arch.widths()
+            ));
+        }
+        if self.widths[0] != 12 {
+            return Err("the first width must be 12".to_string());
+        }
+        if let Optimizer::Momentum { beta } = self.optimizer
+            && !(0.0..1.0).contains(&beta)
+        {
+            return Err(format!("momentum must be in [0, 1): {beta}"));
+        }
+        if self.batch == 0 || self.report_every == 0 {
+            return Err("batch and report_every must be positive".to_string());
+        }
+        if !(0.0..1.0).contains(&(self.split.validation + self.split.test))
+            || self.split.validation <= 0.0
+        {
+            return Err("validation must be positive and validation + test < 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// `epoch` エポック目 (0 から) の学習率
+    pub fn lr_at(&self, epoch: u64) -> f64 {
+        match self.schedule {
+            Schedule::Constant => self.lr,
+            Schedule::Step { every, gamma } => self.lr * gamma.powi((epoch / every.max(1)) as i32),
+            Schedule::Exp { gamma } => self.lr * gamma.powi(epoch as i32),
+            Schedule::Cosine { min } => {
+                let t = epoch as f64 / self.max_epochs.max(1) as f64;
+                min + (self.lr - min) * (1.0 + (std::f64::consts::PI * t.min(1.0)).cos()) / 2.0
+            }
+        }
+    }
+
+    pub fn dataset(&self, stealing: bool) -> Dataset {
+        let mut dataset = Dataset::new(&self.db, stealing);
+        dataset.filter = DbFilter {
+            seeds: self.seeds.clone(),
+            depth: self.depth.clone(),
+        };
+        dataset.split = self.split;
+        dataset.buffer = self.buffer;
+        dataset.seed = self.seed;
+        dataset
+    }
+}
+
+/// `sgd`, `momentum:(beta)`
+fn parse_optimizer(value: &str) -> Result<Optimizer, String> {
+    let args = value.split(':').map(str::trim).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["sgd"] => Ok(Optimizer::Sgd),
+        ["momentum", beta] => Ok(Optimizer::Momentum {
+            beta: parse_value("optimizer", beta)?,
+        }),
+        _ => Err(format!("optimizer must be sgd or momentum:(beta): {value}")),
+    }
+}
+
+impl fmt::Display for Optimizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Optimizer::Sgd => write!(f, "sgd"),
+            Optimizer::Momentum { beta } => write!(f, "momentum:{beta}"),
+        }
+    }
+}
+
+/// `constant`, `step:(every):(gamma)`, `exp:(gamma)`, `cosine:(min_lr)`
+fn parse_schedule(value: &str) -> Result<Schedule, String> {
+    let args = value.split(':').map(str::trim).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["constant"] => Ok(Schedule::Constant),
+        ["step", every, gamma] => Ok(Schedule::Step {
+            every: parse_value("schedule", every)?,
+            gamma: parse_value("schedule", gamma)?,
+        }),
+        ["exp", gamma] => Ok(Schedule::Exp {
+            gamma: parse_value("schedule", gamma)?,
+        }),
+        ["cosine", min] => Ok(Schedule::Cosine {
+            min: parse_value("schedule", min)?,
+        }),
+        _ => Err(format!(
+            "schedule must be constant, step:(every):(gamma), exp:(gamma) or cosine:(min_lr): {value}"
+        )),
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Schedule::Constant => write!(f, "constant"),
+            Schedule::Step { every, gamma } => write!(f, "step:{every}:{gamma}"),
+            Schedule::Exp { gamma } => write!(f, "exp:{gamma}"),
+            Schedule::Cosine { min } => write!(f, "cosine:{min}"),
+        }
+    }
+}
+
+/// `TrainConfig::parse` で読める形で書く
+impl fmt::Display for TrainConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let widths = self.widths.iter().map(usize::to_string).collect::<Vec<_>>();
+        writeln!(f, "db = {}", self.db)?;
+        writeln!(f, "out = {}", self.out)?;
+        if let Some(init) = self.init.as_ref() {
+            writeln!(f, "init = {init}")?;
+        }
+        let arch = match self.arch {
+            Arch::NN4 => "nn4",
+            Arch::NN6 => "nn6",
+        };
+        writeln!(f, "arch = {arch}")?;
+        writeln!(f, "widths = {}", widths.join(","))?;
+        writeln!(f, "optimizer = {}", self.optimizer)?;
+        writeln!(f, "lr = {}", self.lr)?;
+        writeln!(f, "schedule = {}", self.schedule)?;
+        writeln!(f, "batch = {}", self.batch)?;
+        writeln!(f, "buffer = {}", self.buffer)?;
+        writeln!(f, "seed = {}", self.seed)?;
+        writeln!(f, "validation = {}", self.split.validation)?;
+        writeln!(f, "test = {}", self.split.test)?;
+        writeln!(f, "split_salt = {}", self.split.salt)?;
+        writeln!(f, "seeds = {}-{}", self.seeds.start(), self.seeds.end())?;
+        writeln!(f, "depth = {}-{}", self.depth.start(), self.depth.end())?;
+        writeln!(f, "max_epochs = {}", self.max_epochs)?;
+        writeln!(f, "max_steps = {}", self.max_steps)?;
+        writeln!(f, "patience = {}", self.patience)?;
+        writeln!(f, "validation_rows = {}", self.validation_rows)?;
+        write!(f, "report_every = {}", self.report_every)
+    }
+}
+
+/// 検証の誤差の二乗平均と絶対値の平均
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Validation {
+    pub rows: u64,
+    pub mse: f64,
+    pub mae: f64,
+}
+
+impl Validation {
+    pub fn new<I: IntoIterator<Item = (f64, f64)>>(pairs: I) -> Validation {
+        let mut v = Validation::default();
+        for (predicted, exact) in pairs {
+            let e = predicted - exact;
+            v.rows += 1;
+            v.mse += e * e;
+            v.mae += e.abs();
+        }
+        if v.rows > 0 {
+            v.mse /= v.rows as f64;
+            v.mae /= v.rows as f64;
+        }
+        v
+    }
+}
+
+/// 検証の誤差が一番小さかったエポックを覚えておき、早めにやめるかを決める
+#[derive(Debug, Clone, PartialEq)]
+pub struct Best {
+    patience: u64,
+    pub epoch: Option<u64>,
+    pub validation: Validation,
+}
+
+impl Best {
+    pub fn new(patience: u64) -> Best {
+        Best {
+            patience,
+            epoch: None,
+            validation: Validation::default(),
+        }
+    }
+
+    /// 良くなったら `true`
+    pub fn update(&mut self, epoch: u64, validation: Validation) -> bool {
+        if self.epoch.is_none() || validation.mse < self.validation.mse {
+            self.epoch = Some(epoch);
+            self.validation = validation;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn should_stop(&self, epoch: u64) -> bool {
+        self.patience > 0 && self.epoch.is_some_and(|best| epoch >= best + self.patience)
+    }
+}
+
+/// モデルの隣に保存する、学習の経過
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunMeta {
+    pub config: TrainConfig,
+    pub db_entries: u64,
+    pub db_checksum: u64,
+    pub epoch: u64,
+    pub step: u64,
+    pub lr: f64,
+    pub train_loss: f64,
+    pub last: Validation,
+    pub best_epoch: Option<u64>,
+    pub best: Validation,
+    pub elapsed_secs: f64,
+    /// 終わったときは理由
+    pub stopped: Option<String>,
+}
+
+impl RunMeta {
+    /// `model` の隣のファイル名
+    pub fn path(model: &str) -> String {
+        format!("{model}.meta")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        std::fs::write(RunMeta::path(&self.config.out), self.to_string())
+    }
+}
+
+impl fmt::Display for RunMeta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.config)?;
+        writeln!(f, "# run")?;
+        writeln!(f, "# db_entries = {}", self.db_entries)?;
+        writeln!(f, "# db_checksum = {:016x}", self.db_checksum)?;
+        writeln!(f, "# epoch = {}", self.epoch)?;
+        writeln!(f, "# step = {}", self.step)?;
+        writeln!(f, "# lr = {}", self.lr)?;
+        writeln!(f, "# train_loss = {}", self.train_loss)?;
+        writeln!(f, "# validation_rows = {}", self.last.rows)?;
+        writeln!(f, "# validation_mse = {}", self.last.mse)?;
+        writeln!(f, "# validation_mae = {}", self.last.mae)?;
+        if let Some(epoch) = self.best_epoch {
+            writeln!(f, "# best_epoch = {epoch}")?;
+            writeln!(f, "# best_mse = {}", self.best.mse)?;
+            writeln!(f, "# best_mae = {}", self.best.mae)?;
+        }
+        writeln!(f, "# elapsed_secs = {:.1}", self.elapsed_secs)?;
+        if let Some(reason) = self.stopped.as_ref() {
+            writeln!(f, "# stopped = {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = TrainConfig::parse(
+            "
+            # NN6 を学習する
+            db = p6s4_true.dat
+            out = nn6_true.model
+            arch = nn6
+            optimizer = momentum:0.9
+            lr = 0.001  # 最初の学習率
+            schedule = step:10:0.5
+            seeds = 8-30
+            validation = 0.1
+            ",
+        )
+        .unwrap();
+        assert_eq!(config.arch, Arch::NN6);
+        assert_eq!(config.widths.len(), 7);
+        assert_eq!(config.seeds, 8..=30);
+        assert_eq!(config.depth, 0..=u8::MAX);
+        assert_eq!(config.optimizer, Optimizer::Momentum { beta: 0.9 });
+        assert_eq!(config.lr_at(0), 0.001);
+        assert_eq!(config.lr_at(9), 0.001);
+        assert_eq!(config.lr_at(25), 0.001 * 0.25);
+        // 書いたものを読み直すと同じ設定になる
+        assert_eq!(TrainConfig::parse(&config.to_string()).unwrap(), config);
+
+        assert!(TrainConfig::parse("db = a\nout = b\nwidths = 12,8").is_err());
+        assert!(TrainConfig::parse("db = a\nout = b\noptimizer = adam").is_err());
+        assert!(TrainConfig::parse("db = a\nout = b\noptimizer = momentum:1").is_err());
+        assert!(TrainConfig::parse("db = a\nout = a").is_err());
+        assert!(TrainConfig::parse("db = a\nout = b\nfoo = 1").is_err());
+        assert!(TrainConfig::parse("db = a\nout = b\nschedule = step:1").is_err());
+    }
+
+    #[test]
+    fn momentum_step() {
+        let mut m = Momentum::new(0.5, vec![0.0, 1.0]);
+        // 1 歩目は SGD と同じ
+        let mut p = vec![1.0, 0.0];
+        m.step(&mut p);
+        assert_eq!(p, vec![1.0, 0.0]);
+        // 2 歩目は前の歩の半分が足される
+        let mut p = vec![2.0, 0.0];
+        m.step(&mut p);
+        assert_eq!(p, vec![2.5, -0.5]);
+        // SGD で動かなくても慣性で進む
+        let mut p = vec![2.5, -0.5];
+        m.step(&mut p);
+        assert_eq!(p, vec![3.25, -0.75]);
+    }
+
+    #[test]
+    fn cosine_schedule() {
+        let config = TrainConfig {
+            lr: 1.0,
+            schedule: Schedule::Cosine { min: 0.1 },
+            max_epochs: 10,
+            ..TrainConfig::default()
+        };
+        assert_eq!(config.lr_at(0), 1.0);
+        assert!((config.lr_at(5) - 0.55).abs() < 1e-12);
+        assert!((config.lr_at(10) - 0.1).abs() < 1e-12);
+        assert!((config.lr_at(20) - 0.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn early_stopping() {
+        let v = |mse| Validation {
+            rows: 1,
+            mse,
+            mae: mse,
+        };
+        assert_eq!(Validation::new([(1.0, 0.0), (-1.0, 2.0)]).mse, 5.0);
+        let mut best = Best::new(2);
+        assert!(best.update(0, v(3.0)));
+        assert!(best.update(1, v(2.0)));
+        assert!(!best.update(2, v(2.5)));
+        assert!(!best.should_stop(2));
+        assert!(!best.update(3, v(2.0)));
+        assert!(best.should_stop(3));
+        assert_eq!(best.epoch, Some(1));
+    }
+}
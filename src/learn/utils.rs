@@ -1,138 +1,74 @@
-use std::io::{Read, Write};
-use std::path::Path;
-
-use fnv::FnvHashMap;
 use rand::Rng;
 
+use super::db::{DbError, Load, iter_load};
 use crate::board::{PIT, SEED};
-use crate::from_compact_key;
 
 pub fn db_name(stealing: bool) -> String {
     format!("p{PIT}s{SEED}_{stealing}.dat")
 }
 
-pub fn load(name: &str) -> FnvHashMap<u64, (i8, u8)> {
-    let mut f = match std::fs::File::open(name) {
-        Err(e) => {
-            eprintln!("{name} is not exists ({e})");
-            return FnvHashMap::with_capacity_and_hasher(7 * 1024, Default::default());
-        }
-        Ok(f) => std::io::BufReader::new(f),
-    };
-    let n = {
-        let mut buf = [0; 8];
-        match f.read_exact(&mut buf) {
-            Err(e) => {
-                eprintln!("read size failed: {e}");
-                return FnvHashMap::with_capacity_and_hasher(7 * 1024, Default::default());
-            }
-            Ok(()) => u64::from_le_bytes(buf) as usize,
-        }
-    };
-
-    let cap = 7 * (n / 7).next_power_of_two();
-    let mut data = FnvHashMap::with_capacity_and_hasher(cap, Default::default());
-    for i in 0..n {
-        let mut buf = [0; 8];
-        let key = match f.read_exact(&mut buf) {
-            Err(e) => {
-                eprintln!("read {i}th key failed: {e}");
-                return data;
-            }
-            Ok(()) => u64::from_le_bytes(buf),
-        };
-        let mut buf = [0; 2];
-        let value = match f.read_exact(&mut buf) {
-            Err(e) => {
-                eprintln!("read {i}th value failed: {e}");
-                return data;
-            }
-            Ok(()) => (buf[0] as i8, buf[1]),
-        };
-        data.insert(key, value);
-    }
-    data
+/// DB を `epochs` 回繰り返して読む
+pub struct RepeatLod {
+    path: String,
+    loader: Option<Load>,
+    epochs: u64,
+    epoch: u64,
+    error: Option<DbError>,
 }
 
-pub fn save(name: &str, data: &FnvHashMap<u64, (i8, u8)>) -> std::io::Result<()> {
-    let mut f = std::io::BufWriter::new(std::fs::File::create(name)?);
-    f.write_all(&(data.len() as u64).to_le_bytes())?;
-    for (key, value) in data.iter() {
-        f.write_all(&key.to_le_bytes())?;
-        f.write_all(&[value.0 as u8, value.1])?;
+impl RepeatLod {
+    pub fn new(path: &str, epochs: u64) -> Result<RepeatLod, DbError> {
+        let loader = iter_load(path)?;
+        Ok(RepeatLod {
+            path: path.to_string(),
+            loader: (epochs > 0).then_some(loader),
+            epochs,
+            epoch: 0,
+            error: None,
+        })
     }
-    Ok(())
-}
 
-pub fn iter_load<P: AsRef<Path>>(path: P) -> std::io::Result<Load> {
-    let mut f = std::io::BufReader::new(std::fs::File::open(path.as_ref())?);
-    let n = {
-        let mut buf = [0; 8];
-        f.read_exact(&mut buf)?;
-        u64::from_le_bytes(buf) as usize
-    };
-    Ok(Load { n, f })
-}
+    /// 読み終えた回数
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
 
-pub struct Load {
-    n: usize,
-    f: std::io::BufReader<std::fs::File>,
+    /// DB が壊れていて途中で終わったときのエラー
+    pub fn error(&self) -> Option<&DbError> {
+        self.error.as_ref()
+    }
 }
 
-impl Iterator for Load {
+impl Iterator for RepeatLod {
     type Item = ([u8; 12], i8, u8);
-
     fn next(&mut self) -> Option<Self::Item> {
-        let mut buf = [0; 8];
-        let key = match self.f.read_exact(&mut buf) {
-            Err(_) => {
+        loop {
+            let loader = self.loader.as_mut()?;
+            if let Some(item) = loader.next() {
+                return Some(item);
+            }
+            let loader = self.loader.take().unwrap();
+            if let Err(e) = loader.finish() {
+                self.error = Some(e);
                 return None;
             }
-            Ok(()) => u64::from_le_bytes(buf),
-        };
-        let mut buf = [0; 2];
-        let value = match self.f.read_exact(&mut buf) {
-            Err(_) => {
+            self.epoch += 1;
+            if self.epoch == self.epochs {
                 return None;
             }
-            Ok(()) => (buf[0] as i8, buf[1]),
-        };
-        Some((from_compact_key(key), value.0, value.1))
-    }
-
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.n, Some(self.n))
-    }
-}
-
-pub struct RepeatLod {
-    path: String,
-    loader: Load,
-}
-
-impl RepeatLod {
-    pub fn new(path: &str) -> RepeatLod {
-        let loader = iter_load(path).unwrap();
-        RepeatLod {
-            path: path.to_string(),
-            loader,
-        }
-    }
-}
-
-impl Iterator for RepeatLod {
-    type Item = ([u8; 12], i8, u8);
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.loader.next() {
-            Some(item) => Some(item),
-            None => {
-                self.loader = iter_load(&self.path).unwrap();
-                self.loader.next()
+            match iter_load(&self.path) {
+                Ok(loader) if loader.header().entries > 0 => self.loader = Some(loader),
+                Ok(_) => return None,
+                Err(e) => {
+                    self.error = Some(e);
+                    return None;
+                }
             }
         }
     }
 }
 
+/// `buffer` 個ずつ先読みして、その中から選んで返す。元が尽きたら残りを出し切って終わる
 pub struct ShuffledStream<I, R>
 where
     I: Iterator,
@@ -149,12 +85,13 @@ where
 {
     pub fn new(iter: I, random: R, buffer: usize) -> ShuffledStream<I, R> {
         let mut iter = iter;
-        let mut buf = Vec::with_capacity(buffer);
-        for _ in 0..buffer {
-            buf.push(iter.next().unwrap())
-        }
+        let buf = iter.by_ref().take(buffer.max(1)).collect();
         ShuffledStream { iter, random, buf }
     }
+
+    pub fn get_ref(&self) -> &I {
+        &self.iter
+    }
 }
 
 impl<I, R> Iterator for ShuffledStream<I, R>
@@ -164,9 +101,14 @@ where
 {
     type Item = I::Item;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
         let idx = self.random.random_range(0..self.buf.len());
         let item = self.buf.swap_remove(idx);
-        self.buf.push(self.iter.next().unwrap());
+        if let Some(next) = self.iter.next() {
+            self.buf.push(next);
+        }
         Some(item)
     }
 }
@@ -5,7 +5,7 @@ pub mod learn;
 
 pub use ai::*;
 pub use board::{Board, PIT, SEED, Side, compact_key, from_compact_key};
-pub use game::Game;
+pub use game::{Game, MatchResult, play_match};
 
 #[macro_use]
 extern crate lazy_static;